@@ -1,34 +1,235 @@
-use crate::{money::MoneyAmount, transactions::TransactionId};
+use crate::{
+    lifecycle::{Action, State},
+    limits::Limit,
+    money::MoneyAmount,
+    transactions::{Timestamp, TransactionId},
+};
 use std::collections::HashMap;
 
-#[derive(Debug, PartialEq)]
+/// What to do with a dispute that stayed open for too long
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExpiryPolicy {
+    Resolve,
+    ChargeBack,
+}
+
+#[derive(Debug, Clone, PartialEq)]
 pub enum AuditRecord {
     Processed,
     CanNotDepositNegative,
     CanNotWithdrawNegative,
     NotEnoughMoneyToWithdraw,
     DisputedDepositNotFound,
+    /// Disputed deposit was made, but it is no longer kept in deposit history
+    DisputedDepositEvicted,
     NotEnoughMoneyToRelease,
     NotEnoughMoneyToChargeBack,
+    NotEnoughMoneyToRefund,
     MoneyOverflow,
     MoneyUnderflow,
     DisputeNotFound,
     AccountLocked,
+
+    /// Transaction with the same id and content was already processed
+    /// (whatever its outcome was), it is skipped
+    AlreadyApplied,
+
+    /// Transaction with the same id but different content was already processed, it is skipped
+    ConflictsWithApplied,
+
+    /// Transaction was rejected by velocity or amount limit
+    LimitExceeded {
+        limit: Limit,
+    },
+
+    /// Deposit or withdrawal to refund or reverse was not found
+    ReferencedTransactionNotFound,
+
+    /// Deposit or withdrawal to refund or reverse was made,
+    /// but it is no longer kept in history
+    ReferencedTransactionEvicted,
+
+    /// Refund is larger than the part of deposit that is not refunded or disputed
+    RefundExceedsDeposit {
+        remaining: MoneyAmount,
+    },
+
+    /// Transaction is not allowed in the dispute lifecycle state of referenced deposit
+    IllegalTransition {
+        state: State,
+        action: Action,
+    },
+
+    /// Deposit was already disputed again after resolved disputes as many times as allowed
+    RedisputeLimitReached {
+        max: u32,
+    },
+
+    /// Partial dispute is larger than the part of deposit that is not disputed yet
+    DisputeExceedsDeposit {
+        remaining: MoneyAmount,
+    },
+
+    /// Partial resolve or chargeback is larger than disputed amount
+    ExceedsDisputedAmount {
+        disputed: MoneyAmount,
+    },
+
+    /// Partial dispute, resolve or chargeback is not positive
+    InvalidPartialAmount,
+
+    /// Transaction was rejected by middleware or screening rule
+    Rejected {
+        reason: String,
+    },
+
+    /// Transaction was processed, but screening rule found it suspicious
+    /// (follows record of the transaction)
+    Flagged {
+        reason: String,
+    },
+
+    /// Fee was taken from client and credited to house account
+    /// (follows record of the transaction)
+    FeeCharged {
+        fee: MoneyAmount,
+    },
+
+    /// Deposit fee was taken back from house account, returned to client after chargeback
+    /// or to sender with reversed deposit (follows record of the transaction)
+    FeeRefunded {
+        fee: MoneyAmount,
+    },
+
+    /// Account or deposit history storage failed, transaction may have been partially applied
+    StorageError,
+
+    /// Dispute was open for too long and processor applied expiry policy to it
+    DisputeExpired {
+        client_id: ClientId,
+        tx_id: TransactionId,
+        policy: ExpiryPolicy,
+        outcome: Box<AuditRecord>,
+    },
+}
+
+impl AuditRecord {
+    /// Name of the kind of record, like `NotEnoughMoneyToWithdraw`
+    pub fn kind(&self) -> &'static str {
+        match self {
+            AuditRecord::Processed => "Processed",
+            AuditRecord::CanNotDepositNegative => "CanNotDepositNegative",
+            AuditRecord::CanNotWithdrawNegative => "CanNotWithdrawNegative",
+            AuditRecord::NotEnoughMoneyToWithdraw => "NotEnoughMoneyToWithdraw",
+            AuditRecord::DisputedDepositNotFound => "DisputedDepositNotFound",
+            AuditRecord::DisputedDepositEvicted => "DisputedDepositEvicted",
+            AuditRecord::NotEnoughMoneyToRelease => "NotEnoughMoneyToRelease",
+            AuditRecord::NotEnoughMoneyToChargeBack => "NotEnoughMoneyToChargeBack",
+            AuditRecord::NotEnoughMoneyToRefund => "NotEnoughMoneyToRefund",
+            AuditRecord::MoneyOverflow => "MoneyOverflow",
+            AuditRecord::MoneyUnderflow => "MoneyUnderflow",
+            AuditRecord::DisputeNotFound => "DisputeNotFound",
+            AuditRecord::AccountLocked => "AccountLocked",
+            AuditRecord::AlreadyApplied => "AlreadyApplied",
+            AuditRecord::ConflictsWithApplied => "ConflictsWithApplied",
+            AuditRecord::LimitExceeded { .. } => "LimitExceeded",
+            AuditRecord::ReferencedTransactionNotFound => "ReferencedTransactionNotFound",
+            AuditRecord::ReferencedTransactionEvicted => "ReferencedTransactionEvicted",
+            AuditRecord::RefundExceedsDeposit { .. } => "RefundExceedsDeposit",
+            AuditRecord::IllegalTransition { .. } => "IllegalTransition",
+            AuditRecord::RedisputeLimitReached { .. } => "RedisputeLimitReached",
+            AuditRecord::DisputeExceedsDeposit { .. } => "DisputeExceedsDeposit",
+            AuditRecord::ExceedsDisputedAmount { .. } => "ExceedsDisputedAmount",
+            AuditRecord::InvalidPartialAmount => "InvalidPartialAmount",
+            AuditRecord::Rejected { .. } => "Rejected",
+            AuditRecord::Flagged { .. } => "Flagged",
+            AuditRecord::FeeCharged { .. } => "FeeCharged",
+            AuditRecord::FeeRefunded { .. } => "FeeRefunded",
+            AuditRecord::StorageError => "StorageError",
+            AuditRecord::DisputeExpired { .. } => "DisputeExpired",
+        }
+    }
+
+    /// Readable details of the record, like `remaining 1.5000`, if it has any
+    pub fn details(&self) -> Option<String> {
+        match self {
+            AuditRecord::Processed
+            | AuditRecord::CanNotDepositNegative
+            | AuditRecord::CanNotWithdrawNegative
+            | AuditRecord::NotEnoughMoneyToWithdraw
+            | AuditRecord::DisputedDepositNotFound
+            | AuditRecord::DisputedDepositEvicted
+            | AuditRecord::NotEnoughMoneyToRelease
+            | AuditRecord::NotEnoughMoneyToChargeBack
+            | AuditRecord::NotEnoughMoneyToRefund
+            | AuditRecord::MoneyOverflow
+            | AuditRecord::MoneyUnderflow
+            | AuditRecord::DisputeNotFound
+            | AuditRecord::AccountLocked
+            | AuditRecord::AlreadyApplied
+            | AuditRecord::ConflictsWithApplied
+            | AuditRecord::ReferencedTransactionNotFound
+            | AuditRecord::ReferencedTransactionEvicted
+            | AuditRecord::InvalidPartialAmount
+            | AuditRecord::StorageError => None,
+            AuditRecord::LimitExceeded { limit } => Some(limit.to_string()),
+            AuditRecord::RefundExceedsDeposit { remaining }
+            | AuditRecord::DisputeExceedsDeposit { remaining } => {
+                Some(format!("remaining {remaining}"))
+            }
+            AuditRecord::IllegalTransition { state, action } => {
+                Some(format!("{action:?} of {state:?} transaction"))
+            }
+            AuditRecord::RedisputeLimitReached { max } => Some(format!("at most {max}")),
+            AuditRecord::ExceedsDisputedAmount { disputed } => Some(format!("disputed {disputed}")),
+            AuditRecord::Rejected { reason } | AuditRecord::Flagged { reason } => {
+                Some(reason.clone())
+            }
+            AuditRecord::FeeCharged { fee } | AuditRecord::FeeRefunded { fee } => {
+                Some(format!("fee {fee}"))
+            }
+            AuditRecord::DisputeExpired { outcome, .. } => Some(match outcome.details() {
+                Some(details) => format!("{} ({details})", outcome.kind()),
+                None => outcome.kind().to_string(),
+            }),
+        }
+    }
 }
 
 pub type ClientId = u16;
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 pub struct Account {
     pub available: MoneyAmount,
     pub held: MoneyAmount,
     pub locked: bool,
 
-    /// Amounts of previously seen transactions in this account (for disputes)
-    pub deposited_amounts: HashMap<TransactionId, MoneyAmount>,
-
     /// Amounts that are under active dispute
-    pub disputed_amounts: HashMap<TransactionId, MoneyAmount>,
+    pub disputed_amounts: HashMap<TransactionId, Dispute>,
+}
+
+/// Active dispute of previously deposited money
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dispute {
+    /// Total of all open (partial) disputes of the deposit
+    pub amount: MoneyAmount,
+
+    /// When (the first) dispute was opened, if known
+    pub opened_at: Option<Timestamp>,
+}
+
+impl Dispute {
+    /// Part of disputed amount to resolve or charge back (everything if not given)
+    pub fn part(&self, amount: Option<MoneyAmount>) -> Result<MoneyAmount, AuditRecord> {
+        match amount {
+            None => Ok(self.amount),
+            Some(amount) if amount <= 0 => Err(AuditRecord::InvalidPartialAmount),
+            Some(amount) if amount > self.amount => Err(AuditRecord::ExceedsDisputedAmount {
+                disputed: self.amount,
+            }),
+            Some(amount) => Ok(amount),
+        }
+    }
 }
 
 impl Account {
@@ -37,7 +238,7 @@ impl Account {
     }
 
     /// Deposit money to the account
-    pub fn deposit(&mut self, tx_id: TransactionId, amount: MoneyAmount) -> AuditRecord {
+    pub fn deposit(&mut self, amount: MoneyAmount) -> AuditRecord {
         if amount < 0 {
             return AuditRecord::CanNotDepositNegative;
         }
@@ -47,7 +248,6 @@ impl Account {
         };
 
         self.available = new_available;
-        self.deposited_amounts.insert(tx_id, amount);
 
         AuditRecord::Processed
     }
@@ -75,13 +275,14 @@ impl Account {
         AuditRecord::Processed
     }
 
-    /// Dispute previously deposited money
-    pub fn dispute(&mut self, disputed_tx_id: TransactionId) -> AuditRecord {
-        let Some(disputed_amount) = self.deposited_amounts.get(&disputed_tx_id) else {
-            return AuditRecord::DisputedDepositNotFound;
-        };
-        let disputed_amount = *disputed_amount;
-
+    /// Dispute (part of) previously deposited money,
+    /// adding to already open disputes of the deposit
+    pub fn dispute(
+        &mut self,
+        disputed_tx_id: TransactionId,
+        disputed_amount: MoneyAmount,
+        opened_at: Option<Timestamp>,
+    ) -> AuditRecord {
         let Some(new_held) = self.held.try_change(disputed_amount) else {
             return AuditRecord::MoneyOverflow;
         };
@@ -90,21 +291,39 @@ impl Account {
             return AuditRecord::MoneyUnderflow;
         };
 
+        let dispute = match self.disputed_amounts.get(&disputed_tx_id) {
+            Some(open) => {
+                let Some(amount) = open.amount.try_change(disputed_amount) else {
+                    return AuditRecord::MoneyOverflow;
+                };
+                Dispute { amount, ..*open }
+            }
+            None => Dispute {
+                amount: disputed_amount,
+                opened_at,
+            },
+        };
+
         self.held = new_held;
         self.available = new_available;
-        self.disputed_amounts
-            .insert(disputed_tx_id, disputed_amount);
-        self.deposited_amounts.remove(&disputed_tx_id);
+        self.disputed_amounts.insert(disputed_tx_id, dispute);
 
         AuditRecord::Processed
     }
 
-    /// Resolve dispute
-    pub fn resolve(&mut self, disputed_tx_id: TransactionId) -> AuditRecord {
-        let Some(disputed_amount) = self.disputed_amounts.get(&disputed_tx_id) else {
+    /// Resolve (part of) dispute, deposit can be disputed again afterwards
+    pub fn resolve(
+        &mut self,
+        disputed_tx_id: TransactionId,
+        amount: Option<MoneyAmount>,
+    ) -> AuditRecord {
+        let Some(dispute) = self.disputed_amounts.get(&disputed_tx_id) else {
             return AuditRecord::DisputeNotFound;
         };
-        let disputed_amount = *disputed_amount;
+        let disputed_amount = match dispute.part(amount) {
+            Ok(part) => part,
+            Err(record) => return record,
+        };
 
         if self.held < disputed_amount {
             return AuditRecord::NotEnoughMoneyToRelease;
@@ -119,18 +338,24 @@ impl Account {
 
         self.available = new_available;
         self.held = new_held;
-        self.disputed_amounts.remove(&disputed_tx_id);
-        self.deposited_amounts
-            .insert(disputed_tx_id, disputed_amount);
+        self.close_dispute(disputed_tx_id, disputed_amount);
 
         AuditRecord::Processed
     }
 
-    pub fn chargeback(&mut self, disputed_tx_id: TransactionId) -> AuditRecord {
-        let Some(disputed_amount) = self.disputed_amounts.get(&disputed_tx_id) else {
+    /// Charge back (part of) dispute and lock the account
+    pub fn chargeback(
+        &mut self,
+        disputed_tx_id: TransactionId,
+        amount: Option<MoneyAmount>,
+    ) -> AuditRecord {
+        let Some(dispute) = self.disputed_amounts.get(&disputed_tx_id) else {
             return AuditRecord::DisputeNotFound;
         };
-        let disputed_amount = *disputed_amount;
+        let disputed_amount = match dispute.part(amount) {
+            Ok(part) => part,
+            Err(record) => return record,
+        };
 
         if self.held < disputed_amount {
             return AuditRecord::NotEnoughMoneyToChargeBack;
@@ -141,11 +366,64 @@ impl Account {
         };
 
         self.held = new_held;
-        self.disputed_amounts.remove(&disputed_tx_id);
+        self.close_dispute(disputed_tx_id, disputed_amount);
         self.locked = true;
 
         AuditRecord::Processed
     }
+
+    /// Return deposited money to the sender
+    pub fn refund(&mut self, amount: MoneyAmount) -> AuditRecord {
+        if self.locked {
+            return AuditRecord::AccountLocked;
+        }
+
+        if self.available < amount {
+            return AuditRecord::NotEnoughMoneyToRefund;
+        }
+        let Some(new_available) = self.available.try_change(-amount) else {
+            return AuditRecord::MoneyUnderflow;
+        };
+
+        self.available = new_available;
+
+        AuditRecord::Processed
+    }
+
+    /// Void deposit, even if that leaves the account with negative balance
+    pub fn reverse_deposit(&mut self, amount: MoneyAmount) -> AuditRecord {
+        let Some(new_available) = self.available.try_change(-amount) else {
+            return AuditRecord::MoneyUnderflow;
+        };
+
+        self.available = new_available;
+
+        AuditRecord::Processed
+    }
+
+    /// Void withdrawal, even on locked account
+    pub fn reverse_withdrawal(&mut self, amount: MoneyAmount) -> AuditRecord {
+        let Some(new_available) = self.available.try_change(amount) else {
+            return AuditRecord::MoneyOverflow;
+        };
+
+        self.available = new_available;
+
+        AuditRecord::Processed
+    }
+
+    /// Remove settled part of dispute, forgetting the dispute once nothing is left
+    fn close_dispute(&mut self, disputed_tx_id: TransactionId, settled: MoneyAmount) {
+        let Some(dispute) = self.disputed_amounts.get_mut(&disputed_tx_id) else {
+            return;
+        };
+        match dispute.amount.try_change(-settled) {
+            Some(left) if left > 0 => dispute.amount = left,
+            _ => {
+                self.disputed_amounts.remove(&disputed_tx_id);
+            }
+        }
+    }
 }
 
 /// Helper function to create accounts in tests
@@ -159,7 +437,6 @@ pub fn account(
         available: available.into(),
         held: held.into(),
         locked,
-        deposited_amounts: Default::default(),
         disputed_amounts: Default::default(),
     }
 }
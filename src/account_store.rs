@@ -0,0 +1,496 @@
+use crate::{
+    account::{Account, AuditRecord, ClientId, Dispute},
+    money::{IntegerType, MoneyAmount},
+    transactions::{Transaction, TransactionId},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::{self, File, OpenOptions},
+    io::{self, BufReader, BufWriter, Read, Seek, SeekFrom, Write},
+    path::{Path, PathBuf},
+};
+
+/// Storage of client accounts
+pub trait AccountStore {
+    /// Look up existing account
+    fn get(&mut self, client_id: ClientId) -> io::Result<Option<&Account>>;
+
+    /// Account for modification, created if it does not exist yet
+    fn get_mut(&mut self, client_id: ClientId) -> io::Result<&mut Account>;
+
+    /// Visit every account
+    fn for_each(&mut self, f: impl FnMut(ClientId, &Account)) -> io::Result<()>;
+
+    /// Make all changes durable
+    fn persist(&mut self) -> io::Result<()>;
+
+    /// Start changes made by a single transaction
+    fn begin(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Atomically store changes made by a transaction together with its audit records,
+    /// if storage supports it
+    fn commit(&mut self, _tx: &Transaction, _audit: &[AuditRecord]) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Discard changes made by a transaction, if storage supports it
+    fn rollback(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Accounts kept in memory only
+impl AccountStore for HashMap<ClientId, Account> {
+    fn get(&mut self, client_id: ClientId) -> io::Result<Option<&Account>> {
+        Ok(HashMap::get(self, &client_id))
+    }
+
+    fn get_mut(&mut self, client_id: ClientId) -> io::Result<&mut Account> {
+        Ok(self.entry(client_id).or_default())
+    }
+
+    fn for_each(&mut self, mut f: impl FnMut(ClientId, &Account)) -> io::Result<()> {
+        self.iter()
+            .for_each(|(client_id, account)| f(*client_id, account));
+        Ok(())
+    }
+
+    fn persist(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Accounts kept in a local key-value file, with a bounded cache of recently used accounts.
+///
+/// The file is a log of length prefixed account records where the latest record of a client wins.
+/// Changed accounts are appended when they leave the cache or on `persist`,
+/// which also compacts the file once most of it is outdated records.
+pub struct FileAccountStore {
+    path: PathBuf,
+    file: File,
+
+    /// Offset of the latest record of each client
+    index: HashMap<ClientId, u64>,
+
+    /// Number of records in the file, including outdated ones
+    records: usize,
+
+    /// Recently used accounts with the time they were used
+    cache: HashMap<ClientId, (Account, u64)>,
+
+    /// Cached accounts in order of use, least recently used first
+    recency: BTreeMap<u64, ClientId>,
+
+    /// Cached accounts that may have changed since they were written,
+    /// with their written state (`None` for accounts that were never written)
+    dirty: HashMap<ClientId, Option<Account>>,
+
+    capacity: usize,
+    clock: u64,
+}
+
+impl FileAccountStore {
+    /// Open (or create) account file keeping at most `capacity` accounts in memory
+    pub fn open(path: impl AsRef<Path>, capacity: usize) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+
+        let file_len = file.metadata()?.len();
+        let mut index = HashMap::new();
+        let mut records = 0;
+        let mut offset = 0;
+        let mut reader = BufReader::new(&mut file);
+        // A partially written trailing record (e.g. after a crash) is dropped
+        while let Some((client_id, len)) = read_record_header(&mut reader)? {
+            if len < 2 || offset + 4 + len > file_len {
+                break;
+            }
+            reader.seek_relative(len as i64 - 2)?;
+            index.insert(client_id, offset);
+            records += 1;
+            offset += 4 + len;
+        }
+        file.set_len(offset)?;
+
+        Ok(Self {
+            path,
+            file,
+            index,
+            records,
+            cache: HashMap::new(),
+            recency: BTreeMap::new(),
+            dirty: HashMap::new(),
+            capacity: capacity.max(1),
+            clock: 0,
+        })
+    }
+
+    /// Rewrite the file keeping only the latest record of each account
+    pub fn compact(&mut self) -> io::Result<()> {
+        self.write_dirty()?;
+
+        let compacted_path = self.path.with_extension("compacting");
+        let mut writer = BufWriter::new(File::create(&compacted_path)?);
+        let mut index = HashMap::with_capacity(self.index.len());
+        let mut offset = 0;
+        // index is kept until the compacted file replaces the old one
+        let mut old_index: Vec<(ClientId, u64)> = self
+            .index
+            .iter()
+            .map(|(&client_id, &offset)| (client_id, offset))
+            .collect();
+        old_index.sort_unstable();
+        for (client_id, old_offset) in old_index {
+            let record = self.read_record(old_offset)?;
+            writer.write_all(&record)?;
+            index.insert(client_id, offset);
+            offset += record.len() as u64;
+        }
+        writer.into_inner()?.sync_all()?;
+
+        fs::rename(&compacted_path, &self.path)?;
+        self.file = OpenOptions::new().read(true).write(true).open(&self.path)?;
+        self.records = index.len();
+        self.index = index;
+        Ok(())
+    }
+
+    fn touch(&mut self, client_id: ClientId) {
+        self.clock += 1;
+        if let Some((_, used)) = self.cache.get_mut(&client_id) {
+            self.recency.remove(used);
+            *used = self.clock;
+            self.recency.insert(self.clock, client_id);
+        }
+    }
+
+    /// Load account into the cache, making room for it if necessary
+    fn load(&mut self, client_id: ClientId) -> io::Result<bool> {
+        if self.cache.contains_key(&client_id) {
+            self.touch(client_id);
+            return Ok(true);
+        }
+        let Some(&offset) = self.index.get(&client_id) else {
+            return Ok(false);
+        };
+        let account = decode_account(&self.read_record(offset)?)?.1;
+        self.cache_account(client_id, account)?;
+        Ok(true)
+    }
+
+    fn cache_account(&mut self, client_id: ClientId, account: Account) -> io::Result<()> {
+        while self.cache.len() >= self.capacity {
+            let Some((_, lru_client_id)) = self.recency.pop_first() else {
+                break;
+            };
+            if let Some((account, _)) = self.cache.remove(&lru_client_id) {
+                if let Some(written) = self.dirty.remove(&lru_client_id) {
+                    if !is_written(&account, written.as_ref()) {
+                        self.append(lru_client_id, &encode_account(lru_client_id, &account))?;
+                    }
+                }
+            }
+        }
+
+        self.clock += 1;
+        self.cache.insert(client_id, (account, self.clock));
+        self.recency.insert(self.clock, client_id);
+        Ok(())
+    }
+
+    fn append(&mut self, client_id: ClientId, record: &[u8]) -> io::Result<()> {
+        let offset = self.file.seek(SeekFrom::End(0))?;
+        self.file.write_all(record)?;
+        self.index.insert(client_id, offset);
+        self.records += 1;
+        Ok(())
+    }
+
+    fn write_dirty(&mut self) -> io::Result<()> {
+        let mut dirty: Vec<(ClientId, Option<Account>)> = self.dirty.drain().collect();
+        dirty.sort_unstable_by_key(|(client_id, _)| *client_id);
+        for (client_id, written) in dirty {
+            let Some((account, _)) = self.cache.get(&client_id) else {
+                continue;
+            };
+            if is_written(account, written.as_ref()) {
+                continue;
+            }
+            let record = encode_account(client_id, account);
+            self.append(client_id, &record)?;
+        }
+        Ok(())
+    }
+
+    /// Read whole record (including length prefix) at given offset
+    fn read_record(&mut self, offset: u64) -> io::Result<Vec<u8>> {
+        self.file.seek(SeekFrom::Start(offset))?;
+        let mut len = [0u8; 4];
+        self.file.read_exact(&mut len)?;
+        let mut record = len.to_vec();
+        record.resize(4 + u32::from_le_bytes(len) as usize, 0);
+        self.file.read_exact(&mut record[4..])?;
+        Ok(record)
+    }
+}
+
+impl AccountStore for FileAccountStore {
+    fn get(&mut self, client_id: ClientId) -> io::Result<Option<&Account>> {
+        if !self.load(client_id)? {
+            return Ok(None);
+        }
+        Ok(self.cache.get(&client_id).map(|(account, _)| account))
+    }
+
+    fn get_mut(&mut self, client_id: ClientId) -> io::Result<&mut Account> {
+        if !self.load(client_id)? {
+            self.cache_account(client_id, Account::default())?;
+        }
+        let account = &mut self
+            .cache
+            .get_mut(&client_id)
+            .expect("account was just cached")
+            .0;
+        if !self.dirty.contains_key(&client_id) {
+            let written = self.index.contains_key(&client_id).then(|| account.clone());
+            self.dirty.insert(client_id, written);
+        }
+        Ok(account)
+    }
+
+    fn for_each(&mut self, mut f: impl FnMut(ClientId, &Account)) -> io::Result<()> {
+        let mut client_ids: Vec<ClientId> = self.index.keys().copied().collect();
+        client_ids.extend(self.cache.keys().filter(|id| !self.index.contains_key(id)));
+        client_ids.sort_unstable();
+
+        for client_id in client_ids {
+            if let Some((account, _)) = self.cache.get(&client_id) {
+                f(client_id, account);
+            } else if let Some(&offset) = self.index.get(&client_id) {
+                let (_, account) = decode_account(&self.read_record(offset)?)?;
+                f(client_id, &account);
+            }
+        }
+        Ok(())
+    }
+
+    fn persist(&mut self) -> io::Result<()> {
+        self.write_dirty()?;
+        if self.records > 2 * self.index.len() {
+            self.compact()?;
+        }
+        self.file.sync_all()
+    }
+}
+
+/// Whether account is the same as its written state (all stored fields are compared)
+fn is_written(account: &Account, written: Option<&Account>) -> bool {
+    written.is_some_and(|written| {
+        account.available == written.available
+            && account.held == written.held
+            && account.locked == written.locked
+            && account.disputed_amounts == written.disputed_amounts
+    })
+}
+
+/// Read record length and client id, `None` at the end of file
+fn read_record_header(reader: &mut impl Read) -> io::Result<Option<(ClientId, u64)>> {
+    let mut header = [0u8; 6];
+    match reader.read_exact(&mut header) {
+        Ok(()) => {}
+        Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => return Ok(None),
+        Err(error) => return Err(error),
+    }
+    let len = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+    Ok(Some((
+        ClientId::from_le_bytes([header[4], header[5]]),
+        len as u64,
+    )))
+}
+
+/// Account record: length, client id, available, held, locked flag, then disputes
+/// as tx id, amount and optional opening time
+fn encode_account(client_id: ClientId, account: &Account) -> Vec<u8> {
+    let mut record = vec![0u8; 4];
+    record.extend(client_id.to_le_bytes());
+    record.extend(account.available.to_raw().to_le_bytes());
+    record.extend(account.held.to_raw().to_le_bytes());
+    record.push(account.locked as u8);
+
+    let mut disputes: Vec<_> = account.disputed_amounts.iter().collect();
+    disputes.sort_unstable_by_key(|(tx_id, _)| **tx_id);
+    for (tx_id, dispute) in disputes {
+        record.extend(tx_id.to_le_bytes());
+        record.extend(dispute.amount.to_raw().to_le_bytes());
+        record.push(dispute.opened_at.is_some() as u8);
+        record.extend(dispute.opened_at.unwrap_or_default().to_le_bytes());
+    }
+
+    let len = (record.len() - 4) as u32;
+    record[0..4].copy_from_slice(&len.to_le_bytes());
+    record
+}
+
+fn decode_account(record: &[u8]) -> io::Result<(ClientId, Account)> {
+    let mut fields = Fields(&record[4..]);
+    let client_id = ClientId::from_le_bytes(fields.take()?);
+    let mut account = Account {
+        available: MoneyAmount::from_raw(IntegerType::from_le_bytes(fields.take()?)),
+        held: MoneyAmount::from_raw(IntegerType::from_le_bytes(fields.take()?)),
+        locked: fields.take::<1>()?[0] != 0,
+        ..Default::default()
+    };
+
+    while !fields.0.is_empty() {
+        let tx_id = TransactionId::from_le_bytes(fields.take()?);
+        let amount = MoneyAmount::from_raw(IntegerType::from_le_bytes(fields.take()?));
+        let has_opened_at = fields.take::<1>()?[0] != 0;
+        let opened_at = u64::from_le_bytes(fields.take()?);
+        account.disputed_amounts.insert(
+            tx_id,
+            Dispute {
+                amount,
+                opened_at: has_opened_at.then_some(opened_at),
+            },
+        );
+    }
+
+    Ok((client_id, account))
+}
+
+/// Fixed size fields of a record
+struct Fields<'a>(&'a [u8]);
+
+impl Fields<'_> {
+    fn take<const N: usize>(&mut self) -> io::Result<[u8; N]> {
+        if self.0.len() < N {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "truncated account record",
+            ));
+        }
+        let (field, rest) = self.0.split_at(N);
+        self.0 = rest;
+        Ok(field.try_into().expect("field has requested size"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::account::account;
+
+    fn temp_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("transactor-{name}-{}", std::process::id()))
+    }
+
+    #[test]
+    fn encodes_accounts() {
+        let mut original = account(12.5, 3, true);
+        original.disputed_amounts.insert(
+            7,
+            Dispute {
+                amount: 3.into(),
+                opened_at: Some(42),
+            },
+        );
+
+        let (client_id, decoded) = decode_account(&encode_account(9, &original)).unwrap();
+
+        assert_eq!(client_id, 9);
+        assert_eq!(decoded, original);
+        assert_eq!(decoded.disputed_amounts, original.disputed_amounts);
+    }
+
+    #[test]
+    fn keeps_accounts_beyond_cache_capacity() {
+        let path = temp_path("accounts-capacity");
+        let mut store = FileAccountStore::open(&path, 2).unwrap();
+        for client_id in 1..=5 {
+            store.get_mut(client_id).unwrap().available = MoneyAmount::from(client_id as i64);
+        }
+
+        assert_eq!(store.cache.len(), 2);
+        for client_id in 1..=5 {
+            assert_eq!(
+                store.get(client_id).unwrap(),
+                Some(&account(client_id as i64, 0, false))
+            );
+        }
+        assert_eq!(store.get(6).unwrap(), None);
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn keeps_accounts_across_restarts() {
+        let path = temp_path("accounts-restart");
+        let mut store = FileAccountStore::open(&path, 10).unwrap();
+        store.get_mut(1).unwrap().available = 10.into();
+        store.get_mut(2).unwrap().held = 20.into();
+        store.persist().unwrap();
+        store.get_mut(1).unwrap().available = 15.into();
+        store.persist().unwrap();
+        drop(store);
+
+        let mut store = FileAccountStore::open(&path, 10).unwrap();
+        let mut accounts = HashMap::new();
+        store
+            .for_each(|client_id, account| {
+                accounts.insert(client_id, account.clone());
+            })
+            .unwrap();
+
+        assert_eq!(
+            accounts,
+            [(1, account(15, 0, false)), (2, account(0, 20, false))].into()
+        );
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn unchanged_accounts_are_not_rewritten() {
+        let path = temp_path("accounts-unchanged");
+        let mut store = FileAccountStore::open(&path, 1).unwrap();
+        store.get_mut(1).unwrap().available = 10.into();
+        store.persist().unwrap();
+
+        store.get_mut(1).unwrap();
+        store.get_mut(2).unwrap();
+        store.get_mut(1).unwrap();
+        store.persist().unwrap();
+
+        assert_eq!(store.records, 2);
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn failed_compaction_keeps_index() {
+        let path = temp_path("accounts-compaction");
+        let mut store = FileAccountStore::open(&path, 1).unwrap();
+        store.get_mut(1).unwrap().available = 10.into();
+        store.get_mut(2).unwrap().available = 20.into();
+        store.persist().unwrap();
+        // records can not be read anymore
+        OpenOptions::new()
+            .write(true)
+            .open(&path)
+            .unwrap()
+            .set_len(0)
+            .unwrap();
+
+        assert!(store.compact().is_err());
+        assert_eq!(store.index.len(), 2);
+
+        fs::remove_file(path.with_extension("compacting")).unwrap();
+        fs::remove_file(path).unwrap();
+    }
+}
@@ -0,0 +1,209 @@
+use crate::transactions::{Transaction, TransactionId};
+use std::{
+    collections::HashMap,
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, Read, Write},
+    path::Path,
+};
+
+/// Hash of transaction content that stays the same across runs and builds
+pub type ContentHash = u64;
+
+/// FNV-1a hash of type, client, referenced transaction, amount and time of transaction
+pub fn content_hash(tx: &Transaction) -> ContentHash {
+    let amount = tx
+        .detail
+        .amount()
+        .map(|a| a.to_string())
+        .unwrap_or_default();
+    let timestamp = tx.timestamp.map(|t| t.to_string()).unwrap_or_default();
+    let content = format!(
+        "{},{},{},{amount},{timestamp}",
+        tx.detail.name(),
+        tx.client_id,
+        tx.input_tx_id()
+    );
+
+    content.bytes().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ ContentHash::from(byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// Storage of ids of processed transactions with hashes of their content
+pub trait AppliedStore {
+    /// Hash of content of processed transaction with given id
+    fn get(&mut self, id: TransactionId) -> io::Result<Option<ContentHash>>;
+
+    /// Remember processed transaction
+    fn insert(&mut self, id: TransactionId, hash: ContentHash) -> io::Result<()>;
+
+    /// Keep transactions inserted since the last commit, once their changes are committed
+    fn commit(&mut self) {}
+
+    /// Forget transactions inserted since the last commit, their changes were not stored
+    fn rollback(&mut self) {}
+
+    /// Make committed transactions durable, after the accounts they changed
+    fn persist(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Default for Box<dyn AppliedStore> {
+    fn default() -> Self {
+        Box::new(FileAppliedStore::default())
+    }
+}
+
+const RECORD_SIZE: usize = size_of::<TransactionId>() + size_of::<ContentHash>();
+
+/// Processed transactions kept in memory, and in an append-only file if it is given.
+///
+/// Each record of the file is tx id and content hash.
+/// Records are written only on `persist`, so that the file never has transactions
+/// whose account changes were not stored.
+#[derive(Default)]
+pub struct FileAppliedStore {
+    hashes: HashMap<TransactionId, ContentHash>,
+    writer: Option<BufWriter<File>>,
+
+    /// Transactions inserted since the last commit
+    uncommitted: Vec<TransactionId>,
+
+    /// Committed transactions that are not written yet
+    unwritten: Vec<(TransactionId, ContentHash)>,
+}
+
+impl FileAppliedStore {
+    /// Open (or create) file of transactions processed by previous runs
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+        let mut content = Vec::new();
+        file.read_to_end(&mut content)?;
+
+        // A partially written trailing record (e.g. after a crash) is dropped
+        let records = content.chunks_exact(RECORD_SIZE);
+        file.set_len((content.len() - records.remainder().len()) as u64)?;
+        let hashes = records
+            .map(|record| {
+                let (id, hash) = record.split_at(size_of::<TransactionId>());
+                (
+                    TransactionId::from_le_bytes(id.try_into().unwrap()),
+                    ContentHash::from_le_bytes(hash.try_into().unwrap()),
+                )
+            })
+            .collect();
+
+        Ok(Self {
+            hashes,
+            writer: Some(BufWriter::new(file)),
+            ..Default::default()
+        })
+    }
+}
+
+impl AppliedStore for FileAppliedStore {
+    fn get(&mut self, id: TransactionId) -> io::Result<Option<ContentHash>> {
+        Ok(self.hashes.get(&id).copied())
+    }
+
+    fn insert(&mut self, id: TransactionId, hash: ContentHash) -> io::Result<()> {
+        self.hashes.insert(id, hash);
+        self.uncommitted.push(id);
+        Ok(())
+    }
+
+    fn commit(&mut self) {
+        for id in self.uncommitted.drain(..) {
+            if self.writer.is_some() {
+                self.unwritten.push((id, self.hashes[&id]));
+            }
+        }
+    }
+
+    fn rollback(&mut self) {
+        for id in self.uncommitted.drain(..) {
+            self.hashes.remove(&id);
+        }
+    }
+
+    fn persist(&mut self) -> io::Result<()> {
+        let Some(writer) = &mut self.writer else {
+            return Ok(());
+        };
+        for (id, hash) in &self.unwritten {
+            writer.write_all(&id.to_le_bytes())?;
+            writer.write_all(&hash.to_le_bytes())?;
+        }
+        writer.flush()?;
+        writer.get_ref().sync_data()?;
+        self.unwritten.clear();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::{deposit, dispute};
+
+    #[test]
+    fn hash_depends_on_content() {
+        assert_eq!(
+            content_hash(&deposit(1, 1, 2.5)),
+            content_hash(&deposit(1, 1, 2.5))
+        );
+        assert_ne!(
+            content_hash(&deposit(1, 1, 2.5)),
+            content_hash(&deposit(1, 1, 2.5).at(7))
+        );
+        assert_ne!(
+            content_hash(&dispute(1, 1).with_id(2)),
+            content_hash(&dispute(2, 1).with_id(2))
+        );
+    }
+
+    #[test]
+    fn keeps_processed_transactions_across_runs() {
+        let path = std::env::temp_dir().join(format!("transactor-applied-{}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+
+        let mut store = FileAppliedStore::open(&path).unwrap();
+        store.insert(1, 10).unwrap();
+        store.insert(2, 20).unwrap();
+        store.commit();
+        store.insert(4, 40).unwrap();
+        store.rollback();
+        assert_eq!(store.get(4).unwrap(), None);
+        store.persist().unwrap();
+        store.insert(5, 50).unwrap();
+        store.commit();
+        // Not persisted, like after a crash before accounts are stored
+        drop(store);
+        // Half-written record is ignored
+        OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(&[3, 0])
+            .unwrap();
+
+        let mut store = FileAppliedStore::open(&path).unwrap();
+        assert_eq!(store.get(1).unwrap(), Some(10));
+        assert_eq!(store.get(3).unwrap(), None);
+        assert_eq!(store.get(5).unwrap(), None);
+        store.insert(3, 30).unwrap();
+        store.commit();
+        store.persist().unwrap();
+        drop(store);
+
+        let mut store = FileAppliedStore::open(&path).unwrap();
+        assert_eq!(store.get(2).unwrap(), Some(20));
+        assert_eq!(store.get(3).unwrap(), Some(30));
+        std::fs::remove_file(path).unwrap();
+    }
+}
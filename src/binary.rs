@@ -0,0 +1,283 @@
+use crate::{
+    account::ClientId,
+    input::Row,
+    money::{IntegerType, MoneyAmount},
+    transactions::{Timestamp, Transaction, TransactionId},
+};
+use std::io::{self, Write};
+
+/// First bytes of binary transaction file
+const MAGIC: &[u8; 4] = b"TXNB";
+
+/// Version of record layout written after magic bytes
+pub const VERSION: u16 = 1;
+
+/// Transaction types in order of their tags
+const TYPES: [&str; 7] = [
+    "deposit",
+    "withdrawal",
+    "dispute",
+    "resolve",
+    "chargeback",
+    "refund",
+    "reversal",
+];
+
+const HAS_AMOUNT: u8 = 1;
+const HAS_TIMESTAMP: u8 = 2;
+const HAS_ID: u8 = 4;
+
+/// Whether bytes start like binary transaction file
+pub fn is_binary(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC)
+}
+
+/// Writer of binary transaction file.
+///
+/// File is magic bytes and version followed by records of
+/// `length: u16, tag: u8, flags: u8, client: u16, tx: u32, [amount: i64], [timestamp: u64], [id: u32], crc32: u32`
+/// in little endian, where length covers the fields between itself and checksum
+/// and flags tell which of the optional fields are present.
+pub struct Encoder<W: Write> {
+    writer: W,
+    record: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(mut writer: W) -> io::Result<Self> {
+        writer.write_all(MAGIC)?;
+        writer.write_all(&VERSION.to_le_bytes())?;
+        Ok(Self {
+            writer,
+            record: Vec::new(),
+        })
+    }
+
+    pub fn write(&mut self, tx: &Transaction) -> io::Result<()> {
+        let tag = TYPES
+            .iter()
+            .position(|name| *name == tx.detail.name())
+            .unwrap_or_default() as u8;
+        let amount = tx.detail.amount();
+        let id = tx.referencing_id();
+        let flags = [
+            (amount.is_some(), HAS_AMOUNT),
+            (tx.timestamp.is_some(), HAS_TIMESTAMP),
+            (id.is_some(), HAS_ID),
+        ]
+        .into_iter()
+        .filter(|(present, _)| *present)
+        .fold(0, |flags, (_, flag)| flags | flag);
+
+        let record = &mut self.record;
+        record.clear();
+        record.extend([tag, flags]);
+        record.extend(tx.client_id.to_le_bytes());
+        record.extend(tx.input_tx_id().to_le_bytes());
+        if let Some(amount) = amount {
+            record.extend(amount.to_raw().to_le_bytes());
+        }
+        if let Some(timestamp) = tx.timestamp {
+            record.extend(timestamp.to_le_bytes());
+        }
+        if let Some(id) = id {
+            record.extend(id.to_le_bytes());
+        }
+
+        self.writer
+            .write_all(&(record.len() as u16).to_le_bytes())?;
+        self.writer.write_all(record)?;
+        self.writer.write_all(&crc32(record).to_le_bytes())
+    }
+
+    /// Flush and return the underlying writer
+    pub fn finish(mut self) -> io::Result<W> {
+        self.writer.flush()?;
+        Ok(self.writer)
+    }
+}
+
+/// Reader of binary transaction file that decodes transactions straight from its bytes
+pub struct Decoder<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl<'a> Decoder<'a> {
+    /// Check header of the file
+    pub fn new(bytes: &'a [u8]) -> io::Result<Self> {
+        if !is_binary(bytes) || bytes.len() < MAGIC.len() + 2 {
+            return Err(invalid_data("not a binary transaction file".into()));
+        }
+        let version = u16::from_le_bytes([bytes[4], bytes[5]]);
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported binary format version {version}"
+            )));
+        }
+        Ok(Self { bytes, offset: 6 })
+    }
+
+    fn take(&mut self, len: usize) -> io::Result<&'a [u8]> {
+        let bytes = self
+            .bytes
+            .get(self.offset..self.offset + len)
+            .ok_or_else(|| self.invalid("truncated record"))?;
+        self.offset += len;
+        Ok(bytes)
+    }
+
+    fn invalid(&self, message: &str) -> io::Error {
+        invalid_data(format!("{message} at byte {}", self.offset))
+    }
+
+    fn decode(&mut self) -> io::Result<Transaction> {
+        let len = u16::from_le_bytes(self.take(2)?.try_into().unwrap());
+        let start = self.offset;
+        let record = self.take(len.into())?;
+        let checksum = u32::from_le_bytes(self.take(4)?.try_into().unwrap());
+        if crc32(record) != checksum {
+            self.offset = start;
+            return Err(self.invalid("checksum mismatch"));
+        }
+
+        match transaction(record) {
+            Some(tx) => Ok(tx),
+            None => {
+                self.offset = start;
+                Err(self.invalid("invalid record"))
+            }
+        }
+    }
+}
+
+impl Iterator for Decoder<'_> {
+    type Item = io::Result<Transaction>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.offset >= self.bytes.len() {
+            return None;
+        }
+        let decoded = self.decode();
+        if decoded.is_err() {
+            // Records after a corrupted one can not be found reliably
+            self.offset = self.bytes.len();
+        }
+        Some(decoded)
+    }
+}
+
+/// Transaction of a valid record
+fn transaction(record: &[u8]) -> Option<Transaction> {
+    let mut fields = Fields(record);
+    let [tag, flags] = fields.next()?;
+    let row = Row {
+        kind: TYPES.get(usize::from(tag))?,
+        client: Some(ClientId::from_le_bytes(fields.next()?)),
+        tx: Some(TransactionId::from_le_bytes(fields.next()?)),
+        amount: fields
+            .optional(flags & HAS_AMOUNT)?
+            .map(|raw| MoneyAmount::from_raw(IntegerType::from_le_bytes(raw))),
+        timestamp: fields
+            .optional(flags & HAS_TIMESTAMP)?
+            .map(Timestamp::from_le_bytes),
+        id: fields
+            .optional(flags & HAS_ID)?
+            .map(TransactionId::from_le_bytes),
+    };
+    row.transaction().ok().filter(|_| fields.0.is_empty())
+}
+
+/// Remaining bytes of a record
+struct Fields<'a>(&'a [u8]);
+
+impl Fields<'_> {
+    fn next<const N: usize>(&mut self) -> Option<[u8; N]> {
+        let (field, rest) = self.0.split_first_chunk::<N>()?;
+        self.0 = rest;
+        Some(*field)
+    }
+
+    /// Field that is present if flag is set, `None` if it is missing
+    fn optional<const N: usize>(&mut self, flag: u8) -> Option<Option<[u8; N]>> {
+        match flag {
+            0 => Some(None),
+            _ => self.next().map(Some),
+        }
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+/// CRC-32 (IEEE) checksum
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes.iter().fold(!0u32, |crc, byte| {
+        (0..8).fold(crc ^ u32::from(*byte), |crc, _| {
+            (crc >> 1) ^ (0xedb88320 & (crc & 1).wrapping_neg())
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::{chargeback, deposit, dispute, refund, reversal, withdraw};
+
+    fn encode(transactions: &[Transaction]) -> Vec<u8> {
+        let mut encoder = Encoder::new(Vec::new()).unwrap();
+        for tx in transactions {
+            encoder.write(tx).unwrap();
+        }
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn checksum() {
+        assert_eq!(crc32(b"123456789"), 0xcbf43926);
+    }
+
+    #[test]
+    fn round_trip() {
+        let transactions = [
+            deposit(1, 1, "922337203685477.5807".parse::<MoneyAmount>().unwrap()).at(10),
+            withdraw(65535, u32::MAX, -0.0001),
+            dispute(1, 1).partial(0.5).with_id(3).at(u64::MAX),
+            chargeback(1, 1),
+            refund(2, 7).with_id(0),
+            reversal(2, 8),
+        ];
+        let bytes = encode(&transactions);
+
+        let decoded: Vec<Transaction> = Decoder::new(&bytes)
+            .unwrap()
+            .collect::<io::Result<_>>()
+            .unwrap();
+        assert_eq!(format!("{decoded:?}"), format!("{transactions:?}"));
+    }
+
+    #[test]
+    fn rejects_corrupted_files() {
+        let mut bytes = encode(&[deposit(1, 1, 1), deposit(1, 2, 2)]);
+        assert!(Decoder::new(b"TXNB").is_err());
+        assert!(Decoder::new(b"type,client,tx,amount").is_err());
+
+        let mut unsupported = bytes.clone();
+        unsupported[4] = 2;
+        assert!(Decoder::new(&unsupported).is_err());
+
+        bytes[12] ^= 1;
+        let decoded: Vec<io::Result<Transaction>> = Decoder::new(&bytes).unwrap().collect();
+        assert_eq!(decoded.len(), 1);
+        assert!(decoded[0]
+            .as_ref()
+            .is_err_and(|e| e.to_string() == "checksum mismatch at byte 8"));
+
+        bytes[12] ^= 1;
+        bytes.pop();
+        let decoded: Vec<io::Result<Transaction>> = Decoder::new(&bytes).unwrap().collect();
+        assert!(decoded[0].is_ok());
+        assert!(decoded[1].is_err());
+    }
+}
@@ -0,0 +1,471 @@
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::{self, Display},
+    io,
+    str::FromStr,
+};
+
+pub const USAGE: &str = "usage:
+    transactor [process] <input>... [options]
+    transactor validate <input>... [options]
+    transactor stats <input>... [options]
+    transactor expiring <input>... --max-dispute-duration <seconds> --within <seconds> [options]
+    transactor replay <journal> [--until <seq>] [--verify <snapshot>] [options]
+    transactor convert <input> <output> [--input-format <format>] [--output-format <format>]
+    transactor help
+
+commands:
+    process     process transactions and print accounts (default)
+    validate    process transactions over stored state without changing it and print
+                their outcomes, failed transactions, clients that would be locked
+                and balance changes
+    stats       process transactions and print how many of them were processed, failed
+                or skipped instead of accounts
+    expiring    process transactions and print disputes that expire soon instead of accounts
+    replay      rebuild accounts from journal
+    convert     convert transactions to another format
+
+inputs are files, directories (for all files in them) or file name patterns with * and ?,
+all are processed in one run
+
+options:
+    -h, --help                          print this help
+    --log-level error|warn|info|debug   what to report to stderr: errors only, rejected and
+                                        skipped transactions, statistics of input files
+                                        (default) or outcome of every transaction
+    --order args|name|mtime             order of input files: as given with files of directories
+                                        and patterns by name (default), by name
+                                        or by modification time
+    --on-error fail|continue            whether to stop when input file can not be read
+                                        (default fail) or to go on with the next one
+    --input-format csv|jsonl|binary     format of input file (by default jsonl for .jsonl
+                                        and .ndjson files, binary for .txb files, csv otherwise)
+    --output-format csv|jsonl|binary    format of converted file (by its extension by default)
+    --csv-config <path>                 read CSV columns with other names and CSV dialect
+                                        from config file
+    --csv-delimiter <char>              delimiter of CSV input and output (default ,)
+    --csv-quote <char>                  quote of CSV fields (default \")
+    --csv-decimal-separator <char>      decimal separator of CSV amounts (default .)
+    --csv-comment <char>                skip CSV lines starting with it
+    --csv-no-header                     CSV input and output has no header, columns are
+                                        type, client, tx, amount, timestamp, id
+    --csv-no-trim                       keep whitespace around CSV fields
+    --output <path>                     write account report to a file instead of stdout
+                                        (compressed if it ends with .gz or .zst)
+    --max-dispute-duration <seconds>    automatically close disputes open for longer
+    --expiry-policy resolve|chargeback  how to close expired disputes (default resolve)
+    --within <seconds>                  print disputes that expire within this time
+    --max-redisputes <count>            how many times resolved dispute can be disputed again
+    --max-deposit-age <seconds>         forget deposits older than this for disputes
+    --max-deposits-per-account <count>  remember at most this many deposits per account
+    --max-deposits <count>              remember at most this many deposits in total
+    --spill-deposits <path>             keep forgotten deposits in a file instead
+    --accounts <path>                   keep accounts in a file (and across runs) instead of memory
+    --account-cache <count>             accounts from the file to keep in memory (default 10000)
+    --applied <path>                    remember processed transactions across runs in a file
+                                        to skip them when they are sent again
+    --db <path.sqlite>                  keep accounts, deposit history, processed transactions
+                                        and outcomes in SQLite database
+                                        (requires sqlite feature)
+    --limits <path>                     enforce velocity and amount limits from config file
+    --fees <path>                       charge fees from config file on deposits and withdrawals
+    --middleware <path>                 run transactions through middleware chain from config file
+    --screening <path>                  screen transactions with fraud rules from config file
+                                        (empty file for all), report flagged and rejected
+                                        ones to stderr
+    --journal <path>                    append processed transactions to a journal
+    --outcomes <path>                   write outcome of every transaction to a CSV file
+                                        (compressed if it ends with .gz or .zst)
+    --snapshot <path>                   write account states after processing to a snapshot
+    --summary <path>|-                  write statistics of the run (counts by type and outcome,
+                                        volumes, accounts created and locked, throughput)
+                                        as JSON to a file, or readable to stderr with -
+    --metrics <address:port>            serve Prometheus metrics on http://<address:port>/metrics
+                                        while transactions are processed
+    --max-failed-percent <percent>      validation fails if more transactions would fail
+    --max-locked-clients <count>        validation fails if more clients would be locked
+    --until <seq>                       replay journal up to this transaction
+    --verify <snapshot>                 check that replayed accounts match the snapshot
+
+exit codes:
+    0   success
+    1   processing failed, like replayed accounts do not match the snapshot
+        or validation found more failed transactions or locked clients than allowed
+    2   invalid command line
+    3   invalid input, journal or config file
+    4   file can not be read or written";
+
+/// Options that have a value
+const OPTIONS: [&str; 36] = [
+    "--log-level",
+    "--order",
+    "--on-error",
+    "--input-format",
+    "--output-format",
+    "--csv-config",
+    "--csv-delimiter",
+    "--csv-quote",
+    "--csv-decimal-separator",
+    "--csv-comment",
+    "--output",
+    "--max-dispute-duration",
+    "--expiry-policy",
+    "--within",
+    "--max-redisputes",
+    "--max-deposit-age",
+    "--max-deposits-per-account",
+    "--max-deposits",
+    "--spill-deposits",
+    "--accounts",
+    "--account-cache",
+    "--applied",
+    "--db",
+    "--limits",
+    "--fees",
+    "--middleware",
+    "--screening",
+    "--journal",
+    "--outcomes",
+    "--snapshot",
+    "--summary",
+    "--metrics",
+    "--max-failed-percent",
+    "--max-locked-clients",
+    "--until",
+    "--verify",
+];
+
+/// Options that have no value
+const FLAGS: [&str; 2] = ["--csv-no-header", "--csv-no-trim"];
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    Process,
+    Validate,
+    Stats,
+    Expiring,
+    Replay,
+    Convert,
+    Help,
+}
+
+impl Command {
+    /// Command named by argument
+    fn named(arg: &str) -> Option<Self> {
+        Some(match arg {
+            "process" => Command::Process,
+            "validate" => Command::Validate,
+            "stats" => Command::Stats,
+            "expiring" => Command::Expiring,
+            "replay" => Command::Replay,
+            "convert" => Command::Convert,
+            "help" | "--help" | "-h" => Command::Help,
+            _ => return None,
+        })
+    }
+
+    /// How many positional arguments command takes, at least and at most
+    fn arity(self) -> (usize, usize) {
+        match self {
+            Command::Process | Command::Validate | Command::Stats | Command::Expiring => {
+                (1, usize::MAX)
+            }
+            Command::Replay => (1, 1),
+            Command::Convert => (2, 2),
+            Command::Help => (0, usize::MAX),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
+pub enum LogLevel {
+    Error,
+    Warn,
+    Info,
+    Debug,
+}
+
+impl FromStr for LogLevel {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown log level {s}"),
+            )),
+        }
+    }
+}
+
+/// Parsed command line
+#[derive(Debug)]
+pub struct Args {
+    pub command: Command,
+
+    /// Arguments that are not options or their values
+    pub positional: Vec<String>,
+
+    pub log_level: LogLevel,
+
+    options: HashMap<&'static str, String>,
+}
+
+impl Args {
+    /// Parse arguments (without program name), options can be anywhere after command
+    pub fn parse(args: impl IntoIterator<Item = String>) -> Result<Self, Failure> {
+        let mut args = args.into_iter().peekable();
+        let command = args.peek().and_then(|arg| Command::named(arg));
+        if command.is_some() {
+            args.next();
+        }
+        let mut command = command.unwrap_or(Command::Process);
+
+        let mut positional = Vec::new();
+        let mut options = HashMap::new();
+        while let Some(arg) = args.next() {
+            if matches!(arg.as_str(), "--help" | "-h") {
+                command = Command::Help;
+                continue;
+            }
+            if !arg.starts_with('-') || arg == "-" {
+                positional.push(arg);
+                continue;
+            }
+            let (name, value) = if let Some(flag) = FLAGS.iter().find(|flag| **flag == arg) {
+                (*flag, String::new())
+            } else if let Some(option) = OPTIONS.iter().find(|option| **option == arg) {
+                let value = args.next();
+                let value = value.ok_or_else(|| Failure::usage(format!("{arg} needs a value")))?;
+                (*option, value)
+            } else {
+                return Err(Failure::usage(format!("unknown option {arg}")));
+            };
+            if options.insert(name, value).is_some() {
+                return Err(Failure::usage(format!("{arg} is given more than once")));
+            }
+        }
+
+        let (min, max) = command.arity();
+        if positional.len() < min || positional.len() > max {
+            let expected = match command {
+                Command::Replay => "one journal",
+                Command::Convert => "input and output files",
+                _ => "input files",
+            };
+            return Err(Failure::usage(format!("expected {expected}")));
+        }
+
+        let mut args = Self {
+            command,
+            positional,
+            log_level: LogLevel::Info,
+            options,
+        };
+        args.log_level = args.parsed("--log-level")?.unwrap_or(LogLevel::Info);
+        Ok(args)
+    }
+
+    /// Value of `--name value` option
+    pub fn option(&self, name: &str) -> Option<&str> {
+        debug_assert!(
+            OPTIONS.contains(&name) || FLAGS.contains(&name),
+            "{name} is not a known option"
+        );
+        self.options.get(name).map(String::as_str)
+    }
+
+    /// Parsed value of `--name value` option
+    pub fn parsed<T>(&self, name: &str) -> Result<Option<T>, Failure>
+    where
+        T: FromStr,
+        T::Err: Display,
+    {
+        self.option(name)
+            .map(|value| {
+                value
+                    .parse()
+                    .map_err(|e| Failure::usage(format!("invalid {name} {value}: {e}")))
+            })
+            .transpose()
+    }
+
+    /// Whether option without value is given
+    pub fn flag(&self, name: &str) -> bool {
+        self.option(name).is_some()
+    }
+
+    /// Whether messages of given level are reported
+    pub fn logs(&self, level: LogLevel) -> bool {
+        level <= self.log_level
+    }
+}
+
+/// What made transactor fail, decides its exit code
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FailureKind {
+    Processing,
+    Usage,
+    Parse,
+    Io,
+
+    /// Output was closed by reader (like `head`), nothing is reported
+    Closed,
+}
+
+/// Error that stops transactor
+#[derive(Debug)]
+pub struct Failure {
+    pub kind: FailureKind,
+    pub message: String,
+}
+
+impl Failure {
+    pub fn new(kind: FailureKind, message: impl Display) -> Self {
+        Self {
+            kind,
+            message: message.to_string(),
+        }
+    }
+
+    pub fn usage(message: impl Display) -> Self {
+        Self::new(FailureKind::Usage, message)
+    }
+
+    pub fn processing(message: impl Display) -> Self {
+        Self::new(FailureKind::Processing, message)
+    }
+
+    /// Prefix message with what failed, like path of the file
+    pub fn context(self, context: impl Display) -> Self {
+        Self::new(self.kind, format!("{context}: {}", self.message))
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        match self.kind {
+            FailureKind::Closed => 0,
+            FailureKind::Processing => 1,
+            FailureKind::Usage => 2,
+            FailureKind::Parse => 3,
+            FailureKind::Io => 4,
+        }
+    }
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.message)?;
+        if self.kind == FailureKind::Usage {
+            write!(f, "\nrun transactor --help for usage")?;
+        }
+        Ok(())
+    }
+}
+
+impl From<io::Error> for Failure {
+    fn from(error: io::Error) -> Self {
+        let kind = match error.kind() {
+            io::ErrorKind::BrokenPipe => FailureKind::Closed,
+            io::ErrorKind::InvalidData
+            | io::ErrorKind::InvalidInput
+            | io::ErrorKind::UnexpectedEof => FailureKind::Parse,
+            _ => FailureKind::Io,
+        };
+        Self::new(kind, error)
+    }
+}
+
+impl From<Box<dyn Error>> for Failure {
+    fn from(error: Box<dyn Error>) -> Self {
+        let error = match error.downcast::<io::Error>() {
+            Ok(error) => return (*error).into(),
+            Err(error) => error,
+        };
+        match error.downcast::<csv::Error>() {
+            Ok(error) => {
+                let message = error.to_string();
+                match error.into_kind() {
+                    csv::ErrorKind::Io(error) => error.into(),
+                    _ => Self::new(FailureKind::Parse, message),
+                }
+            }
+            Err(error) => Self::new(FailureKind::Parse, error),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Args, Failure> {
+        Args::parse(args.split_whitespace().map(String::from))
+    }
+
+    #[test]
+    fn parses_commands_and_options() {
+        let args = parse("in.csv --csv-no-header --fees fees.ini other.csv").unwrap();
+        assert_eq!(args.command, Command::Process);
+        assert_eq!(args.positional, ["in.csv", "other.csv"]);
+        assert!(args.flag("--csv-no-header"));
+        assert!(!args.flag("--csv-no-trim"));
+        assert_eq!(args.option("--fees"), Some("fees.ini"));
+        assert_eq!(args.log_level, LogLevel::Info);
+
+        let args = parse("convert a.csv b.txb --log-level warn").unwrap();
+        assert_eq!(args.command, Command::Convert);
+        assert!(args.logs(LogLevel::Warn));
+        assert!(!args.logs(LogLevel::Info));
+        assert_eq!(parse("validate -h").unwrap().command, Command::Help);
+        assert_eq!(parse("").unwrap_err().kind, FailureKind::Usage);
+
+        for invalid in [
+            "in.csv --unknown",
+            "in.csv --fees",
+            "in.csv --fees a --fees b",
+            "in.csv --log-level loud",
+            "convert in.csv",
+            "replay a b",
+        ] {
+            let failure = parse(invalid).unwrap_err();
+            assert_eq!(failure.kind, FailureKind::Usage, "{invalid}");
+            assert_eq!(failure.exit_code(), 2);
+        }
+        let failure = parse("in.csv --max-deposits many")
+            .unwrap()
+            .parsed::<usize>("--max-deposits");
+        assert_eq!(
+            failure.unwrap_err().message,
+            "invalid --max-deposits many: invalid digit found in string"
+        );
+    }
+
+    #[test]
+    fn options_are_documented() {
+        for option in OPTIONS.iter().chain(&FLAGS) {
+            assert!(USAGE.contains(&format!("\n    {option} ")), "{option}");
+        }
+        let documented = USAGE.lines().filter(|line| line.starts_with("    --"));
+        assert_eq!(documented.count(), OPTIONS.len() + FLAGS.len());
+    }
+
+    #[test]
+    fn failures_have_exit_codes_by_kind() {
+        let failure = |error: io::Error| Failure::from(error).exit_code();
+        assert_eq!(failure(io::ErrorKind::NotFound.into()), 4);
+        assert_eq!(failure(io::ErrorKind::InvalidData.into()), 3);
+        assert_eq!(failure(io::ErrorKind::BrokenPipe.into()), 0);
+
+        let error: Box<dyn Error> = "1.2.3".parse::<f64>().unwrap_err().into();
+        assert_eq!(Failure::from(error).exit_code(), 3);
+        let error: Box<dyn Error> = Box::new(io::Error::from(io::ErrorKind::PermissionDenied));
+        let failure = Failure::from(error).context("in.csv");
+        assert_eq!(failure.exit_code(), 4);
+        assert_eq!(failure.message, "in.csv: permission denied");
+    }
+}
@@ -0,0 +1,231 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+};
+
+/// Compression of input and output files
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+impl Compression {
+    /// Compression of file by its extension, `.gz` or `.zst`
+    pub fn of(path: impl AsRef<Path>) -> Self {
+        match path.as_ref().extension().and_then(|e| e.to_str()) {
+            Some("gz") => Self::Gzip,
+            Some("zst") => Self::Zstd,
+            _ => Self::None,
+        }
+    }
+
+    /// Compression of data by its first bytes
+    pub fn detect(bytes: &[u8]) -> Self {
+        if bytes.starts_with(GZIP_MAGIC) {
+            Self::Gzip
+        } else if bytes.starts_with(ZSTD_MAGIC) {
+            Self::Zstd
+        } else {
+            Self::None
+        }
+    }
+}
+
+/// Path without compression extension, to find format of compressed file
+pub fn uncompressed(path: &Path) -> PathBuf {
+    match Compression::of(path) {
+        Compression::None => path.into(),
+        _ => path.with_extension(""),
+    }
+}
+
+/// Open file for reading, decompressing it while it is read if it starts like compressed data
+/// (or, when it is too short to tell, if its extension says so)
+pub fn open(path: impl AsRef<Path>) -> io::Result<Box<dyn BufRead>> {
+    let mut reader = BufReader::new(File::open(&path)?);
+    let start = reader.fill_buf()?;
+    let compression = match Compression::detect(start) {
+        Compression::None if start.len() < ZSTD_MAGIC.len() => Compression::of(&path),
+        compression => compression,
+    };
+    decoder(reader, compression)
+}
+
+#[cfg(feature = "compression")]
+fn decoder(reader: BufReader<File>, compression: Compression) -> io::Result<Box<dyn BufRead>> {
+    Ok(match compression {
+        Compression::None => Box::new(reader),
+        // Appending to compressed file adds another gzip member or zstd frame, all are read
+        Compression::Gzip => Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader))),
+        Compression::Zstd => Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?)),
+    })
+}
+
+#[cfg(not(feature = "compression"))]
+fn decoder(reader: BufReader<File>, compression: Compression) -> io::Result<Box<dyn BufRead>> {
+    match compression {
+        Compression::None => Ok(Box::new(reader)),
+        _ => Err(unsupported(compression)),
+    }
+}
+
+#[cfg(not(feature = "compression"))]
+fn unsupported(compression: Compression) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::Unsupported,
+        format!(
+            "can not use {compression:?} data, transactor is built without compression feature"
+        ),
+    )
+}
+
+/// Writer that compresses written data, compressed data is complete only after `finish`
+/// (or drop, which ignores errors)
+pub struct Writer(Encoder);
+
+enum Encoder {
+    Plain(Box<dyn Write>),
+    #[cfg(feature = "compression")]
+    Gzip(flate2::write::GzEncoder<Box<dyn Write>>),
+    #[cfg(feature = "compression")]
+    Zstd(zstd::Encoder<'static, Box<dyn Write>>),
+}
+
+impl Writer {
+    #[cfg(feature = "compression")]
+    pub fn new(writer: Box<dyn Write>, compression: Compression) -> io::Result<Self> {
+        Ok(Self(match compression {
+            Compression::None => Encoder::Plain(writer),
+            Compression::Gzip => Encoder::Gzip(flate2::write::GzEncoder::new(
+                writer,
+                flate2::Compression::default(),
+            )),
+            Compression::Zstd => Encoder::Zstd(zstd::Encoder::new(writer, 0)?),
+        }))
+    }
+
+    #[cfg(not(feature = "compression"))]
+    pub fn new(writer: Box<dyn Write>, compression: Compression) -> io::Result<Self> {
+        match compression {
+            Compression::None => Ok(Self(Encoder::Plain(writer))),
+            _ => Err(unsupported(compression)),
+        }
+    }
+
+    /// Writer to file, compressed by its extension
+    pub fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let file = File::create(&path)?;
+        Self::new(Box::new(io::BufWriter::new(file)), Compression::of(path))
+    }
+
+    fn writer(&mut self) -> &mut dyn Write {
+        match &mut self.0 {
+            Encoder::Plain(writer) => writer,
+            #[cfg(feature = "compression")]
+            Encoder::Gzip(encoder) => encoder,
+            #[cfg(feature = "compression")]
+            Encoder::Zstd(encoder) => encoder,
+        }
+    }
+
+    /// Complete compressed data and flush it
+    pub fn finish(&mut self) -> io::Result<()> {
+        match &mut self.0 {
+            Encoder::Plain(writer) => writer.flush(),
+            #[cfg(feature = "compression")]
+            Encoder::Gzip(encoder) => {
+                encoder.try_finish()?;
+                encoder.get_mut().flush()
+            }
+            #[cfg(feature = "compression")]
+            Encoder::Zstd(encoder) => {
+                encoder.do_finish()?;
+                encoder.get_mut().flush()
+            }
+        }
+    }
+}
+
+impl Drop for Writer {
+    fn drop(&mut self) {
+        let _ = self.finish();
+    }
+}
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.writer().write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.writer().flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn detects_compression() {
+        assert_eq!(Compression::of("in.csv.gz"), Compression::Gzip);
+        assert_eq!(Compression::of("in.zst"), Compression::Zstd);
+        assert_eq!(Compression::of("in.csv"), Compression::None);
+        assert_eq!(Compression::detect(&[0x1f, 0x8b, 8]), Compression::Gzip);
+        assert_eq!(
+            Compression::detect(&[0x28, 0xb5, 0x2f, 0xfd]),
+            Compression::Zstd
+        );
+        assert_eq!(Compression::detect(b"type"), Compression::None);
+        assert_eq!(
+            uncompressed(Path::new("a/in.jsonl.zst")),
+            Path::new("a/in.jsonl")
+        );
+        assert_eq!(
+            uncompressed(Path::new("a/in.jsonl")),
+            Path::new("a/in.jsonl")
+        );
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn reads_what_is_written() {
+        use std::io::Read;
+
+        for (name, compression) in [("gz", Compression::Gzip), ("zst", Compression::Zstd)] {
+            let path =
+                std::env::temp_dir().join(format!("transactor-{}-data.{name}", std::process::id()));
+            // Concatenated gzip members or zstd frames are read as one
+            let mut bytes = Vec::new();
+            for line in ["first\n", "second\n"] {
+                let mut writer = Writer::create(&path).unwrap();
+                writer.write_all(line.as_bytes()).unwrap();
+                writer.finish().unwrap();
+                bytes.extend(std::fs::read(&path).unwrap());
+            }
+            std::fs::write(&path, &bytes).unwrap();
+
+            assert_eq!(Compression::detect(&bytes), compression);
+            let mut content = String::new();
+            open(&path).unwrap().read_to_string(&mut content).unwrap();
+            assert_eq!(content, "first\nsecond\n", "{name}");
+
+            // Compressed data is recognized without extension
+            let renamed = path.with_extension("data");
+            std::fs::rename(&path, &renamed).unwrap();
+            let mut content = String::new();
+            open(&renamed)
+                .unwrap()
+                .read_to_string(&mut content)
+                .unwrap();
+            assert_eq!(content, "first\nsecond\n", "{name}");
+            std::fs::remove_file(renamed).unwrap();
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::{fs, io, path::Path, str::FromStr};
+
+/// `key = value` line of a config file
+#[derive(Debug, PartialEq)]
+pub struct Entry {
+    pub key: String,
+    pub value: String,
+    pub line: usize,
+}
+
+impl Entry {
+    pub fn parse<T: FromStr>(&self) -> io::Result<T> {
+        self.value
+            .parse()
+            .map_err(|_| invalid(self.line, format!("invalid value of {}", self.key)))
+    }
+}
+
+/// `[name]` line of a config file followed by its entries.
+/// Entries before the first section belong to a section with empty name.
+#[derive(Debug, PartialEq)]
+pub struct Section {
+    pub name: String,
+    pub entries: Vec<Entry>,
+}
+
+pub fn invalid(line: usize, message: impl std::fmt::Display) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidData,
+        format!("line {line}: {message}"),
+    )
+}
+
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<Section>> {
+    parse(&fs::read_to_string(path)?)
+}
+
+/// Parse simple ini-like config.
+///
+/// Lines starting with `#` are comments,
+/// values can be put in double quotes to keep surrounding whitespace.
+pub fn parse(text: &str) -> io::Result<Vec<Section>> {
+    let mut sections = vec![Section {
+        name: String::new(),
+        entries: Vec::new(),
+    }];
+
+    for (index, line) in text.lines().enumerate() {
+        let line_number = index + 1;
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+
+        if let Some(name) = line.strip_prefix('[').and_then(|l| l.strip_suffix(']')) {
+            sections.push(Section {
+                name: name.trim().to_string(),
+                entries: Vec::new(),
+            });
+            continue;
+        }
+
+        let Some((key, value)) = line.split_once('=') else {
+            return Err(invalid(line_number, "expected `key = value`"));
+        };
+        let value = value.trim();
+        let value = value
+            .strip_prefix('"')
+            .and_then(|v| v.strip_suffix('"'))
+            .unwrap_or(value);
+
+        sections
+            .last_mut()
+            .expect("there is always a section")
+            .entries
+            .push(Entry {
+                key: key.trim().to_string(),
+                value: value.to_string(),
+                line: line_number,
+            });
+    }
+
+    if sections[0].entries.is_empty() {
+        sections.remove(0);
+    }
+    Ok(sections)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_sections() {
+        let sections = parse(
+            "
+            # comment
+            a = 1
+            [first]
+            b = two words
+            [ second ]
+            c = \" \"
+            ",
+        )
+        .unwrap();
+
+        let names: Vec<&str> = sections.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["", "first", "second"]);
+        assert_eq!(sections[1].entries[0].key, "b");
+        assert_eq!(sections[1].entries[0].value, "two words");
+        assert_eq!(sections[2].entries[0].value, " ");
+        assert_eq!(sections[2].entries[0].line, 7);
+    }
+
+    #[test]
+    fn rejects_lines_without_value() {
+        let error = parse("[limits]\nwindow").unwrap_err();
+        assert_eq!(error.to_string(), "line 2: expected `key = value`");
+    }
+}
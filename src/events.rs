@@ -0,0 +1,71 @@
+use crate::{
+    account::{Account, ClientId},
+    money::MoneyAmount,
+    transactions::TransactionId,
+};
+use std::sync::mpsc::Sender;
+
+/// Funds of an account at some moment
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Balance {
+    pub available: MoneyAmount,
+    pub held: MoneyAmount,
+}
+
+impl From<&Account> for Balance {
+    fn from(account: &Account) -> Self {
+        Self {
+            available: account.available,
+            held: account.held,
+        }
+    }
+}
+
+/// Change of account state that downstream services may react to
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Event {
+    BalanceChanged {
+        client_id: ClientId,
+        before: Balance,
+        after: Balance,
+    },
+    DisputeOpened {
+        client_id: ClientId,
+        tx_id: TransactionId,
+        amount: MoneyAmount,
+    },
+    DisputeResolved {
+        client_id: ClientId,
+        tx_id: TransactionId,
+        amount: MoneyAmount,
+    },
+    ChargedBack {
+        client_id: ClientId,
+        tx_id: TransactionId,
+        amount: MoneyAmount,
+    },
+    AccountLocked {
+        client_id: ClientId,
+    },
+}
+
+/// Receiver of events of processed transactions.
+///
+/// Events are delivered in order once the transaction is committed to storage.
+pub trait Subscriber {
+    fn notify(&mut self, event: &Event);
+}
+
+/// Synchronous callback
+impl<F: FnMut(&Event)> Subscriber for F {
+    fn notify(&mut self, event: &Event) {
+        self(event)
+    }
+}
+
+/// Channel to another thread, events are dropped once the receiver is gone
+impl Subscriber for Sender<Event> {
+    fn notify(&mut self, event: &Event) {
+        let _ = self.send(*event);
+    }
+}
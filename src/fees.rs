@@ -0,0 +1,218 @@
+use crate::{
+    account::ClientId,
+    config::{self, Section},
+    money::MoneyAmount,
+};
+use std::{io, path::Path, str::FromStr};
+
+/// Fee of one transaction type
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Fee {
+    pub flat: MoneyAmount,
+    pub percent: MoneyAmount,
+    pub min: Option<MoneyAmount>,
+    pub max: Option<MoneyAmount>,
+}
+
+impl Fee {
+    /// Fee for given amount, None if it does not fit into MoneyAmount
+    pub fn of(&self, amount: MoneyAmount) -> Option<MoneyAmount> {
+        let mut fee = self.flat.try_change(amount.percent(self.percent)?)?;
+        if let Some(min) = self.min.filter(|min| fee < *min) {
+            fee = min;
+        }
+        if let Some(max) = self.max.filter(|max| fee > *max) {
+            fee = max;
+        }
+        Some(fee)
+    }
+}
+
+/// What happens to deposit fee when the deposit is charged back
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum FeeReversal {
+    /// House keeps the fee
+    #[default]
+    Keep,
+
+    /// Fee is returned from house account to the client
+    Refund,
+}
+
+impl FromStr for FeeReversal {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "keep" => Ok(FeeReversal::Keep),
+            "refund" => Ok(FeeReversal::Refund),
+            _ => Err(format!("unknown fee reversal {s}")),
+        }
+    }
+}
+
+/// Fee schedule.
+///
+/// Deposit fee is taken from deposited money (and never exceeds it),
+/// withdrawal fee is taken on top of withdrawn amount.
+/// Disputes hold the whole deposited amount, resolving them does not change fees.
+/// As only the deposit without fee was credited, a charged back deposit leaves the client
+/// with the fee owed (negative balance) unless the fee is refunded.
+/// Fee of a deposit is split among its refunded and charged back parts in proportion,
+/// so it is refunded at most once however the deposit is charged back.
+/// Refund takes back only what its part of the deposit credited, the house keeps its fee,
+/// reversal takes back what is left of the deposit and returns its fee from house account.
+#[derive(Debug, Default, PartialEq)]
+pub struct Fees {
+    pub deposit: Option<Fee>,
+    pub withdrawal: Option<Fee>,
+
+    /// Account fees are credited to (required in config with any fee)
+    pub house_account: ClientId,
+
+    pub reversal: FeeReversal,
+}
+
+impl Fees {
+    /// Read fee schedule from config file like
+    ///
+    /// ```text
+    /// house_account = 0
+    /// reversal = refund
+    ///
+    /// [deposit]
+    /// percent = 0.5
+    /// max = 10
+    ///
+    /// [withdrawal]
+    /// flat = 1
+    /// percent = 1.5
+    /// min = 2
+    /// ```
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_config(&config::read(path)?)
+    }
+
+    pub fn from_config(sections: &[Section]) -> io::Result<Self> {
+        let mut fees = Self::default();
+        let mut house_account = None;
+        for section in sections {
+            if section.name.is_empty() {
+                for entry in &section.entries {
+                    match entry.key.as_str() {
+                        "house_account" => house_account = Some(entry.parse()?),
+                        "reversal" => fees.reversal = entry.parse()?,
+                        key => {
+                            return Err(config::invalid(
+                                entry.line,
+                                format!("unknown setting {key}"),
+                            ))
+                        }
+                    }
+                }
+                continue;
+            }
+
+            let fee = match section.name.as_str() {
+                "deposit" => fees.deposit.insert(Fee::default()),
+                "withdrawal" => fees.withdrawal.insert(Fee::default()),
+                name => {
+                    let line = section.entries.first().map_or(0, |e| e.line);
+                    return Err(config::invalid(line, format!("unknown section {name}")));
+                }
+            };
+            for entry in &section.entries {
+                match entry.key.as_str() {
+                    "flat" => fee.flat = entry.parse()?,
+                    "percent" => fee.percent = entry.parse()?,
+                    "min" => fee.min = Some(entry.parse()?),
+                    "max" => fee.max = Some(entry.parse()?),
+                    key => return Err(config::invalid(entry.line, format!("unknown fee {key}"))),
+                }
+            }
+        }
+
+        match house_account {
+            Some(house_account) => fees.house_account = house_account,
+            None if fees.deposit.is_some() || fees.withdrawal.is_some() => {
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "house_account is required to charge fees",
+                ))
+            }
+            None => {}
+        }
+        Ok(fees)
+    }
+
+    /// Fee for depositing given amount
+    pub fn deposit_fee(&self, amount: MoneyAmount) -> Option<MoneyAmount> {
+        match self.deposit {
+            Some(fee) if amount > 0 => fee.of(amount).map(|fee| match fee > amount {
+                true => amount,
+                false => fee,
+            }),
+            _ => Some(MoneyAmount::default()),
+        }
+    }
+
+    /// Fee for withdrawing given amount
+    pub fn withdrawal_fee(&self, amount: MoneyAmount) -> Option<MoneyAmount> {
+        match self.withdrawal {
+            Some(fee) if amount > 0 => fee.of(amount),
+            _ => Some(MoneyAmount::default()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fees(config: &str) -> Fees {
+        Fees::from_config(&config::parse(config).unwrap()).unwrap()
+    }
+
+    fn amount(amount: &str) -> MoneyAmount {
+        amount.parse().unwrap()
+    }
+
+    #[test]
+    fn reads_schedule() {
+        let fees = fees("house_account = 9\nreversal = refund\n[withdrawal]\nflat = 1\nmax = 5");
+
+        assert_eq!(fees.house_account, 9);
+        assert_eq!(fees.reversal, FeeReversal::Refund);
+        assert_eq!(fees.deposit, None);
+        assert_eq!(
+            fees.withdrawal,
+            Some(Fee {
+                flat: 1.into(),
+                max: Some(5.into()),
+                ..Default::default()
+            })
+        );
+        assert!(Fees::from_config(
+            &config::parse("house_account = 0\n[refund]\nflat = 1").unwrap()
+        )
+        .is_err());
+        assert!(Fees::from_config(&config::parse("[deposit]\nfixed = 1").unwrap()).is_err());
+        assert!(Fees::from_config(&config::parse("[deposit]\nflat = 1").unwrap()).is_err());
+        assert!(Fees::from_config(&config::parse("reversal = refund").unwrap()).is_ok());
+    }
+
+    #[test]
+    fn fee_is_capped() {
+        let fees =
+            fees("house_account = 0\n[withdrawal]\nflat = 0.1\npercent = 1\nmin = 0.5\nmax = 2\n[deposit]\nflat = 1");
+
+        assert_eq!(fees.withdrawal_fee(amount("10")), Some(amount("0.5")));
+        assert_eq!(
+            fees.withdrawal_fee(amount("100.05")),
+            Some(amount("1.1005"))
+        );
+        assert_eq!(fees.withdrawal_fee(amount("1000")), Some(amount("2")));
+        assert_eq!(fees.withdrawal_fee(amount("-10")), Some(amount("0")));
+        assert_eq!(fees.deposit_fee(amount("0.25")), Some(amount("0.25")));
+    }
+}
@@ -0,0 +1,1054 @@
+use crate::{
+    account::ClientId,
+    binary::{Decoder, Encoder},
+    compression::{self, Writer},
+    config::{self, Section},
+    money::MoneyAmount,
+    transactions::{
+        chargeback, deposit, dispute, refund, resolve, reversal, withdraw, Timestamp, Transaction,
+        TransactionId,
+    },
+};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fs,
+    io::{self, BufRead, Read, Write},
+    iter::Peekable,
+    path::{Path, PathBuf},
+    str::{Chars, FromStr},
+};
+
+/// Format of input file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// `type, client, tx, amount, timestamp, id` rows with a header line
+    Csv,
+
+    /// One JSON object per line, like `{"type":"deposit","client":1,"tx":1,"amount":"1.0"}`
+    JsonLines,
+
+    /// Length-prefixed binary records, see `binary::Encoder`
+    Binary,
+}
+
+impl Format {
+    /// Format by file extension, CSV unless it is `.jsonl`, `.ndjson` or `.txb`
+    /// (with optional `.gz` or `.zst` extension after it)
+    pub fn of(path: impl AsRef<Path>) -> Self {
+        let path = compression::uncompressed(path.as_ref());
+        match path.extension().and_then(|e| e.to_str()) {
+            Some("jsonl" | "ndjson") => Format::JsonLines,
+            Some("txb") => Format::Binary,
+            _ => Format::Csv,
+        }
+    }
+}
+
+impl FromStr for Format {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "jsonl" => Ok(Format::JsonLines),
+            "binary" => Ok(Format::Binary),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown input format {s}"),
+            )),
+        }
+    }
+}
+
+/// Order in which input files are processed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Order {
+    /// Order of arguments, files of a directory or glob pattern by name
+    Args,
+
+    /// By path
+    Name,
+
+    /// By modification time, oldest first
+    Modified,
+}
+
+impl FromStr for Order {
+    type Err = io::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "args" => Ok(Order::Args),
+            "name" => Ok(Order::Name),
+            "mtime" => Ok(Order::Modified),
+            _ => Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("unknown input order {s}"),
+            )),
+        }
+    }
+}
+
+/// Input files of given paths, where a directory stands for files in it and
+/// a file name with `*` or `?` for matching files in its directory.
+/// Other paths are kept as they are, even if they do not exist.
+/// Directory or pattern without any files is an error, as it is most likely a mistake.
+pub fn input_files(paths: &[&str], order: Order) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for path in paths.iter().map(Path::new) {
+        let name = path.file_name().and_then(|name| name.to_str());
+        let mut expanded: Vec<PathBuf> = if path.is_dir() {
+            files_in(path, |_| true)?
+        } else if let Some(pattern) = name.filter(|name| name.contains(['*', '?'])) {
+            let dir = path.parent().filter(|dir| !dir.as_os_str().is_empty());
+            files_in(dir.unwrap_or(Path::new(".")), |name| {
+                matches(pattern.as_bytes(), name.as_bytes())
+            })?
+        } else {
+            vec![path.into()]
+        };
+        if expanded.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::NotFound,
+                format!("no input files match {}", path.display()),
+            ));
+        }
+        expanded.sort();
+        files.append(&mut expanded);
+    }
+
+    match order {
+        Order::Args => {}
+        Order::Name => files.sort(),
+        Order::Modified => files.sort_by_cached_key(|path| {
+            let modified = fs::metadata(path).and_then(|m| m.modified()).ok();
+            (modified, path.clone())
+        }),
+    }
+    Ok(files)
+}
+
+/// Files in directory with names matching the filter
+fn files_in(dir: &Path, filter: impl Fn(&str) -> bool) -> io::Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let entry = entry?;
+        let name = entry.file_name();
+        if entry.file_type()?.is_file() && name.to_str().is_some_and(&filter) {
+            files.push(entry.path());
+        }
+    }
+    Ok(files)
+}
+
+/// Whether name matches glob pattern with `*` for any characters and `?` for one character
+fn matches(pattern: &[u8], name: &[u8]) -> bool {
+    match (pattern.split_first(), name.split_first()) {
+        (None, _) => name.is_empty(),
+        (Some((b'*', rest)), _) => {
+            matches(rest, name) || !name.is_empty() && matches(pattern, &name[1..])
+        }
+        (Some((b'?', rest)), Some((_, name))) => matches(rest, name),
+        (Some((p, rest)), Some((n, name))) => p == n && matches(rest, name),
+        (Some(_), None) => false,
+    }
+}
+
+/// Columns of CSV file in order they are written
+const COLUMNS: [&str; 6] = ["type", "client", "tx", "amount", "timestamp", "id"];
+
+/// Columns that CSV file must have
+const REQUIRED_COLUMNS: usize = 4;
+
+/// How CSV files are read and written
+#[derive(Debug, Default, PartialEq)]
+pub struct CsvOptions {
+    /// Other names of columns, by column
+    pub aliases: HashMap<&'static str, Vec<String>>,
+
+    pub dialect: Dialect,
+}
+
+/// Syntax of CSV files, also used for reports
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Dialect {
+    pub delimiter: u8,
+    pub quote: u8,
+    pub decimal_separator: u8,
+
+    /// Whether the first line names columns, otherwise they are in order of `COLUMNS`
+    pub has_header: bool,
+
+    /// Lines starting with it are skipped
+    pub comment: Option<u8>,
+
+    /// Whether whitespace around fields is ignored (names in header are always trimmed)
+    pub trim: bool,
+}
+
+impl Default for Dialect {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quote: b'"',
+            decimal_separator: b'.',
+            has_header: true,
+            comment: None,
+            trim: true,
+        }
+    }
+}
+
+/// Single ASCII character of CSV syntax, `tab` or `\t` for tab
+pub fn parse_char(value: &str) -> io::Result<u8> {
+    match value {
+        "tab" | "\\t" => Ok(b'\t'),
+        _ if value.len() == 1 && value.is_ascii() => Ok(value.as_bytes()[0]),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            format!("expected single character, got {value}"),
+        )),
+    }
+}
+
+impl Dialect {
+    fn read_section(&mut self, section: &Section) -> io::Result<()> {
+        let mut separators_line = 0;
+        for entry in &section.entries {
+            let char = || parse_char(&entry.value).map_err(|e| config::invalid(entry.line, e));
+            match entry.key.as_str() {
+                "delimiter" => self.delimiter = char()?,
+                "quote" => self.quote = char()?,
+                "decimal_separator" => self.decimal_separator = char()?,
+                "header" => self.has_header = entry.parse()?,
+                "comment" => self.comment = Some(char()?),
+                "trim" => self.trim = entry.parse()?,
+                key => {
+                    return Err(config::invalid(
+                        entry.line,
+                        format!("unknown setting {key}"),
+                    ))
+                }
+            }
+            if matches!(entry.key.as_str(), "delimiter" | "decimal_separator") {
+                separators_line = entry.line;
+            }
+        }
+        self.check()
+            .map_err(|error| config::invalid(separators_line, error))
+    }
+
+    /// Check that fields and decimals can be told apart
+    pub fn check(&self) -> io::Result<()> {
+        if self.decimal_separator == self.delimiter {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "decimal separator can not be the same as delimiter",
+            ));
+        }
+        Ok(())
+    }
+
+    fn reader(&self) -> csv::ReaderBuilder {
+        let mut builder = csv::ReaderBuilder::new();
+        builder
+            .flexible(true)
+            .delimiter(self.delimiter)
+            .quote(self.quote)
+            .has_headers(self.has_header)
+            .comment(self.comment);
+        builder
+    }
+
+    /// Amount written with decimal separator of the dialect
+    pub fn amount(&self, amount: MoneyAmount) -> String {
+        let amount = amount.to_string();
+        match self.decimal_separator {
+            b'.' => amount,
+            separator => amount.replace('.', &char::from(separator).to_string()),
+        }
+    }
+
+    /// Amount written with decimal separator of the dialect, see [`parse_amount`]
+    fn parse_amount(&self, field: Option<&str>) -> Option<MoneyAmount> {
+        let field = field?;
+        let field = match self.decimal_separator {
+            b'.' => field.to_string(),
+            _ if field.contains('.') => return None,
+            separator => field.replace(char::from(separator), "."),
+        };
+        parse_amount(Some(&field))
+    }
+
+    /// Fields separated by delimiter, quoted if needed
+    pub fn row<S: AsRef<str>>(&self, fields: &[S]) -> String {
+        self.join(fields, "")
+    }
+
+    /// Fields separated by delimiter and a space, for reports read by people
+    pub fn padded_row<S: AsRef<str>>(&self, fields: &[S]) -> String {
+        self.join(fields, " ")
+    }
+
+    fn join<S: AsRef<str>>(&self, fields: &[S], padding: &str) -> String {
+        let (delimiter, quote) = (char::from(self.delimiter), char::from(self.quote));
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|field| {
+                let field = field.as_ref();
+                match field.contains([delimiter, quote, '\n', '\r']) {
+                    true => {
+                        let escaped = field.replace(quote, &format!("{quote}{quote}"));
+                        format!("{quote}{escaped}{quote}")
+                    }
+                    false => field.to_string(),
+                }
+            })
+            .collect();
+        fields.join(&format!("{delimiter}{padding}"))
+    }
+}
+
+impl CsvOptions {
+    /// Read options from config file like
+    ///
+    /// ```text
+    /// [columns]
+    /// type = kind
+    /// client = client_id customer
+    ///
+    /// [dialect]
+    /// delimiter = ;
+    /// quote = '
+    /// decimal_separator = ,
+    /// header = false
+    /// comment = #
+    /// trim = false
+    /// ```
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_config(&config::read(path)?)
+    }
+
+    pub fn from_config(sections: &[Section]) -> io::Result<Self> {
+        let mut options = Self::default();
+        for section in sections {
+            if section.name == "dialect" {
+                options.dialect.read_section(section)?;
+                continue;
+            }
+            if section.name != "columns" {
+                let line = section.entries.first().map_or(0, |e| e.line);
+                let name = &section.name;
+                return Err(config::invalid(line, format!("unknown section {name}")));
+            }
+            for entry in &section.entries {
+                let Some(column) = COLUMNS.into_iter().find(|column| *column == entry.key) else {
+                    return Err(config::invalid(
+                        entry.line,
+                        format!("unknown column {}", entry.key),
+                    ));
+                };
+                options
+                    .aliases
+                    .entry(column)
+                    .or_default()
+                    .extend(entry.value.split_whitespace().map(String::from));
+            }
+        }
+        Ok(options)
+    }
+
+    /// Index of each of `COLUMNS` in given header, other columns are ignored
+    fn columns(&self, header: &csv::StringRecord) -> io::Result<[Option<usize>; COLUMNS.len()]> {
+        if !self.dialect.has_header {
+            return Ok(std::array::from_fn(Some));
+        }
+        let mut indices = [None; COLUMNS.len()];
+        for (index, name) in header.iter().map(str::trim).enumerate() {
+            let column = COLUMNS.iter().position(|column| {
+                *column == name
+                    || self
+                        .aliases
+                        .get(column)
+                        .is_some_and(|aliases| aliases.iter().any(|alias| alias == name))
+            });
+            if let Some(column) = column {
+                indices[column].get_or_insert(index);
+            }
+        }
+
+        let missing: Vec<&str> = COLUMNS[..REQUIRED_COLUMNS]
+            .iter()
+            .zip(indices)
+            .filter(|(_, index)| index.is_none())
+            .map(|(column, _)| *column)
+            .collect();
+        match missing.is_empty() {
+            true => Ok(indices),
+            false => Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("missing required columns: {}", missing.join(", ")),
+            )),
+        }
+    }
+}
+
+/// Fields of one input row, `None` if missing or invalid
+#[derive(Debug, Default)]
+pub struct Row<'a> {
+    pub kind: &'a str,
+    pub client: Option<ClientId>,
+    pub tx: Option<TransactionId>,
+    pub amount: Option<MoneyAmount>,
+    pub timestamp: Option<Timestamp>,
+    pub id: Option<TransactionId>,
+}
+
+impl Row<'_> {
+    /// Transaction described by the row, or why it is not valid
+    pub fn transaction(&self) -> Result<Transaction, String> {
+        let invalid = |field: &str| format!("missing or invalid {field}");
+        let client_id = self.client.ok_or_else(|| invalid("client"))?;
+        let tx_id = self.tx.ok_or_else(|| invalid("tx"))?;
+        let amount = || self.amount.ok_or_else(|| invalid("amount"));
+        let partial = |tx: Transaction| match self.amount {
+            Some(amount) => tx.partial(amount),
+            None => tx,
+        };
+        let tracked = |tx: Transaction| match self.id {
+            Some(id) => tx.with_id(id),
+            None => tx,
+        };
+
+        let transaction = match self.kind {
+            "deposit" => deposit(client_id, tx_id, amount()?),
+            "withdrawal" => withdraw(client_id, tx_id, amount()?),
+            "dispute" => tracked(partial(dispute(client_id, tx_id))),
+            "resolve" => tracked(partial(resolve(client_id, tx_id))),
+            "chargeback" => tracked(partial(chargeback(client_id, tx_id))),
+            "refund" => tracked(partial(refund(client_id, tx_id))),
+            "reversal" => tracked(reversal(client_id, tx_id)),
+            "" => return Err(invalid("type")),
+            kind => return Err(format!("unknown type {kind}")),
+        };
+
+        Ok(match self.timestamp {
+            Some(timestamp) => transaction.at(timestamp),
+            None => transaction,
+        })
+    }
+}
+
+/// Line of input file that is not a valid transaction
+#[derive(Debug, PartialEq)]
+pub struct Skipped {
+    pub line: u64,
+    pub reason: String,
+}
+
+/// Read transactions from file, passing invalid lines to `skipped`
+/// (binary file fails instead, as it is not written by hand)
+pub fn read(
+    path: impl AsRef<Path>,
+    format: Format,
+    csv_options: &CsvOptions,
+    skipped: impl FnMut(Skipped),
+) -> Result<Vec<Transaction>, Box<dyn Error>> {
+    match format {
+        Format::Csv => read_csv(path, csv_options, skipped),
+        Format::JsonLines => read_json_lines(path, skipped),
+        Format::Binary => {
+            let mut bytes = Vec::new();
+            compression::open(path)?.read_to_end(&mut bytes)?;
+            Ok(Decoder::new(&bytes)?.collect::<io::Result<_>>()?)
+        }
+    }
+}
+
+/// Write transactions to file (compressed by its extension) so that reading it gives the same
+/// transactions
+pub fn write(
+    path: impl AsRef<Path>,
+    format: Format,
+    csv_options: &CsvOptions,
+    transactions: &[Transaction],
+) -> io::Result<()> {
+    let mut writer = Writer::create(path)?;
+    if format == Format::Binary {
+        let mut encoder = Encoder::new(writer)?;
+        for tx in transactions {
+            encoder.write(tx)?;
+        }
+        return encoder.finish()?.finish();
+    }
+
+    let dialect = csv_options.dialect;
+    if format == Format::Csv && dialect.has_header {
+        writeln!(writer, "{}", dialect.row(&COLUMNS))?;
+    }
+    for tx in transactions {
+        let amount = tx.detail.amount();
+        let timestamp = tx.timestamp.map(|t| t.to_string());
+        let id = tx.referencing_id().map(|id| id.to_string());
+        match format {
+            Format::Csv => writeln!(
+                writer,
+                "{}",
+                dialect.row(&[
+                    tx.detail.name().to_string(),
+                    tx.client_id.to_string(),
+                    tx.input_tx_id().to_string(),
+                    amount.map(|a| dialect.amount(a)).unwrap_or_default(),
+                    timestamp.unwrap_or_default(),
+                    id.unwrap_or_default(),
+                ])
+            )?,
+            _ => {
+                let optional = [
+                    ("amount", amount.map(|a| format!("\"{a}\""))),
+                    ("timestamp", timestamp),
+                    ("id", id),
+                ]
+                .into_iter()
+                .filter_map(|(key, value)| Some(format!(",\"{key}\":{}", value?)))
+                .collect::<String>();
+                writeln!(
+                    writer,
+                    "{{\"type\":\"{}\",\"client\":{},\"tx\":{}{optional}}}",
+                    tx.detail.name(),
+                    tx.client_id,
+                    tx.input_tx_id()
+                )?
+            }
+        }
+    }
+    writer.finish()
+}
+
+/// Columns are found by their names in the header, missing required ones fail before any row is read
+fn read_csv(
+    path: impl AsRef<Path>,
+    options: &CsvOptions,
+    mut skipped: impl FnMut(Skipped),
+) -> Result<Vec<Transaction>, Box<dyn Error>> {
+    let dialect = options.dialect;
+    let mut rdr = dialect.reader().from_reader(compression::open(path)?);
+    let columns = options.columns(rdr.headers()?)?;
+    let mut transactions = Vec::new();
+
+    for result in rdr.records() {
+        let record = match result {
+            Ok(record) => record,
+            Err(error) => {
+                skipped(Skipped {
+                    line: error.position().map_or(0, |position| position.line()),
+                    reason: error.to_string(),
+                });
+                continue;
+            }
+        };
+        let field = |column: usize| {
+            let field = record.get(columns[column]?)?;
+            Some(if dialect.trim { field.trim() } else { field })
+        };
+        let row = Row {
+            kind: field(0).unwrap_or_default(),
+            client: parse(field(1)),
+            tx: parse(field(2)),
+            amount: dialect.parse_amount(field(3)),
+            timestamp: parse(field(4)),
+            id: parse(field(5)),
+        };
+        match row.transaction() {
+            Ok(transaction) => transactions.push(transaction),
+            Err(reason) => skipped(Skipped {
+                line: record.position().map_or(0, |position| position.line()),
+                reason,
+            }),
+        }
+    }
+    Ok(transactions)
+}
+
+/// Amounts are read like CSV ones, numbers can be written as JSON strings or numbers
+fn read_json_lines(
+    path: impl AsRef<Path>,
+    mut skipped: impl FnMut(Skipped),
+) -> Result<Vec<Transaction>, Box<dyn Error>> {
+    let reader = compression::open(path)?;
+    let mut transactions = Vec::new();
+
+    for (line, text) in (1..).zip(reader.lines()) {
+        let text = text?;
+        if text.trim().is_empty() {
+            continue;
+        }
+        let Some(fields) = json_fields(&text) else {
+            skipped(Skipped {
+                line,
+                reason: "not a flat JSON object".into(),
+            });
+            continue;
+        };
+        let field = |key: &str| fields.get(key).map(String::as_str);
+        let row = Row {
+            kind: field("type").unwrap_or_default(),
+            client: parse(field("client")),
+            tx: parse(field("tx")),
+            amount: parse_amount(field("amount")),
+            timestamp: parse(field("timestamp")),
+            id: parse(field("id")),
+        };
+        match row.transaction() {
+            Ok(transaction) => transactions.push(transaction),
+            Err(reason) => skipped(Skipped { line, reason }),
+        }
+    }
+    Ok(transactions)
+}
+
+fn parse<T: FromStr>(field: Option<&str>) -> Option<T> {
+    field?.parse().ok()
+}
+
+/// Amount read exactly, or rounded to 4 decimals if it has more
+fn parse_amount(field: Option<&str>) -> Option<MoneyAmount> {
+    parse(field).or_else(|| {
+        parse::<f64>(field)
+            .filter(|amount| amount.is_finite())
+            .map(MoneyAmount::from)
+    })
+}
+
+/// Fields of flat JSON object, with string values unescaped and other values as they are written.
+/// Null fields are left out, `None` if line is not such object.
+fn json_fields(line: &str) -> Option<HashMap<String, String>> {
+    let mut chars = line.trim().chars().peekable();
+    let mut fields = HashMap::new();
+    expect(&mut chars, '{')?;
+
+    if skip_whitespace(&mut chars) == Some('}') {
+        chars.next();
+    } else {
+        loop {
+            skip_whitespace(&mut chars);
+            let key = json_string(&mut chars)?;
+            skip_whitespace(&mut chars);
+            expect(&mut chars, ':')?;
+
+            let value = match skip_whitespace(&mut chars)? {
+                '"' => Some(json_string(&mut chars)?),
+                _ => {
+                    let mut literal = String::new();
+                    while let Some(c) =
+                        chars.next_if(|c| !matches!(c, ',' | '}') && !c.is_whitespace())
+                    {
+                        literal.push(c);
+                    }
+                    match literal.as_str() {
+                        "" => return None,
+                        "null" => None,
+                        _ => Some(literal),
+                    }
+                }
+            };
+            if let Some(value) = value {
+                fields.insert(key, value);
+            }
+
+            skip_whitespace(&mut chars);
+            match chars.next()? {
+                ',' => continue,
+                '}' => break,
+                _ => return None,
+            }
+        }
+    }
+
+    chars.next().is_none().then_some(fields)
+}
+
+fn json_string(chars: &mut Peekable<Chars>) -> Option<String> {
+    expect(chars, '"')?;
+    let mut value = String::new();
+    loop {
+        match chars.next()? {
+            '"' => return Some(value),
+            '\\' => value.push(match chars.next()? {
+                'n' => '\n',
+                't' => '\t',
+                'r' => '\r',
+                'b' => '\u{8}',
+                'f' => '\u{c}',
+                'u' => {
+                    // characters outside of basic plane are escaped as surrogate pair
+                    let first = json_code_unit(chars)?;
+                    let second = match first {
+                        0xD800..=0xDBFF => {
+                            expect(chars, '\\')?;
+                            expect(chars, 'u')?;
+                            Some(json_code_unit(chars)?)
+                        }
+                        _ => None,
+                    };
+                    char::decode_utf16(std::iter::once(first).chain(second))
+                        .next()?
+                        .ok()?
+                }
+                c @ ('"' | '\\' | '/') => c,
+                _ => return None,
+            }),
+            c => value.push(c),
+        }
+    }
+}
+
+/// UTF-16 code unit of `\u` escape, written as 4 hex digits
+fn json_code_unit(chars: &mut Peekable<Chars>) -> Option<u16> {
+    let hex: String = chars.by_ref().take(4).collect();
+    if hex.len() != 4 || !hex.chars().all(|c| c.is_ascii_hexdigit()) {
+        return None;
+    }
+    u16::from_str_radix(&hex, 16).ok()
+}
+
+fn expect(chars: &mut Peekable<Chars>, expected: char) -> Option<()> {
+    chars.next_if_eq(&expected).map(drop)
+}
+
+/// Skip whitespace and peek at the next character
+fn skip_whitespace(chars: &mut Peekable<Chars>) -> Option<char> {
+    while chars.next_if(|c| c.is_whitespace()).is_some() {}
+    chars.peek().copied()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_str(name: &str, content: &str) -> Vec<Transaction> {
+        read_skipping(name, content).0
+    }
+
+    /// Transactions and skipped lines of file with given content
+    fn read_skipping(name: &str, content: &str) -> (Vec<Transaction>, Vec<Skipped>) {
+        let path = std::env::temp_dir().join(format!("transactor-{}-{name}", std::process::id()));
+        std::fs::write(&path, content).unwrap();
+        let mut skipped = Vec::new();
+        let transactions = read(&path, Format::of(&path), &CsvOptions::default(), |line| {
+            skipped.push(line)
+        })
+        .unwrap();
+        std::fs::remove_file(path).unwrap();
+        (transactions, skipped)
+    }
+
+    fn debug(transactions: &[Transaction]) -> Vec<String> {
+        transactions.iter().map(|tx| format!("{tx:?}")).collect()
+    }
+
+    #[test]
+    fn detects_format_by_extension() {
+        assert_eq!(Format::of("in.jsonl"), Format::JsonLines);
+        assert_eq!(Format::of("in.ndjson"), Format::JsonLines);
+        assert_eq!(Format::of("in.csv"), Format::Csv);
+        assert_eq!("jsonl".parse::<Format>().unwrap(), Format::JsonLines);
+        assert!("json".parse::<Format>().is_err());
+    }
+
+    #[test]
+    fn parses_flat_json_objects() {
+        let fields = json_fields(r#" { "a" : "x\"A" ,"b":12.5, "c":null,"d":true } "#).unwrap();
+        assert_eq!(
+            fields,
+            [
+                ("a".to_string(), "x\"A".to_string()),
+                ("b".to_string(), "12.5".to_string()),
+                ("d".to_string(), "true".to_string()),
+            ]
+            .into()
+        );
+        assert_eq!(json_fields("{}"), Some(HashMap::new()));
+        assert_eq!(
+            json_fields(r#"{"a":"\u00e9\uD83D\uDE00"}"#).unwrap()["a"],
+            "\u{e9}\u{1f600}"
+        );
+
+        for invalid in [
+            "",
+            "{",
+            r#"{"a":}"#,
+            r#"{"a":1,}"#,
+            r#"{"a":1} x"#,
+            r#"{"a":[1,2]}"#,
+            r#"{a:1}"#,
+            r#"{"a":"\uD83D"}"#,
+            r#"{"a":"\uDE00\uD83D"}"#,
+            r#"{"a":"\u12"}"#,
+        ] {
+            assert_eq!(json_fields(invalid), None, "{invalid}");
+        }
+    }
+
+    #[test]
+    fn reads_json_lines() {
+        let (transactions, skipped) = read_skipping(
+            "lines.jsonl",
+            r#"{"type":"deposit","client":1,"tx":1,"amount":"0.1234"}
+{"type":"withdrawal","client":"2","tx":2,"amount":1.5,"timestamp":10}
+
+{"type":"dispute","client":1,"tx":1,"amount":null,"id":3}
+{"type":"deposit","client":1,"tx":4}
+{"type":"deposit","client":1,"tx":5,"amount":"0.12346"}
+not json
+{"type":"resolve","client":1,"tx":1,"amount":"0.1"}
+"#,
+        );
+
+        assert_eq!(
+            debug(&transactions),
+            debug(&[
+                deposit(1, 1, "0.1234".parse::<MoneyAmount>().unwrap()),
+                withdraw(2, 2, 1.5).at(10),
+                dispute(1, 1).with_id(3),
+                deposit(1, 5, "0.1235".parse::<MoneyAmount>().unwrap()),
+                resolve(1, 1).partial(0.1),
+            ])
+        );
+        assert_eq!(
+            skipped,
+            [
+                (5, "missing or invalid amount"),
+                (7, "not a flat JSON object"),
+            ]
+            .map(|(line, reason)| Skipped {
+                line,
+                reason: reason.into()
+            })
+        );
+    }
+
+    #[test]
+    fn rounds_json_amounts_like_csv_ones() {
+        let (json, skipped) = read_skipping(
+            "rounded.jsonl",
+            r#"{"type":"deposit","client":1,"tx":1,"amount":"1.23456"}
+{"type":"withdrawal","client":1,"tx":2,"amount":7.00009}
+"#,
+        );
+        assert_eq!(skipped, []);
+        let (csv, _) = read_skipping(
+            "rounded.csv",
+            "type,client,tx,amount\ndeposit,1,1,1.23456\nwithdrawal,1,2,7.00009\n",
+        );
+
+        let expected = [
+            deposit(1, 1, "1.2346".parse::<MoneyAmount>().unwrap()),
+            withdraw(1, 2, "7.0001".parse::<MoneyAmount>().unwrap()),
+        ];
+        assert_eq!(debug(&json), debug(&expected));
+        assert_eq!(debug(&csv), debug(&expected));
+    }
+
+    #[test]
+    fn reports_skipped_csv_rows() {
+        let (transactions, skipped) = read_skipping(
+            "skipped.csv",
+            "type,client,tx,amount\ndeposit,1,1,1.0\ntransfer,1,2,1.0\ndeposit,x,3,1.0\n",
+        );
+
+        assert_eq!(debug(&transactions), debug(&[deposit(1, 1, 1.0)]));
+        assert_eq!(
+            skipped,
+            [
+                (3, "unknown type transfer"),
+                (4, "missing or invalid client"),
+            ]
+            .map(|(line, reason)| Skipped {
+                line,
+                reason: reason.into()
+            })
+        );
+    }
+
+    #[test]
+    fn csv_and_json_lines_give_same_transactions() {
+        let csv = read_str(
+            "same.csv",
+            "type,client,tx,amount,timestamp,id\n\
+            deposit, 1, 1, 2.5\n\
+            refund, 1, 1, 0.5, 20, 7\n\
+            reversal, 1, 1,\n\
+            deposit, 1, 2,\n",
+        );
+        let jsonl = read_str(
+            "same.jsonl",
+            "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"2.5\"}\n\
+            {\"type\":\"refund\",\"client\":1,\"tx\":1,\"amount\":\"0.5\",\"timestamp\":20,\"id\":7}\n\
+            {\"type\":\"reversal\",\"client\":1,\"tx\":1}\n\
+            {\"type\":\"deposit\",\"client\":1,\"tx\":2}\n",
+        );
+
+        assert_eq!(csv.len(), 3);
+        assert_eq!(debug(&csv), debug(&jsonl));
+    }
+
+    #[test]
+    fn converts_between_formats_losslessly() {
+        let transactions = [
+            deposit(1, 1, "922337203685477.5807".parse::<MoneyAmount>().unwrap()).at(5),
+            withdraw(2, 2, 0.0001),
+            dispute(1, 1).partial(0.5).with_id(3),
+            chargeback(1, 1),
+            refund(2, 4).at(9),
+            reversal(2, 2).with_id(6),
+        ];
+        let path = std::env::temp_dir().join(format!("transactor-{}-convert", std::process::id()));
+
+        for format in [Format::Csv, Format::JsonLines, Format::Binary] {
+            write(&path, format, &CsvOptions::default(), &transactions).unwrap();
+            let read = read(&path, format, &CsvOptions::default(), drop).unwrap();
+            assert_eq!(debug(&read), debug(&transactions), "{format:?}");
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn maps_csv_columns_by_name() {
+        let options = CsvOptions::from_config(
+            &config::parse("[columns]\nclient = client_id customer\ntype = kind").unwrap(),
+        )
+        .unwrap();
+        let path =
+            std::env::temp_dir().join(format!("transactor-{}-columns.csv", std::process::id()));
+        std::fs::write(
+            &path,
+            " amount , note, customer,tx, kind\n2.5, first, 1, 1, deposit\n,, 1, 1, dispute\n",
+        )
+        .unwrap();
+
+        let transactions = read(&path, Format::Csv, &options, drop).unwrap();
+        assert_eq!(
+            debug(&transactions),
+            debug(&[deposit(1, 1, 2.5), dispute(1, 1)])
+        );
+
+        std::fs::write(&path, "type, client, amount\ndeposit, 1, 2.5\n").unwrap();
+        let error = read(&path, Format::Csv, &options, drop).unwrap_err();
+        assert_eq!(error.to_string(), "missing required columns: tx");
+        std::fs::remove_file(path).unwrap();
+
+        for invalid in ["[columns]\nclient_id = client", "[aliases]\nclient = id"] {
+            assert!(CsvOptions::from_config(&config::parse(invalid).unwrap()).is_err());
+        }
+    }
+
+    #[test]
+    fn reads_csv_dialects() {
+        let fixtures = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/dialects");
+        let expected = [deposit(1, 1, 1.5), withdraw(1, 2, 0.25), dispute(1, 1)];
+        for name in ["semicolon", "quoted", "headerless", "comments", "untrimmed"] {
+            let options = CsvOptions::read(fixtures.join(format!("{name}.ini"))).unwrap();
+            let path = fixtures.join(format!("{name}.csv"));
+            let transactions = read(path, Format::Csv, &options, drop).unwrap();
+            assert_eq!(debug(&transactions), debug(&expected), "{name}");
+        }
+
+        // Amounts with the other separator are ambiguous
+        let options = CsvOptions::read(fixtures.join("semicolon.ini")).unwrap();
+        assert_eq!(options.dialect.parse_amount(Some("1.5")), None);
+
+        for invalid in [
+            "[dialect]\ndelimiter = ;;",
+            "[dialect]\nheader = no",
+            "[dialect]\ndecimal_separator = ,",
+        ] {
+            assert!(CsvOptions::from_config(&config::parse(invalid).unwrap()).is_err());
+        }
+    }
+
+    #[test]
+    fn writes_csv_dialects() {
+        let dialect = Dialect {
+            delimiter: b';',
+            decimal_separator: b',',
+            has_header: false,
+            ..Default::default()
+        };
+        assert_eq!(
+            dialect.row(&["a;b", "say \"hi\"", "c"]),
+            "\"a;b\";\"say \"\"hi\"\"\";c"
+        );
+        assert_eq!(dialect.padded_row(&["a", "b"]), "a; b");
+        assert_eq!(dialect.amount(MoneyAmount::from(-1.25)), "-1,2500");
+
+        let transactions = [deposit(1, 1, 1.5).at(3), dispute(1, 1).with_id(2)];
+        let options = CsvOptions {
+            dialect,
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join(format!("transactor-{}-dialect", std::process::id()));
+        write(&path, Format::Csv, &options, &transactions).unwrap();
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "deposit;1;1;1,5000;3;\ndispute;1;1;;;2\n"
+        );
+        let read = read(&path, Format::Csv, &options, drop).unwrap();
+        assert_eq!(debug(&read), debug(&transactions));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(feature = "compression")]
+    #[test]
+    fn reads_compressed_files() {
+        let transactions = [deposit(1, 1, 1.5).at(3), dispute(1, 1).with_id(2)];
+        for name in ["in.csv.gz", "in.jsonl.zst", "in.txb.gz"] {
+            let path =
+                std::env::temp_dir().join(format!("transactor-{}-{name}", std::process::id()));
+            write(
+                &path,
+                Format::of(&path),
+                &CsvOptions::default(),
+                &transactions,
+            )
+            .unwrap();
+            let read = read(&path, Format::of(&path), &CsvOptions::default(), drop).unwrap();
+            assert_eq!(debug(&read), debug(&transactions), "{name}");
+            std::fs::remove_file(path).unwrap();
+        }
+    }
+
+    #[test]
+    fn finds_input_files() {
+        let dir = std::env::temp_dir().join(format!("transactor-{}-inputs", std::process::id()));
+        fs::create_dir_all(dir.join("nested")).unwrap();
+        for name in ["b.csv", "a.csv", "c.jsonl"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let path = |name: &str| dir.join(name).to_str().unwrap().to_string();
+
+        let files = input_files(
+            &[&path("c.jsonl"), &path("*.csv"), &path("x.csv")],
+            Order::Args,
+        );
+        assert_eq!(
+            files.unwrap(),
+            ["c.jsonl", "a.csv", "b.csv", "x.csv"].map(|name| dir.join(name))
+        );
+        let files = input_files(&[&path("c.jsonl"), &path("")], Order::Name);
+        assert_eq!(
+            files.unwrap(),
+            ["a.csv", "b.csv", "c.jsonl", "c.jsonl"].map(|name| dir.join(name))
+        );
+        let error = input_files(&[&path("c.jsonl"), &path("day-*.csv")], Order::Args);
+        assert_eq!(error.unwrap_err().kind(), io::ErrorKind::NotFound);
+        let error = input_files(&[&path("nested")], Order::Args);
+        assert_eq!(error.unwrap_err().kind(), io::ErrorKind::NotFound);
+
+        assert!(matches(b"day-??.csv*", b"day-01.csv.gz"));
+        assert!(!matches(b"day-??.csv*", b"day-1.csv"));
+        assert!(matches(b"*", b""));
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,436 @@
+use crate::{
+    account::{Account, ClientId},
+    account_store::AccountStore,
+    compression::{self, Compression},
+    money::MoneyAmount,
+    processor::Processor,
+    transactions::{
+        chargeback, deposit, dispute, refund, resolve, reversal, withdraw, Transaction,
+        TransactionId,
+    },
+};
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fs::File,
+    io::{self, BufWriter, Read, Seek, SeekFrom, Write},
+    path::Path,
+    str::FromStr,
+};
+
+/// Position of transaction in the journal
+pub type Seq = u64;
+
+fn invalid_data(message: impl Into<String>) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.into())
+}
+
+/// Append-only log of processed transactions.
+///
+/// Each line is `seq, type, client, tx, amount, timestamp, id`, amounts are exact decimals,
+/// id of transactions referencing another one is empty if input does not have it.
+/// Transactions are journaled as they were given to processor, before middleware changed them,
+/// so replay runs them through the same middleware (which has to be deterministic).
+/// Journal is never compressed, so that every appended line is complete on disk
+/// (compressed data would be unreadable after a crash), but a compressed copy can be read.
+pub struct Journal {
+    writer: BufWriter<File>,
+    last_seq: Option<Seq>,
+}
+
+impl Journal {
+    /// Open (or create) journal to append transactions to it
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        if Compression::of(&path) != Compression::None {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "journal can not be compressed, it would be unreadable after a crash",
+            ));
+        }
+        let last_seq = match path.as_ref().exists() {
+            true => read_last_seq(path.as_ref())?,
+            false => None,
+        };
+        Ok(Self {
+            writer: BufWriter::new(File::options().create(true).append(true).open(path)?),
+            last_seq,
+        })
+    }
+
+    /// Sequence number of the latest journaled transaction
+    pub fn last_seq(&self) -> Option<Seq> {
+        self.last_seq
+    }
+
+    pub fn append(&mut self, tx: &Transaction) -> io::Result<Seq> {
+        let seq = self.last_seq.map_or(1, |seq| seq + 1);
+        let amount = tx
+            .detail
+            .amount()
+            .map(|a| a.to_string())
+            .unwrap_or_default();
+        let timestamp = tx.timestamp.map(|t| t.to_string()).unwrap_or_default();
+        let id = tx
+            .referencing_id()
+            .map(|id| id.to_string())
+            .unwrap_or_default();
+
+        writeln!(
+            self.writer,
+            "{seq},{},{},{},{amount},{timestamp},{id}",
+            tx.detail.name(),
+            tx.client_id,
+            tx.input_tx_id()
+        )?;
+        self.writer.flush()?;
+        self.last_seq = Some(seq);
+        Ok(seq)
+    }
+}
+
+/// Sequence number of the last journaled transaction, reading only the end of the file
+fn read_last_seq(path: &Path) -> io::Result<Option<Seq>> {
+    const BLOCK: u64 = 4096;
+    let mut file = File::open(path)?;
+    let mut start = file.seek(SeekFrom::End(0))?;
+    let mut tail = Vec::new();
+    // Read blocks from the end until the tail holds the whole last line
+    while start > 0 && !tail.trim_ascii_end().contains(&b'\n') {
+        let block = start.min(BLOCK);
+        start -= block;
+        let mut bytes = vec![0; block as usize];
+        file.seek(SeekFrom::Start(start))?;
+        file.read_exact(&mut bytes)?;
+        bytes.append(&mut tail);
+        tail = bytes;
+    }
+
+    let tail = tail.trim_ascii_end();
+    let last_line = match tail.iter().rposition(|byte| *byte == b'\n') {
+        Some(newline) => &tail[newline + 1..],
+        None => tail,
+    };
+    Ok(parse(last_line)?.last().map(|(seq, _)| *seq))
+}
+
+/// Read all journaled transactions in order
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<(Seq, Transaction)>> {
+    parse(compression::open(path)?)
+}
+
+fn parse(reader: impl Read) -> io::Result<Vec<(Seq, Transaction)>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .from_reader(reader);
+
+    rdr.records()
+        .map(|record| {
+            let record = record.map_err(io::Error::other)?;
+            let field = |index: usize| record.get(index).unwrap_or_default();
+            let seq: Seq = parse_field(&record, 0)?;
+            let client_id: ClientId = parse_field(&record, 2)?;
+            let tx_id: TransactionId = parse_field(&record, 3)?;
+            let amount = || parse_field::<MoneyAmount>(&record, 4);
+            let partial = |tx: Transaction| match field(4) {
+                "" => Ok(tx),
+                _ => amount().map(|amount| tx.partial(amount)),
+            };
+
+            let tx = match field(1) {
+                "deposit" => deposit(client_id, tx_id, amount()?),
+                "withdrawal" => withdraw(client_id, tx_id, amount()?),
+                "dispute" => partial(dispute(client_id, tx_id))?,
+                "resolve" => partial(resolve(client_id, tx_id))?,
+                "chargeback" => partial(chargeback(client_id, tx_id))?,
+                "refund" => partial(refund(client_id, tx_id))?,
+                "reversal" => reversal(client_id, tx_id),
+                other => return Err(invalid_data(format!("unknown transaction type {other}"))),
+            };
+
+            let tx = match field(5) {
+                "" => tx,
+                _ => tx.at(parse_field(&record, 5)?),
+            };
+            Ok((
+                seq,
+                match field(6) {
+                    "" => tx,
+                    _ => tx.with_id(parse_field(&record, 6)?),
+                },
+            ))
+        })
+        .collect()
+}
+
+fn parse_field<T: FromStr>(record: &csv::StringRecord, index: usize) -> io::Result<T> {
+    record
+        .get(index)
+        .and_then(|field| field.parse().ok())
+        .ok_or_else(|| invalid_data(format!("invalid journal entry {record:?}")))
+}
+
+/// Rebuild state by processing journaled transactions up to (and including) given sequence number.
+/// Returns sequence number of the last replayed transaction.
+pub fn replay<A: AccountStore>(
+    processor: &mut Processor<A>,
+    journal: &[(Seq, Transaction)],
+    until: Option<Seq>,
+) -> Option<Seq> {
+    let mut last_seq = None;
+    for (seq, tx) in journal {
+        if until.is_some_and(|until| *seq > until) {
+            break;
+        }
+        processor.process_transaction(tx);
+        last_seq = Some(*seq);
+    }
+    last_seq
+}
+
+/// State of account that must match after replay
+#[derive(Debug, PartialEq)]
+pub struct AccountState {
+    pub available: MoneyAmount,
+    pub held: MoneyAmount,
+    pub locked: bool,
+    pub disputes: BTreeSet<TransactionId>,
+}
+
+impl From<&Account> for AccountState {
+    fn from(account: &Account) -> Self {
+        Self {
+            available: account.available,
+            held: account.held,
+            locked: account.locked,
+            disputes: account.disputed_amounts.keys().copied().collect(),
+        }
+    }
+}
+
+/// Account states after a journaled transaction.
+///
+/// Stored as `seq, client, available, held, locked, disputes` lines,
+/// with disputed transaction ids separated by spaces.
+#[derive(Debug, PartialEq)]
+pub struct Snapshot {
+    pub seq: Option<Seq>,
+    pub accounts: BTreeMap<ClientId, AccountState>,
+}
+
+impl Snapshot {
+    pub fn of(accounts: &mut impl AccountStore, seq: Option<Seq>) -> io::Result<Self> {
+        let mut states = BTreeMap::new();
+        accounts.for_each(|client_id, account| {
+            states.insert(client_id, AccountState::from(account));
+        })?;
+        Ok(Self {
+            seq,
+            accounts: states,
+        })
+    }
+
+    pub fn write(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        let mut writer = BufWriter::new(File::create(path)?);
+        let seq = self.seq.map(|seq| seq.to_string()).unwrap_or_default();
+        for (client_id, state) in &self.accounts {
+            let disputes: Vec<String> = state.disputes.iter().map(|id| id.to_string()).collect();
+            writeln!(
+                writer,
+                "{seq},{client_id},{},{},{},{}",
+                state.available,
+                state.held,
+                state.locked,
+                disputes.join(" ")
+            )?;
+        }
+        writer.flush()
+    }
+
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        let mut rdr = csv::ReaderBuilder::new()
+            .has_headers(false)
+            .from_path(path)
+            .map_err(io::Error::other)?;
+        let mut snapshot = Self {
+            seq: None,
+            accounts: BTreeMap::new(),
+        };
+
+        for record in rdr.records() {
+            let record = record.map_err(io::Error::other)?;
+            let invalid = || invalid_data(format!("invalid snapshot entry {record:?}"));
+            let field = |index: usize| record.get(index).ok_or_else(invalid);
+
+            snapshot.seq = match field(0)? {
+                "" => None,
+                seq => Some(seq.parse().map_err(|_| invalid())?),
+            };
+            let client_id = field(1)?.parse().map_err(|_| invalid())?;
+            let state = AccountState {
+                available: field(2)?.parse().map_err(|_| invalid())?,
+                held: field(3)?.parse().map_err(|_| invalid())?,
+                locked: field(4)?.parse().map_err(|_| invalid())?,
+                disputes: field(5)?
+                    .split_whitespace()
+                    .map(|id| id.parse().map_err(|_| invalid()))
+                    .collect::<io::Result<_>>()?,
+            };
+            snapshot.accounts.insert(client_id, state);
+        }
+        Ok(snapshot)
+    }
+
+    /// Clients whose accounts differ between snapshots
+    pub fn mismatches(&self, other: &Snapshot) -> Vec<ClientId> {
+        let client_ids: BTreeSet<&ClientId> =
+            self.accounts.keys().chain(other.accounts.keys()).collect();
+        client_ids
+            .into_iter()
+            .filter(|client_id| self.accounts.get(client_id) != other.accounts.get(client_id))
+            .copied()
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> std::path::PathBuf {
+        std::env::temp_dir().join(format!("transactor-{name}-{}", std::process::id()))
+    }
+
+    fn journal(name: &str, transactions: &[Transaction]) -> Vec<(Seq, Transaction)> {
+        let path = temp_path(name);
+        let mut journal = Journal::open(&path).unwrap();
+        for tx in transactions {
+            journal.append(tx).unwrap();
+        }
+        let entries = read(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        entries
+    }
+
+    #[test]
+    fn journal_keeps_transactions_exactly() {
+        let entries = journal(
+            "journal-exact",
+            &[
+                deposit(1, 100, 0.0001).at(10),
+                withdraw(2, 101, 123456789.5),
+                dispute(1, 100).partial(0.5).with_id(102),
+            ],
+        );
+
+        assert_eq!(
+            entries.iter().map(|(seq, _)| *seq).collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+        assert_eq!(
+            format!("{:?}", entries[0].1),
+            format!("{:?}", deposit(1, 100, 0.0001).at(10))
+        );
+        assert_eq!(
+            format!("{:?}", entries[1].1),
+            format!("{:?}", withdraw(2, 101, 123456789.5))
+        );
+        assert_eq!(
+            format!("{:?}", entries[2].1),
+            format!("{:?}", dispute(1, 100).partial(0.5).with_id(102))
+        );
+    }
+
+    #[test]
+    fn reopened_journal_continues_after_last_line() {
+        let path = temp_path("journal-reopen");
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(Journal::open(&path).unwrap().last_seq(), None);
+
+        // Enough lines that the last one is not in the first block read from the end
+        let mut journal = Journal::open(&path).unwrap();
+        for tx_id in 0..500 {
+            journal.append(&deposit(1, tx_id, 1.5).at(1)).unwrap();
+        }
+        drop(journal);
+
+        let mut journal = Journal::open(&path).unwrap();
+        assert_eq!(journal.last_seq(), Some(500));
+        assert_eq!(journal.append(&withdraw(1, 500, 1.0)).unwrap(), 501);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn compressed_journal_is_refused() {
+        for extension in ["gz", "zst"] {
+            let path = temp_path("journal").with_extension(extension);
+            let error = Journal::open(&path).err().unwrap();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidInput, "{extension}");
+            assert!(!path.exists());
+        }
+    }
+
+    #[test]
+    fn replays_state_at_any_point() {
+        let entries = journal(
+            "journal-replay",
+            &[
+                deposit(1, 100, 10.0),
+                deposit(2, 101, 5.0),
+                dispute(1, 100),
+                chargeback(1, 100),
+            ],
+        );
+
+        let mut processor: Processor = Processor::default();
+        assert_eq!(replay(&mut processor, &entries, Some(3)), Some(3));
+        let snapshot = Snapshot::of(&mut processor.accounts, Some(3)).unwrap();
+        assert_eq!(
+            snapshot.accounts[&1],
+            AccountState {
+                available: 0.into(),
+                held: 10.into(),
+                locked: false,
+                disputes: [100].into(),
+            }
+        );
+
+        let mut processor: Processor = Processor::default();
+        replay(&mut processor, &entries, None);
+        let replayed = Snapshot::of(&mut processor.accounts, Some(4)).unwrap();
+        assert_eq!(snapshot.mismatches(&replayed), [1]);
+    }
+
+    #[test]
+    fn snapshot_round_trip() {
+        let path = temp_path("snapshot");
+        let snapshot = Snapshot {
+            seq: Some(7),
+            accounts: [
+                (
+                    1,
+                    AccountState {
+                        available: "-1.5".parse().unwrap(),
+                        held: 3.into(),
+                        locked: true,
+                        disputes: [5, 9].into(),
+                    },
+                ),
+                (
+                    2,
+                    AccountState {
+                        available: 2.into(),
+                        held: 0.into(),
+                        locked: false,
+                        disputes: [].into(),
+                    },
+                ),
+            ]
+            .into(),
+        };
+        snapshot.write(&path).unwrap();
+
+        assert_eq!(Snapshot::read(&path).unwrap(), snapshot);
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
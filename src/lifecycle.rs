@@ -0,0 +1,206 @@
+use crate::{
+    account::{AuditRecord, ClientId},
+    transactions::TransactionId,
+};
+use std::collections::HashMap;
+
+/// Where deposit (or withdrawal) is in its dispute lifecycle
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum State {
+    /// Applied and never disputed
+    Settled,
+    /// (Part of it) is under open dispute
+    Disputed,
+    /// All disputes were resolved, it can be disputed again
+    Resolved,
+    /// Dispute was closed by chargeback, nothing can happen to it anymore
+    ChargedBack,
+    /// Voided by reversal, nothing can happen to it anymore
+    Reversed,
+}
+
+/// Transaction referencing a deposit or withdrawal
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Action {
+    Dispute,
+    Resolve,
+    ChargeBack,
+    Refund,
+    Reversal,
+}
+
+impl State {
+    /// Whether action is allowed in this state.
+    ///
+    /// | state       | dispute  | resolve  | chargeback  | refund   | reversal |
+    /// |-------------|----------|----------|-------------|----------|----------|
+    /// | Settled     | Disputed |          |             | Settled  | Reversed |
+    /// | Disputed    | Disputed | Resolved | ChargedBack | Disputed |          |
+    /// | Resolved    | Disputed |          |             | Resolved | Reversed |
+    /// | ChargedBack |          |          |             |          |          |
+    /// | Reversed    |          |          |             |          |          |
+    ///
+    /// Partial resolve or chargeback keeps the deposit Disputed while part of dispute is open,
+    /// refund applies to the part that is not disputed.
+    pub fn allows(self, action: Action) -> bool {
+        use Action::*;
+        match self {
+            State::Settled | State::Resolved => matches!(action, Dispute | Refund | Reversal),
+            State::Disputed => !matches!(action, Reversal),
+            State::ChargedBack | State::Reversed => false,
+        }
+    }
+}
+
+/// Lifecycle states of deposits and withdrawals.
+///
+/// Only states that can not be told from stored deposit are kept: Settled and Resolved
+/// deposits differ by their count of disputes (kept with the deposit, so the redispute
+/// limit holds across restarts), Disputed state is rebuilt from open disputes of stored
+/// accounts.
+/// Terminal states are kept for the run only, their transactions are forgotten by the store,
+/// so after restart they are rejected as not found instead.
+#[derive(Debug, Default)]
+pub struct Lifecycles {
+    /// How many times deposit can be disputed again after its disputes were resolved
+    pub max_redisputes: Option<u32>,
+
+    states: HashMap<(ClientId, TransactionId), State>,
+
+    /// Previous states changed since the last commit, to restore them on rollback
+    uncommitted: Vec<((ClientId, TransactionId), Option<State>)>,
+}
+
+impl Lifecycles {
+    /// Check that action is allowed for referenced transaction that was disputed
+    /// given number of times
+    pub fn check(
+        &self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        disputes: u32,
+        action: Action,
+    ) -> Result<(), AuditRecord> {
+        let state = self.state(client_id, tx_id, disputes);
+        // actions of never disputed transactions are left to the account to reject
+        if state == State::Settled {
+            return Ok(());
+        }
+        if !state.allows(action) {
+            return Err(AuditRecord::IllegalTransition { state, action });
+        }
+
+        let redispute = state == State::Resolved && action == Action::Dispute;
+        match self.max_redisputes {
+            Some(max) if redispute && disputes > max => {
+                Err(AuditRecord::RedisputeLimitReached { max })
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Current state of referenced transaction that was disputed given number of times
+    pub fn state(&self, client_id: ClientId, tx_id: TransactionId, disputes: u32) -> State {
+        match self.states.get(&(client_id, tx_id)) {
+            Some(&state) => state,
+            None if disputes > 0 => State::Resolved,
+            None => State::Settled,
+        }
+    }
+
+    /// Move referenced transaction to new state after action was applied
+    pub fn change(&mut self, client_id: ClientId, tx_id: TransactionId, state: State) {
+        let key = (client_id, tx_id);
+        let previous = match state {
+            State::Settled | State::Resolved => self.states.remove(&key),
+            _ => self.states.insert(key, state),
+        };
+        self.uncommitted.push((key, previous));
+    }
+
+    /// Keep changes made since the last commit
+    pub fn commit(&mut self) {
+        self.uncommitted.clear();
+    }
+
+    /// Undo changes made since the last commit
+    pub fn rollback(&mut self) {
+        for (key, state) in self.uncommitted.drain(..).rev() {
+            match state {
+                Some(state) => self.states.insert(key, state),
+                None => self.states.remove(&key),
+            };
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn terminal_states_allow_nothing() {
+        for action in [
+            Action::Dispute,
+            Action::Resolve,
+            Action::ChargeBack,
+            Action::Refund,
+            Action::Reversal,
+        ] {
+            assert!(!State::ChargedBack.allows(action));
+            assert!(!State::Reversed.allows(action));
+        }
+        assert!(!State::Settled.allows(Action::Resolve));
+        assert!(!State::Disputed.allows(Action::Reversal));
+    }
+
+    #[test]
+    fn limits_redisputes() {
+        let lifecycles = Lifecycles {
+            max_redisputes: Some(1),
+            ..Default::default()
+        };
+        assert_eq!(lifecycles.check(1, 100, 0, Action::Dispute), Ok(()));
+        assert_eq!(lifecycles.check(1, 100, 1, Action::Dispute), Ok(()));
+        assert_eq!(
+            lifecycles.check(1, 100, 2, Action::Dispute),
+            Err(AuditRecord::RedisputeLimitReached { max: 1 })
+        );
+        assert_eq!(
+            lifecycles.check(1, 100, 2, Action::ChargeBack),
+            Err(AuditRecord::IllegalTransition {
+                state: State::Resolved,
+                action: Action::ChargeBack
+            })
+        );
+    }
+
+    #[test]
+    fn keeps_only_states_not_told_by_dispute_count() {
+        let mut lifecycles = Lifecycles::default();
+        lifecycles.change(1, 100, State::Disputed);
+        assert_eq!(lifecycles.state(1, 100, 1), State::Disputed);
+
+        lifecycles.change(1, 100, State::Resolved);
+        lifecycles.change(1, 101, State::Reversed);
+        assert_eq!(lifecycles.state(1, 100, 1), State::Resolved);
+        assert_eq!(lifecycles.state(1, 101, 0), State::Reversed);
+        assert_eq!(lifecycles.states.len(), 1);
+    }
+
+    #[test]
+    fn rollback_restores_committed_states() {
+        let mut lifecycles = Lifecycles::default();
+        lifecycles.change(1, 100, State::Disputed);
+        lifecycles.commit();
+
+        lifecycles.change(1, 100, State::Resolved);
+        lifecycles.change(1, 100, State::Disputed);
+        lifecycles.change(1, 101, State::Reversed);
+        lifecycles.rollback();
+
+        assert_eq!(lifecycles.state(1, 100, 1), State::Disputed);
+        assert_eq!(lifecycles.state(1, 101, 0), State::Settled);
+        assert_eq!(lifecycles.states.len(), 1);
+    }
+}
@@ -0,0 +1,388 @@
+use crate::{
+    account::ClientId,
+    config::{self, Section},
+    money::MoneyAmount,
+    transactions::{Timestamp, TransactionDetail},
+};
+use std::{
+    collections::{HashMap, VecDeque},
+    fmt, io,
+    path::Path,
+    str::FromStr,
+};
+
+/// Limit rule that prevented a transaction
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    /// Single withdrawal is larger than allowed
+    Withdrawal { max: MoneyAmount },
+
+    /// Total withdrawn within the window would be larger than allowed
+    WithdrawnInWindow { max: MoneyAmount, window: Window },
+
+    /// Client made too many deposits and withdrawals within the window
+    TransactionsInWindow { max: usize, window: Window },
+}
+
+/// Readable rule, like `at most 3 transactions per day`
+impl fmt::Display for Limit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Limit::Withdrawal { max } => write!(f, "at most {max} per withdrawal"),
+            Limit::WithdrawnInWindow { max, window } => {
+                write!(f, "at most {max} withdrawn per {window}")
+            }
+            Limit::TransactionsInWindow { max, window } => {
+                write!(f, "at most {max} transactions per {window}")
+            }
+        }
+    }
+}
+
+const DAY: Timestamp = 24 * 60 * 60;
+
+/// Period in which transactions count towards window limits
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Window {
+    /// Given number of seconds up to the transaction
+    Rolling(Timestamp),
+
+    /// Calendar day (UTC) of the transaction
+    Day,
+}
+
+impl Window {
+    /// Whether transaction made at given time is in the window of transaction made `now`
+    fn contains(self, at: Timestamp, now: Timestamp) -> bool {
+        match self {
+            Window::Rolling(length) => at.saturating_add(length) > now,
+            Window::Day => at / DAY == now / DAY,
+        }
+    }
+}
+
+impl fmt::Display for Window {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Window::Rolling(length) => write!(f, "{length} seconds"),
+            Window::Day => f.write_str("day"),
+        }
+    }
+}
+
+/// `day` or length of rolling window in seconds
+impl FromStr for Window {
+    type Err = <Timestamp as FromStr>::Err;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "day" => Ok(Window::Day),
+            seconds => seconds.parse().map(Window::Rolling),
+        }
+    }
+}
+
+/// Limits of a single client
+#[derive(Debug, Clone, PartialEq)]
+pub struct ClientLimits {
+    pub max_withdrawal: Option<MoneyAmount>,
+    pub max_withdrawn_per_window: Option<MoneyAmount>,
+    pub max_transactions_per_window: Option<usize>,
+
+    /// Window of window limits (rolling day by default)
+    pub window: Window,
+}
+
+impl Default for ClientLimits {
+    fn default() -> Self {
+        Self {
+            max_withdrawal: None,
+            max_withdrawn_per_window: None,
+            max_transactions_per_window: None,
+            window: Window::Rolling(DAY),
+        }
+    }
+}
+
+/// Deposit or withdrawal that counts towards window limits
+struct Activity {
+    at: Timestamp,
+    withdrawn: MoneyAmount,
+}
+
+/// Velocity and amount limits, default ones and overrides for specific clients.
+///
+/// Window limits apply only to transactions with known time.
+#[derive(Default)]
+pub struct Limits {
+    pub default: ClientLimits,
+    pub clients: HashMap<ClientId, ClientLimits>,
+
+    /// Recent deposits and withdrawals of each client, oldest first
+    activity: HashMap<ClientId, VecDeque<Activity>>,
+
+    /// Activity recorded since the last commit
+    uncommitted: Vec<(ClientId, Activity)>,
+}
+
+impl Limits {
+    /// Read limits from config file like
+    ///
+    /// ```text
+    /// [default]
+    /// max_withdrawal = 1000
+    /// max_withdrawn_per_window = 5000
+    /// max_transactions_per_window = 100
+    /// window = 86400
+    ///
+    /// [client 7]
+    /// max_withdrawal = 20000
+    /// window = day
+    /// ```
+    ///
+    /// Window is length of rolling window in seconds or `day` for calendar day (UTC).
+    /// Client sections override only the limits they mention.
+    pub fn read(path: impl AsRef<Path>) -> io::Result<Self> {
+        Self::from_config(&config::read(path)?)
+    }
+
+    pub fn from_config(sections: &[Section]) -> io::Result<Self> {
+        let mut limits = Self::default();
+        if let Some(section) = sections.iter().find(|s| s.name == "default") {
+            apply(&mut limits.default, section)?;
+        }
+
+        for section in sections.iter().filter(|s| s.name != "default") {
+            let client_id = section
+                .name
+                .strip_prefix("client ")
+                .and_then(|id| id.trim().parse().ok())
+                .ok_or_else(|| {
+                    let line = section.entries.first().map_or(0, |e| e.line);
+                    config::invalid(line, format!("unknown section {}", section.name))
+                })?;
+
+            let mut client_limits = limits.default.clone();
+            apply(&mut client_limits, section)?;
+            limits.clients.insert(client_id, client_limits);
+        }
+
+        Ok(limits)
+    }
+
+    fn of(&self, client_id: ClientId) -> &ClientLimits {
+        self.clients.get(&client_id).unwrap_or(&self.default)
+    }
+
+    /// Find limit that the transaction would exceed
+    pub fn check(
+        &self,
+        client_id: ClientId,
+        detail: &TransactionDetail,
+        at: Option<Timestamp>,
+    ) -> Option<Limit> {
+        let withdrawn = match detail {
+            TransactionDetail::Withdrawal { amount } => *amount,
+            TransactionDetail::Deposit { .. } => MoneyAmount::default(),
+            _ => return None,
+        };
+        let limits = self.of(client_id);
+
+        if let Some(max) = limits.max_withdrawal.filter(|max| withdrawn > *max) {
+            return Some(Limit::Withdrawal { max });
+        }
+
+        let at = at?;
+        let window = limits.window;
+        let recent = self
+            .activity
+            .get(&client_id)
+            .into_iter()
+            .flatten()
+            .filter(|activity| window.contains(activity.at, at));
+
+        if let Some(max) = limits.max_transactions_per_window {
+            if recent.clone().count() >= max {
+                return Some(Limit::TransactionsInWindow { max, window });
+            }
+        }
+
+        if let Some(max) = limits.max_withdrawn_per_window {
+            let total = recent.clone().try_fold(withdrawn, |total, activity| {
+                total.try_change(activity.withdrawn)
+            });
+            if total.is_none_or(|total| total > max) {
+                return Some(Limit::WithdrawnInWindow { max, window });
+            }
+        }
+
+        None
+    }
+
+    /// Count processed transaction towards window limits once it is committed
+    pub fn record(
+        &mut self,
+        client_id: ClientId,
+        detail: &TransactionDetail,
+        at: Option<Timestamp>,
+    ) {
+        let withdrawn = match detail {
+            TransactionDetail::Withdrawal { amount } => *amount,
+            TransactionDetail::Deposit { .. } => MoneyAmount::default(),
+            _ => return,
+        };
+        let Some(at) = at else {
+            return;
+        };
+        let limits = self.of(client_id);
+        if limits.max_transactions_per_window.is_none() && limits.max_withdrawn_per_window.is_none()
+        {
+            return;
+        }
+
+        self.uncommitted
+            .push((client_id, Activity { at, withdrawn }));
+    }
+
+    /// Count recorded transactions, forgetting activity that is out of window
+    pub fn commit(&mut self) {
+        for (client_id, activity) in std::mem::take(&mut self.uncommitted) {
+            let window = self.of(client_id).window;
+            let recent = self.activity.entry(client_id).or_default();
+            while recent
+                .front()
+                .is_some_and(|recent| !window.contains(recent.at, activity.at))
+            {
+                recent.pop_front();
+            }
+            recent.push_back(activity);
+        }
+    }
+
+    /// Forget transactions recorded since the last commit
+    pub fn rollback(&mut self) {
+        self.uncommitted.clear();
+    }
+}
+
+fn apply(limits: &mut ClientLimits, section: &Section) -> io::Result<()> {
+    for entry in &section.entries {
+        match entry.key.as_str() {
+            "max_withdrawal" => limits.max_withdrawal = Some(entry.parse()?),
+            "max_withdrawn_per_window" => limits.max_withdrawn_per_window = Some(entry.parse()?),
+            "max_transactions_per_window" => {
+                limits.max_transactions_per_window = Some(entry.parse()?)
+            }
+            "window" => limits.window = entry.parse()?,
+            key => return Err(config::invalid(entry.line, format!("unknown limit {key}"))),
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn limits(config: &str) -> Limits {
+        Limits::from_config(&config::parse(config).unwrap()).unwrap()
+    }
+
+    fn withdrawal(amount: f64) -> TransactionDetail {
+        TransactionDetail::Withdrawal {
+            amount: amount.into(),
+        }
+    }
+
+    #[test]
+    fn reads_client_overrides() {
+        let limits = limits(
+            "
+            [client 7]
+            max_withdrawal = 20000
+            [default]
+            max_withdrawal = 1000.5
+            window = 3600
+            ",
+        );
+
+        assert_eq!(limits.default.max_withdrawal, Some(1000.5.into()));
+        assert_eq!(limits.clients[&7].max_withdrawal, Some(20000.into()));
+        assert_eq!(limits.clients[&7].window, Window::Rolling(3600));
+    }
+
+    #[test]
+    fn rejects_unknown_limits() {
+        let error = Limits::from_config(&config::parse("[default]\nmax = 1").unwrap());
+        assert!(error.is_err());
+    }
+
+    #[test]
+    fn limits_single_withdrawal() {
+        let limits = limits("[default]\nmax_withdrawal = 100");
+
+        assert_eq!(limits.check(1, &withdrawal(100.0), None), None);
+        assert_eq!(
+            limits.check(1, &withdrawal(100.01), None),
+            Some(Limit::Withdrawal { max: 100.into() })
+        );
+    }
+
+    #[test]
+    fn limits_withdrawn_in_rolling_window() {
+        let mut limits = limits("[default]\nmax_withdrawn_per_window = 100\nwindow = 10");
+        limits.record(1, &withdrawal(60.0), Some(0));
+        limits.commit();
+        limits.record(1, &withdrawal(50.0), Some(1));
+        limits.rollback();
+
+        assert_eq!(
+            limits.check(1, &withdrawal(50.0), Some(9)),
+            Some(Limit::WithdrawnInWindow {
+                max: 100.into(),
+                window: Window::Rolling(10)
+            })
+        );
+        assert_eq!(limits.check(1, &withdrawal(40.0), Some(9)), None);
+        assert_eq!(limits.check(2, &withdrawal(50.0), Some(9)), None);
+        assert_eq!(limits.check(1, &withdrawal(50.0), Some(10)), None);
+    }
+
+    #[test]
+    fn limits_number_of_transactions_in_window() {
+        let mut limits = limits("[default]\nmax_transactions_per_window = 2\nwindow = 10");
+        let deposit = TransactionDetail::Deposit { amount: 1.into() };
+        limits.record(1, &deposit, Some(0));
+        limits.record(1, &withdrawal(1.0), Some(5));
+        limits.commit();
+
+        assert_eq!(
+            limits.check(1, &deposit, Some(6)),
+            Some(Limit::TransactionsInWindow {
+                max: 2,
+                window: Window::Rolling(10)
+            })
+        );
+        assert_eq!(limits.check(1, &deposit, Some(10)), None);
+    }
+
+    #[test]
+    fn limits_withdrawn_in_calendar_day() {
+        let mut limits = limits("[default]\nmax_withdrawn_per_window = 100\nwindow = day");
+        limits.record(1, &withdrawal(60.0), Some(DAY + 10));
+        limits.commit();
+
+        assert_eq!(
+            limits.check(1, &withdrawal(50.0), Some(2 * DAY - 1)),
+            Some(Limit::WithdrawnInWindow {
+                max: 100.into(),
+                window: Window::Day
+            })
+        );
+        assert_eq!(limits.check(1, &withdrawal(50.0), Some(2 * DAY)), None);
+
+        limits.record(1, &withdrawal(50.0), Some(2 * DAY));
+        limits.commit();
+        assert_eq!(limits.activity[&1].len(), 1);
+    }
+}
@@ -1,68 +1,660 @@
 mod account;
+mod account_store;
+mod applied;
+mod binary;
+mod cli;
+mod compression;
+mod config;
+mod events;
+mod fees;
+mod input;
+mod journal;
+mod lifecycle;
+mod limits;
+mod metrics;
+mod middleware;
 mod money;
 mod processor;
+mod reversible;
+mod screening;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+mod stats;
 mod transactions;
+mod validation;
 
-use account::ClientId;
-use processor::Processor;
-use std::{error::Error, io};
-use transactions::{chargeback, deposit, dispute, resolve, withdraw, Transaction, TransactionId};
+use account::{AuditRecord, ExpiryPolicy};
+use account_store::{AccountStore, FileAccountStore};
+use applied::{AppliedStore, FileAppliedStore};
+use cli::{Args, Command, Failure, FailureKind, LogLevel, USAGE};
+use compression::{Compression, Writer};
+use fees::Fees;
+use input::{CsvOptions, Dialect, Format, Order, Skipped};
+use journal::{Journal, Snapshot};
+use limits::Limits;
+use metrics::Metrics;
+use middleware::Middleware;
+use processor::{DisputeExpiry, Processor};
+use reversible::{EvictionPolicy, MemoryReversibleStore};
+use screening::Screening;
+use stats::{Stats, Summary};
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, Write},
+    net::{SocketAddr, TcpListener},
+    path::PathBuf,
+    process::ExitCode,
+};
+use transactions::{Timestamp, Transaction};
+use validation::{AppliedOverlay, Change, Overlay, ReversibleOverlay, Validation};
 
-fn main() {
-    let transactions = read_transactions().unwrap();
-    let mut processor = Processor::default();
-    let _audit_records: Vec<_> = processor.process(&transactions).collect();
+fn main() -> ExitCode {
+    let result = Args::parse(std::env::args().skip(1)).and_then(|args| run_command(&args));
+    match result {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(failure) => {
+            if failure.kind != FailureKind::Closed {
+                eprintln!("error: {failure}");
+            }
+            ExitCode::from(failure.exit_code())
+        }
+    }
+}
+
+fn run_command(args: &Args) -> Result<(), Failure> {
+    let paths: Vec<&str> = args.positional.iter().map(String::as_str).collect();
+    let csv_options = csv_options(args)?;
+    match args.command {
+        Command::Help => return writeln!(io::stdout(), "{USAGE}").map_err(Failure::from),
+        Command::Replay => return replay_journal(paths[0], args, csv_options.dialect),
+        Command::Convert => return convert(paths[0], paths[1], args, &csv_options),
+        _ => {}
+    }
+
+    let order = args.parsed("--order")?.unwrap_or(Order::Args);
+    let inputs = input::input_files(&paths, order)?;
+
+    if let Some(path) = args.option("--db") {
+        #[cfg(feature = "sqlite")]
+        {
+            let store = sqlite::SqliteStore::open(path).map_err(|e| context(e, path))?;
+            let mut processor = Processor::new(store).map_err(|e| context(e, path))?;
+            processor.deposits = Box::new(processor.accounts.deposits());
+            processor.withdrawals = Box::new(processor.accounts.withdrawals());
+            processor.applied = Box::new(processor.accounts.applied());
+            return run(processor, args, &inputs, &csv_options);
+        }
+        #[cfg(not(feature = "sqlite"))]
+        return Err(Failure::usage(format!(
+            "can not use {path}, transactor is built without sqlite feature"
+        )));
+    }
+
+    match args.option("--accounts") {
+        Some(path) => {
+            let capacity = args.parsed("--account-cache")?.unwrap_or(10000);
+            let accounts = FileAccountStore::open(path, capacity).map_err(|e| context(e, path))?;
+            let mut processor = Processor::new(accounts).map_err(|e| context(e, path))?;
+            processor.deposits = Box::new(deposit_store(args)?);
+            processor.withdrawals = Box::new(withdrawal_store(args)?);
+            processor.applied = applied_store(args)?;
+            run(processor, args, &inputs, &csv_options)
+        }
+        None => {
+            let mut processor = <Processor>::default();
+            processor.deposits = Box::new(deposit_store(args)?);
+            processor.withdrawals = Box::new(withdrawal_store(args)?);
+            processor.applied = applied_store(args)?;
+            run(processor, args, &inputs, &csv_options)
+        }
+    }
+}
+
+fn run<A: AccountStore>(
+    mut processor: Processor<A>,
+    args: &Args,
+    inputs: &[PathBuf],
+    csv_options: &CsvOptions,
+) -> Result<(), Failure> {
+    if args.command == Command::Validate {
+        // Changes are kept in memory over stores that are only read
+        let Processor {
+            accounts,
+            deposits,
+            withdrawals,
+            applied,
+            ..
+        } = processor;
+        let mut processor = Processor::new(Overlay::new(accounts))?;
+        processor.deposits = Box::new(ReversibleOverlay::new(deposits));
+        processor.withdrawals = Box::new(ReversibleOverlay::new(withdrawals));
+        processor.applied = Box::new(AppliedOverlay::new(applied));
+        return validate(processor, args, inputs, csv_options);
+    }
+
+    let within: Option<Timestamp> = args.parsed("--within")?;
+    if args.command == Command::Expiring && within.is_none() {
+        return Err(Failure::usage("expiring needs --within"));
+    }
+    configure(&mut processor, args)?;
+    processor.journal = read_config(args, "--journal", Journal::open)?;
+    if let Some(address) = args.parsed::<SocketAddr>("--metrics")? {
+        let listener = TcpListener::bind(address).map_err(|e| context(e, address))?;
+        let metrics = Metrics::shared();
+        metrics::serve(listener, metrics.clone());
+        processor.metrics = Some(metrics);
+    }
+
+    let mut storage_errors = 0;
+    let total = process_inputs(&mut processor, args, inputs, csv_options, |_, records| {
+        if records.contains(&AuditRecord::StorageError) {
+            storage_errors += 1;
+        }
+    })?;
+
+    if let Some(path) = args.option("--snapshot") {
+        let seq = processor.journal.as_ref().and_then(Journal::last_seq);
+        Snapshot::of(&mut processor.accounts, seq)
+            .and_then(|snapshot| snapshot.write(path))
+            .map_err(|e| context(e, path))?;
+    }
+
+    let dialect = csv_options.dialect;
+    let mut out = report_writer(args)?;
+    match (args.command, within) {
+        (Command::Expiring, Some(within)) => {
+            print_expiring_disputes(&mut out, &processor, within, dialect)?
+        }
+        (Command::Stats, _) => writeln!(out, "{total}")?,
+        _ => print_accounts(&mut out, &mut processor.accounts, dialect)?,
+    }
+    out.finish()?;
+    processor.persist()?;
+
+    match args.option("--summary") {
+        Some("-") => eprintln!("{}", processor.summary),
+        Some(path) => write_summary(path, &processor.summary).map_err(|e| context(e, path))?,
+        None => {}
+    }
+
+    if storage_errors > 0 {
+        return Err(Failure::processing(format!(
+            "{storage_errors} transactions failed to be stored and may be partially applied"
+        )));
+    }
+    Ok(())
+}
+
+/// Process transactions without committing them and report what they would change
+fn validate<A: AccountStore>(
+    mut processor: Processor<Overlay<A>>,
+    args: &Args,
+    inputs: &[PathBuf],
+    csv_options: &CsvOptions,
+) -> Result<(), Failure> {
+    let max_failed_percent: Option<f64> = args.parsed("--max-failed-percent")?;
+    let max_locked_clients: Option<usize> = args.parsed("--max-locked-clients")?;
+    configure(&mut processor, args)?;
+
+    let mut validation = Validation::default();
+    process_inputs(&mut processor, args, inputs, csv_options, |tx, records| {
+        validation.count(tx, records)
+    })?;
+    let changes = processor.accounts.changes()?;
+
+    let mut out = report_writer(args)?;
+    print_validation(&mut out, &validation, &changes, csv_options.dialect)?;
+    out.finish()?;
+
+    let failed = validation.failed_percent();
+    let locked = changes.iter().filter(|change| change.locked).count();
+    let mut exceeded = Vec::new();
+    if let Some(max) = max_failed_percent.filter(|max| failed > *max) {
+        exceeded.push(format!(
+            "{failed:.2}% of transactions failed (at most {max}% allowed)"
+        ));
+    }
+    if let Some(max) = max_locked_clients.filter(|max| locked > *max) {
+        exceeded.push(format!(
+            "{locked} clients would be locked (at most {max} allowed)"
+        ));
+    }
+    match exceeded.is_empty() {
+        true => Ok(()),
+        false => Err(Failure::processing(exceeded.join(", "))),
+    }
+}
+
+/// Set up processing rules of processor from command line options
+fn configure<A: AccountStore>(processor: &mut Processor<A>, args: &Args) -> Result<(), Failure> {
+    processor.dispute_expiry = dispute_expiry(args)?;
+    processor.limits = read_config(args, "--limits", Limits::read)?.unwrap_or_default();
+    processor.lifecycles.max_redisputes = args.parsed("--max-redisputes")?;
+    processor.fees = read_config(args, "--fees", Fees::read)?.unwrap_or_default();
+    processor.middleware = middleware(args)?;
+    processor.screening = screening(args)?;
+    Ok(())
+}
+
+/// Process transactions of input files one after another, `processed` gets records of each
+fn process_inputs<A: AccountStore>(
+    processor: &mut Processor<A>,
+    args: &Args,
+    inputs: &[PathBuf],
+    csv_options: &CsvOptions,
+    mut processed: impl FnMut(&Transaction, &[AuditRecord]),
+) -> Result<Stats, Failure> {
+    let continue_on_error = match args.option("--on-error") {
+        None | Some("fail") => false,
+        Some("continue") => true,
+        Some(other) => {
+            return Err(Failure::usage(format!(
+                "unknown --on-error {other}, expected fail or continue"
+            )))
+        }
+    };
+    let input_format = args.parsed("--input-format")?;
+    if !continue_on_error {
+        // Missing or unreadable file fails the run before anything is processed
+        for path in inputs {
+            File::open(path).map_err(|e| context(e, path.display()))?;
+        }
+    }
+
+    let mut outcomes = outcome_log(args, csv_options.dialect)?;
+    let mut total = Stats::default();
+    for path in inputs {
+        let format = input_format.unwrap_or_else(|| Format::of(path));
+        let skipped = |skipped| report_skipped(args, path.display(), skipped);
+        let transactions = match input::read(path, format, csv_options, skipped) {
+            Ok(transactions) => transactions,
+            Err(error) if continue_on_error => {
+                if args.logs(LogLevel::Warn) {
+                    eprintln!("{}: can not be read, skipped: {error}", path.display());
+                }
+                continue;
+            }
+            Err(error) => {
+                // Transactions of previous files are journaled and may be marked as applied,
+                // their account changes are stored before failing
+                processor.persist()?;
+                return Err(Failure::from(error).context(path.display()));
+            }
+        };
+
+        let mut stats = Stats::default();
+        for tx in &transactions {
+            let records: Vec<AuditRecord> = processor.process([tx]).collect();
+            for record in &records {
+                report(args, tx, record);
+            }
+            if let Some((path, out)) = &mut outcomes {
+                log_outcomes(out, tx, &records, csv_options.dialect)
+                    .map_err(|e| context(e, path))?;
+            }
+            processed(tx, &records);
+            stats.count(&records);
+        }
+        if inputs.len() > 1 && args.logs(LogLevel::Info) {
+            eprintln!("{}: {stats}", path.display());
+        }
+        total += stats;
+    }
+    if let Some((path, mut out)) = outcomes {
+        out.finish().map_err(|e| context(e, path))?;
+    }
+    Ok(total)
+}
+
+/// Writer of `--outcomes` file (compressed by its extension) with its header written
+fn outcome_log(args: &Args, dialect: Dialect) -> Result<Option<(&str, Writer)>, Failure> {
+    let Some(path) = args.option("--outcomes") else {
+        return Ok(None);
+    };
+    let create = || {
+        let mut out = Writer::create(path)?;
+        if dialect.has_header {
+            writeln!(
+                out,
+                "{}",
+                dialect.row(&["type", "client", "tx", "outcome", "details"])
+            )?;
+        }
+        Ok(out)
+    };
+    create()
+        .map(|out| Some((path, out)))
+        .map_err(|e| context(e, path))
+}
+
+/// Write a row for every record of processed transaction
+fn log_outcomes(
+    out: &mut impl Write,
+    tx: &Transaction,
+    records: &[AuditRecord],
+    dialect: Dialect,
+) -> io::Result<()> {
+    for record in records {
+        let row = [
+            tx.detail.name().to_string(),
+            tx.client_id.to_string(),
+            tx.input_tx_id().to_string(),
+            record.kind().to_string(),
+            record.details().unwrap_or_default(),
+        ];
+        writeln!(out, "{}", dialect.row(&row))?;
+    }
+    Ok(())
+}
+
+/// Write run statistics as JSON to file (compressed by its extension)
+fn write_summary(path: &str, summary: &Summary) -> io::Result<()> {
+    let mut writer = Writer::create(path)?;
+    summary.write_json(&mut writer)?;
+    writer.finish()
+}
+
+fn convert(
+    input_path: &str,
+    output_path: &str,
+    args: &Args,
+    csv_options: &CsvOptions,
+) -> Result<(), Failure> {
+    let format = args.parsed("--input-format")?;
+    let format = format.unwrap_or_else(|| Format::of(input_path));
+    let skipped = |skipped| report_skipped(args, input_path, skipped);
+    let transactions = input::read(input_path, format, csv_options, skipped)
+        .map_err(|e| Failure::from(e).context(input_path))?;
+    let format = args.parsed("--output-format")?;
+    let format = format.unwrap_or_else(|| Format::of(output_path));
+    input::write(output_path, format, csv_options, &transactions)
+        .map_err(|e| context(e, output_path))
+}
+
+/// Rebuild accounts from journal (up to snapshot if it is verified)
+fn replay_journal(journal_path: &str, args: &Args, dialect: Dialect) -> Result<(), Failure> {
+    let entries = journal::read(journal_path).map_err(|e| context(e, journal_path))?;
+    let snapshot = read_config(args, "--verify", Snapshot::read)?;
+    let until = args.parsed("--until")?;
+    let until = until.or(snapshot.as_ref().and_then(|snapshot| snapshot.seq));
+
+    let mut processor = <Processor>::default();
+    processor.deposits = Box::new(deposit_store(args)?);
+    processor.withdrawals = Box::new(withdrawal_store(args)?);
+    configure(&mut processor, args)?;
+    let seq = journal::replay(&mut processor, &entries, until);
+    let mut out = report_writer(args)?;
+    print_accounts(&mut out, &mut processor.accounts, dialect)?;
+    out.finish()?;
 
-    // print out accounts
-    println!("client, available, held, total, locked");
-    processor.accounts.iter().for_each(|(id, account)| {
+    let Some(snapshot) = snapshot else {
+        return Ok(());
+    };
+    let replayed = Snapshot::of(&mut processor.accounts, seq)?;
+    if snapshot.seq != replayed.seq {
+        return Err(Failure::processing(format!(
+            "snapshot is taken after transaction {:?}, but journal is replayed until {:?}",
+            snapshot.seq, replayed.seq
+        )));
+    }
+    let mismatches = snapshot.mismatches(&replayed);
+    if !mismatches.is_empty() {
+        return Err(Failure::processing(format!(
+            "accounts of clients {mismatches:?} do not match snapshot"
+        )));
+    }
+    Ok(())
+}
+
+/// Writer of account report, to stdout unless `--output` file is given
+fn report_writer(args: &Args) -> Result<Writer, Failure> {
+    Ok(match args.option("--output") {
+        Some(path) => Writer::create(path).map_err(|e| context(e, path))?,
+        None => Writer::new(Box::new(io::stdout().lock()), Compression::None)?,
+    })
+}
+
+fn print_accounts(
+    out: &mut impl Write,
+    accounts: &mut impl AccountStore,
+    dialect: Dialect,
+) -> io::Result<()> {
+    if dialect.has_header {
+        let header = ["client", "available", "held", "total", "locked"];
+        writeln!(out, "{}", dialect.padded_row(&header))?;
+    }
+    let mut result = Ok(());
+    accounts.for_each(|id, account| {
         let Some(total) = account.total() else { return };
-        println!(
-            "{id}, {}, {}, {}, {}",
-            account.available, account.held, total, account.locked
+        if result.is_err() {
+            return;
+        }
+        result = writeln!(
+            out,
+            "{}",
+            dialect.padded_row(&[
+                id.to_string(),
+                dialect.amount(account.available),
+                dialect.amount(account.held),
+                dialect.amount(total),
+                account.locked.to_string(),
+            ])
         );
-    });
-}
-
-fn read_transactions() -> Result<Vec<Transaction>, Box<dyn Error>> {
-    let input_file_path = std::env::args().skip(1).next().ok_or(io::Error::new(
-        io::ErrorKind::NotFound,
-        "must provide an input file path",
-    ))?;
-    let mut rdr = csv::Reader::from_path(input_file_path)?;
-
-    Ok(rdr
-        .records()
-        .filter_map(|result| {
-            let Ok(record) = result else {
-                return None;
-            };
-            let Some(transaction_type) = record.get(0) else {
-                return None;
-            };
-            let Some(Some(client_id)) = record.get(1).map(|s| s.trim().parse::<ClientId>().ok())
-            else {
-                return None;
-            };
-            let Some(Some(tx_id)) = record
-                .get(2)
-                .map(|s| s.trim().parse::<TransactionId>().ok())
-            else {
-                return None;
-            };
-            let amount = record
-                .get(3)
-                .map(|s| s.trim().parse::<f64>().ok())
-                .flatten();
-
-            match transaction_type {
-                "deposit" => amount.map(|amount| deposit(client_id, tx_id, amount)),
-                "withdrawal" => amount.map(|amount| withdraw(client_id, tx_id, amount)),
-                "dispute" => Some(dispute(client_id, tx_id)),
-                "resolve" => Some(resolve(client_id, tx_id)),
-                "chargeback" => Some(chargeback(client_id, tx_id)),
-                _ => None,
-            }
-        })
-        .collect())
+    })?;
+    result
+}
+
+fn print_expiring_disputes<A: AccountStore>(
+    out: &mut impl Write,
+    processor: &Processor<A>,
+    within: Timestamp,
+    dialect: Dialect,
+) -> io::Result<()> {
+    if dialect.has_header {
+        let header = ["client", "tx", "amount", "expires_at"];
+        writeln!(out, "{}", dialect.padded_row(&header))?;
+    }
+    for dispute in processor.expiring_disputes(within) {
+        writeln!(
+            out,
+            "{}",
+            dialect.padded_row(&[
+                dispute.client_id.to_string(),
+                dispute.tx_id.to_string(),
+                dialect.amount(dispute.amount),
+                dispute.expires_at.to_string(),
+            ])
+        )?;
+    }
+    Ok(())
+}
+
+fn print_validation(
+    out: &mut impl Write,
+    validation: &Validation,
+    changes: &[Change],
+    dialect: Dialect,
+) -> io::Result<()> {
+    writeln!(out, "{}", validation.stats)?;
+    writeln!(out, "\noutcomes:")?;
+    for (outcome, count) in &validation.outcomes {
+        writeln!(out, "    {outcome}: {count}")?;
+    }
+    writeln!(out, "\nfailed transactions:")?;
+    for (tx, outcome) in &validation.failed {
+        writeln!(out, "    {}: {}", describe(tx), outcome.kind())?;
+    }
+    let locked: Vec<String> = changes
+        .iter()
+        .filter(|change| change.locked)
+        .map(|change| change.client_id.to_string())
+        .collect();
+    writeln!(out, "\nclients that would be locked: {}", locked.join(", "))?;
+
+    writeln!(out, "\nbalance changes:")?;
+    if dialect.has_header {
+        let header = ["client", "available", "held", "total", "locked"];
+        writeln!(out, "{}", dialect.padded_row(&header))?;
+    }
+    for change in changes {
+        writeln!(
+            out,
+            "{}",
+            dialect.padded_row(&[
+                change.client_id.to_string(),
+                dialect.amount(change.available),
+                dialect.amount(change.held),
+                dialect.amount(change.total),
+                change.locked.to_string(),
+            ])
+        )?;
+    }
+    Ok(())
+}
+
+/// Transaction as it is named in reports, like `withdrawal 5 (id 7) of client 2`
+fn describe(tx: &Transaction) -> String {
+    let id = tx
+        .referencing_id()
+        .map(|id| format!(" (id {id})"))
+        .unwrap_or_default();
+    format!(
+        "{} {}{id} of client {}",
+        tx.detail.name(),
+        tx.input_tx_id(),
+        tx.client_id
+    )
+}
+
+/// Print records of flagged, rejected and skipped transactions to stderr
+/// (and of all transactions with debug log level)
+fn report(args: &Args, tx: &Transaction, record: &AuditRecord) {
+    let (verdict, reason) = match record {
+        AuditRecord::Flagged { reason } => ("flagged", reason.clone()),
+        AuditRecord::Rejected { reason } => ("rejected", reason.clone()),
+        AuditRecord::AlreadyApplied => ("skipped", "already applied".into()),
+        AuditRecord::ConflictsWithApplied => (
+            "skipped",
+            "conflicts with applied transaction with the same id".into(),
+        ),
+        _ if args.logs(LogLevel::Debug) => ("processed", format!("{record:?}")),
+        _ => return,
+    };
+    if args.logs(LogLevel::Warn) {
+        eprintln!("{verdict} {}: {reason}", describe(tx));
+    }
+}
+
+/// Print line of input file that was skipped as it is not a valid transaction
+fn report_skipped(args: &Args, path: impl Display, skipped: Skipped) {
+    if args.logs(LogLevel::Warn) {
+        eprintln!(
+            "skipped line {} of {path}: {}",
+            skipped.line, skipped.reason
+        );
+    }
+}
+
+/// Failure with path of the file it is about
+fn context(error: io::Error, path: impl Display) -> Failure {
+    Failure::from(error).context(path)
+}
+
+/// Config read from file given by option
+fn read_config<'a, T>(
+    args: &'a Args,
+    name: &str,
+    read: impl FnOnce(&'a str) -> io::Result<T>,
+) -> Result<Option<T>, Failure> {
+    args.option(name)
+        .map(|path| read(path).map_err(|e| context(e, path)))
+        .transpose()
+}
+
+/// CSV options from `--csv-config` file, overridden by `--csv-*` options
+fn csv_options(args: &Args) -> Result<CsvOptions, Failure> {
+    let mut options = read_config(args, "--csv-config", CsvOptions::read)?.unwrap_or_default();
+    let dialect = &mut options.dialect;
+    let char = |name: &str| -> Result<Option<u8>, Failure> {
+        args.option(name)
+            .map(|value| input::parse_char(value).map_err(|e| Failure::usage(e).context(name)))
+            .transpose()
+    };
+    if let Some(delimiter) = char("--csv-delimiter")? {
+        dialect.delimiter = delimiter;
+    }
+    if let Some(quote) = char("--csv-quote")? {
+        dialect.quote = quote;
+    }
+    if let Some(separator) = char("--csv-decimal-separator")? {
+        dialect.decimal_separator = separator;
+    }
+    if let Some(comment) = char("--csv-comment")? {
+        dialect.comment = Some(comment);
+    }
+    if args.flag("--csv-no-header") {
+        dialect.has_header = false;
+    }
+    if args.flag("--csv-no-trim") {
+        dialect.trim = false;
+    }
+    dialect.check().map_err(Failure::usage)?;
+    Ok(options)
+}
+
+fn dispute_expiry(args: &Args) -> Result<Option<DisputeExpiry>, Failure> {
+    let Some(max_duration) = args.parsed("--max-dispute-duration")? else {
+        return Ok(None);
+    };
+    let policy = match args.option("--expiry-policy") {
+        None | Some("resolve") => ExpiryPolicy::Resolve,
+        Some("chargeback") => ExpiryPolicy::ChargeBack,
+        Some(policy) => return Err(Failure::usage(format!("unknown expiry policy {policy}"))),
+    };
+
+    Ok(Some(DisputeExpiry {
+        max_duration,
+        policy,
+    }))
+}
+
+fn middleware(args: &Args) -> Result<Vec<Box<dyn Middleware>>, Failure> {
+    Ok(read_config(args, "--middleware", middleware::read)?.unwrap_or_default())
+}
+
+fn screening(args: &Args) -> Result<Vec<Box<dyn Screening>>, Failure> {
+    Ok(read_config(args, "--screening", screening::read)?.unwrap_or_default())
+}
+
+fn eviction_policy(args: &Args) -> Result<EvictionPolicy, Failure> {
+    Ok(EvictionPolicy {
+        max_age: args.parsed("--max-deposit-age")?,
+        max_per_account: args.parsed("--max-deposits-per-account")?,
+        max_total: args.parsed("--max-deposits")?,
+    })
+}
+
+/// Withdrawals in memory with the same eviction limits as deposits, but never spilled
+fn withdrawal_store(args: &Args) -> Result<MemoryReversibleStore, Failure> {
+    Ok(MemoryReversibleStore::new(eviction_policy(args)?))
+}
+
+fn deposit_store(args: &Args) -> Result<MemoryReversibleStore, Failure> {
+    let store = MemoryReversibleStore::new(eviction_policy(args)?);
+
+    Ok(match args.option("--spill-deposits") {
+        // Validation keeps deposits over the store, which never spills then,
+        // so the file is not created (or truncated)
+        Some(_) if args.command == Command::Validate => store,
+        Some(path) => store.with_spill_file(path).map_err(|e| context(e, path))?,
+        None => store,
+    })
+}
+
+fn applied_store(args: &Args) -> Result<Box<dyn AppliedStore>, Failure> {
+    Ok(match args.option("--applied") {
+        Some(path) => Box::new(FileAppliedStore::open(path).map_err(|e| context(e, path))?),
+        None => Box::default(),
+    })
 }
@@ -0,0 +1,266 @@
+use crate::{
+    account::{Account, AuditRecord},
+    money::{self, MoneyAmount},
+    stats,
+    transactions::Transaction,
+};
+use std::{
+    collections::BTreeMap,
+    fmt::Write as _,
+    io::{self, BufRead, BufReader, Write},
+    net::{TcpListener, TcpStream},
+    ops::{AddAssign, Sub},
+    sync::{Arc, Mutex, PoisonError},
+    thread::{self, JoinHandle},
+    time::Duration,
+};
+
+/// Upper bounds of processing latency buckets, in seconds
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.00001, 0.00005, 0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 1.0,
+];
+
+/// How long to wait for request of a client before giving up on it
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// State of all accounts that is exposed as gauges
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Gauges {
+    pub active_disputes: i64,
+    pub locked_accounts: i64,
+    pub held: MoneyAmount,
+}
+
+impl Gauges {
+    pub fn of(account: &Account) -> Self {
+        Self {
+            active_disputes: account.disputed_amounts.len() as i64,
+            locked_accounts: i64::from(account.locked),
+            held: account.held,
+        }
+    }
+}
+
+impl AddAssign for Gauges {
+    fn add_assign(&mut self, other: Self) {
+        self.active_disputes += other.active_disputes;
+        self.locked_accounts += other.locked_accounts;
+        self.held = self.held.try_change(other.held).unwrap_or(money::MAX);
+    }
+}
+
+impl Sub for Gauges {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Self {
+            active_disputes: self.active_disputes - other.active_disputes,
+            locked_accounts: self.locked_accounts - other.locked_accounts,
+            held: self.held.try_change(-other.held).unwrap_or(-money::MAX),
+        }
+    }
+}
+
+/// Metrics of processor, shared with the thread serving them
+pub type SharedMetrics = Arc<Mutex<Metrics>>;
+
+/// Counters, latency histogram and gauges of processed transactions
+#[derive(Debug, Default)]
+pub struct Metrics {
+    /// Processed transactions by type and outcome
+    transactions: BTreeMap<(&'static str, &'static str), u64>,
+
+    /// Number of processed transactions by latency bucket (the last one is for slower ones)
+    latency_buckets: [u64; LATENCY_BUCKETS.len() + 1],
+    latency_sum: f64,
+
+    gauges: Gauges,
+}
+
+impl Metrics {
+    pub fn shared() -> SharedMetrics {
+        Arc::new(Mutex::new(Self::default()))
+    }
+
+    /// Count transaction by its records and time it took, with gauges of accounts after it
+    pub fn observe(
+        &mut self,
+        tx: &Transaction,
+        records: &[AuditRecord],
+        took: Duration,
+        gauges: Gauges,
+    ) {
+        let outcome = stats::outcome(records).map_or("", AuditRecord::kind);
+        *self
+            .transactions
+            .entry((tx.detail.name(), outcome))
+            .or_default() += 1;
+
+        let seconds = took.as_secs_f64();
+        let bucket = LATENCY_BUCKETS.partition_point(|bound| *bound < seconds);
+        self.latency_buckets[bucket] += 1;
+        self.latency_sum += seconds;
+        self.gauges = gauges;
+    }
+
+    /// Metrics in Prometheus text exposition format
+    pub fn render(&self) -> String {
+        let mut out = String::new();
+        out.push_str("# HELP transactor_transactions_total Processed transactions.\n");
+        out.push_str("# TYPE transactor_transactions_total counter\n");
+        for ((kind, outcome), count) in &self.transactions {
+            let _ = writeln!(
+                out,
+                "transactor_transactions_total{{type=\"{kind}\",outcome=\"{outcome}\"}} {count}"
+            );
+        }
+
+        out.push_str("# HELP transactor_processing_seconds Time to process a transaction.\n");
+        out.push_str("# TYPE transactor_processing_seconds histogram\n");
+        let mut count = 0;
+        for (bound, bucket) in LATENCY_BUCKETS.iter().zip(self.latency_buckets) {
+            count += bucket;
+            let _ = writeln!(
+                out,
+                "transactor_processing_seconds_bucket{{le=\"{bound}\"}} {count}"
+            );
+        }
+        count += self.latency_buckets[LATENCY_BUCKETS.len()];
+        let _ = writeln!(
+            out,
+            "transactor_processing_seconds_bucket{{le=\"+Inf\"}} {count}"
+        );
+        let _ = writeln!(
+            out,
+            "transactor_processing_seconds_sum {}",
+            self.latency_sum
+        );
+        let _ = writeln!(out, "transactor_processing_seconds_count {count}");
+
+        let gauges = [
+            (
+                "active_disputes",
+                "Open disputes of all accounts.",
+                self.gauges.active_disputes.to_string(),
+            ),
+            (
+                "locked_accounts",
+                "Accounts locked by chargebacks.",
+                self.gauges.locked_accounts.to_string(),
+            ),
+            (
+                "held",
+                "Total held amount of all accounts.",
+                self.gauges.held.to_string(),
+            ),
+        ];
+        for (name, help, value) in gauges {
+            let _ = writeln!(out, "# HELP transactor_{name} {help}");
+            let _ = writeln!(out, "# TYPE transactor_{name} gauge");
+            let _ = writeln!(out, "transactor_{name} {value}");
+        }
+        out
+    }
+}
+
+/// Serve metrics on `GET /metrics` from a background thread, one request at a time
+pub fn serve(listener: TcpListener, metrics: SharedMetrics) -> JoinHandle<()> {
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            // A failing client should not stop metrics from being served to others
+            let _ = stream.and_then(|stream| respond(stream, &metrics));
+        }
+    })
+}
+
+fn respond(mut stream: TcpStream, metrics: &Mutex<Metrics>) -> io::Result<()> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(&stream);
+    let mut request = String::new();
+    reader.read_line(&mut request)?;
+    // Headers are not needed, but are read so that client is not reset while sending them
+    let mut header = String::new();
+    while reader.read_line(&mut header)? > 2 {
+        header.clear();
+    }
+
+    let mut parts = request.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => {
+            let metrics = metrics.lock().unwrap_or_else(PoisonError::into_inner);
+            ("200 OK", metrics.render())
+        }
+        (Some("GET"), _) => ("404 Not Found", "not found\n".to_string()),
+        _ => (
+            "405 Method Not Allowed",
+            "only GET is allowed\n".to_string(),
+        ),
+    };
+    write!(
+        stream,
+        "HTTP/1.1 {status}\r\n\
+         Content-Type: text/plain; version=0.0.4\r\n\
+         Content-Length: {}\r\n\
+         Connection: close\r\n\r\n{body}",
+        body.len()
+    )?;
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        processor::Processor,
+        transactions::{chargeback, deposit, dispute, withdraw},
+    };
+    use std::io::Read;
+
+    fn get(address: std::net::SocketAddr, path: &str) -> String {
+        let mut stream = TcpStream::connect(address).unwrap();
+        write!(stream, "GET {path} HTTP/1.1\r\nHost: localhost\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn serves_metrics_of_processor() {
+        let metrics = Metrics::shared();
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let address = listener.local_addr().unwrap();
+        serve(listener, metrics.clone());
+
+        let mut processor: Processor = Processor::default();
+        processor.metrics = Some(metrics);
+        let transactions = [
+            deposit(1, 1, 10),
+            deposit(2, 2, 5),
+            withdraw(2, 3, 7),
+            dispute(1, 1),
+            chargeback(1, 1),
+            dispute(2, 2),
+        ];
+        let _ = processor.process(&transactions).count();
+
+        let response = get(address, "/metrics");
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        assert!(head.starts_with("HTTP/1.1 200 OK\r\n"), "{head}");
+        assert!(head.contains(&format!("Content-Length: {}\r\n", body.len())));
+        for line in [
+            "transactor_transactions_total{type=\"deposit\",outcome=\"Processed\"} 2",
+            "transactor_transactions_total{type=\"withdrawal\",outcome=\"NotEnoughMoneyToWithdraw\"} 1",
+            "transactor_transactions_total{type=\"dispute\",outcome=\"Processed\"} 2",
+            "transactor_transactions_total{type=\"chargeback\",outcome=\"Processed\"} 1",
+            "transactor_processing_seconds_bucket{le=\"+Inf\"} 6",
+            "transactor_processing_seconds_count 6",
+            "transactor_active_disputes 1",
+            "transactor_locked_accounts 1",
+            "transactor_held 5.0000",
+        ] {
+            assert!(body.lines().any(|l| l == line), "{line} not in\n{body}");
+        }
+
+        assert!(get(address, "/other").starts_with("HTTP/1.1 404 Not Found\r\n"));
+    }
+}
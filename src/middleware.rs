@@ -0,0 +1,153 @@
+use crate::{
+    account::{Account, AuditRecord, ClientId},
+    config::{self, Section},
+    money::MoneyAmount,
+    transactions::{Transaction, TransactionDetail},
+};
+use std::{collections::HashSet, io, path::Path};
+
+/// Step of processing chain run by `Processor` around the core `Account` mutation.
+///
+/// Middleware runs in order before the transaction is applied
+/// (validators reject it, enrichers change it)
+/// and after it is applied (observers see its outcome).
+/// Journal keeps transactions as they were before middleware changed them.
+pub trait Middleware {
+    /// Check or change transaction before it is applied, `Err` rejects it with the reason
+    fn before(&mut self, _tx: &mut Transaction, _account: Option<&Account>) -> Result<(), String> {
+        Ok(())
+    }
+
+    /// See outcome of transaction and account state after it,
+    /// also called for transactions rejected by any step
+    fn after(&mut self, _tx: &Transaction, _record: &AuditRecord, _account: Option<&Account>) {}
+}
+
+/// Rejects deposits smaller than minimum
+pub struct MinimumDeposit {
+    pub min: MoneyAmount,
+}
+
+impl Middleware for MinimumDeposit {
+    fn before(&mut self, tx: &mut Transaction, _account: Option<&Account>) -> Result<(), String> {
+        match tx.detail {
+            TransactionDetail::Deposit { amount } if amount < self.min => {
+                Err(format!("deposit below minimum {}", self.min))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Rejects all transactions of given clients
+pub struct BlockedClients {
+    pub clients: HashSet<ClientId>,
+}
+
+impl Middleware for BlockedClients {
+    fn before(&mut self, tx: &mut Transaction, _account: Option<&Account>) -> Result<(), String> {
+        match self.clients.contains(&tx.client_id) {
+            true => Err(format!("client {} is blocked", tx.client_id)),
+            false => Ok(()),
+        }
+    }
+}
+
+/// Read middleware chain from config file like
+///
+/// ```text
+/// [blocked_clients]
+/// clients = 3 17
+///
+/// [minimum_deposit]
+/// min = 0.5
+/// ```
+///
+/// Middleware runs in order of sections.
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<Box<dyn Middleware>>> {
+    from_config(&config::read(path)?)
+}
+
+pub fn from_config(sections: &[Section]) -> io::Result<Vec<Box<dyn Middleware>>> {
+    sections
+        .iter()
+        .map(|section| {
+            let line = section.entries.first().map_or(0, |e| e.line);
+            let middleware: Box<dyn Middleware> = match section.name.as_str() {
+                "minimum_deposit" => Box::new(MinimumDeposit {
+                    min: setting(section, "min", line)?.parse()?,
+                }),
+                "blocked_clients" => {
+                    let entry = setting(section, "clients", line)?;
+                    let clients = entry
+                        .value
+                        .split_whitespace()
+                        .map(|id| id.parse())
+                        .collect::<Result<_, _>>()
+                        .map_err(|_| config::invalid(entry.line, "invalid client id"))?;
+                    Box::new(BlockedClients { clients })
+                }
+                name => return Err(config::invalid(line, format!("unknown middleware {name}"))),
+            };
+            Ok(middleware)
+        })
+        .collect()
+}
+
+fn setting<'a>(section: &'a Section, key: &str, line: usize) -> io::Result<&'a config::Entry> {
+    if let Some(entry) = section.entries.iter().find(|e| e.key != key) {
+        return Err(config::invalid(
+            entry.line,
+            format!("unknown setting {}", entry.key),
+        ));
+    }
+    section
+        .entries
+        .iter()
+        .find(|e| e.key == key)
+        .ok_or_else(|| config::invalid(line, format!("{} requires {key}", section.name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::{deposit, withdraw};
+
+    #[test]
+    fn reads_chain_in_order() {
+        let mut chain = from_config(
+            &config::parse("[blocked_clients]\nclients = 3 17\n[minimum_deposit]\nmin = 0.5")
+                .unwrap(),
+        )
+        .unwrap();
+
+        let mut reasons = |mut tx: Transaction| {
+            chain
+                .iter_mut()
+                .map(|middleware| middleware.before(&mut tx, None))
+                .find_map(Result::err)
+        };
+        assert_eq!(
+            reasons(deposit(17, 1, 0.4)),
+            Some("client 17 is blocked".into())
+        );
+        assert_eq!(
+            reasons(deposit(1, 2, 0.4)),
+            Some("deposit below minimum 0.5000".into())
+        );
+        assert_eq!(reasons(deposit(1, 3, 0.5)), None);
+        assert_eq!(reasons(withdraw(1, 4, 0.1)), None);
+    }
+
+    #[test]
+    fn rejects_invalid_config() {
+        for config in [
+            "[minimum_deposit]",
+            "[minimum_deposit]\nmin = 1\nmax = 2",
+            "[blocked_clients]\nclients = 1 x",
+            "[maximum_deposit]\nmax = 1",
+        ] {
+            assert!(from_config(&config::parse(config).unwrap()).is_err());
+        }
+    }
+}
@@ -1,4 +1,4 @@
-use std::ops::Neg;
+use std::{ops::Neg, str::FromStr};
 
 /// Integer type to be used for money amount with 4 decimal points.
 ///
@@ -24,7 +24,36 @@ impl MoneyAmount {
             self.0.checked_sub(-value)
         };
 
-        result.map(|result| MoneyAmount(result))
+        result.map(MoneyAmount)
+    }
+
+    /// Given percent of the amount, rounded to 4 decimals (halves away from zero).
+    /// Returns None if result does not fit.
+    pub fn percent(&self, percent: MoneyAmount) -> Option<Self> {
+        // amounts have 4 decimals each, percent is 1/100
+        let divisor = 10000 * 100;
+        let product = i128::from(self.0).checked_mul(i128::from(percent.0))?;
+        let rounded = (product.abs() + divisor / 2) / divisor * product.signum();
+        IntegerType::try_from(rounded).ok().map(MoneyAmount)
+    }
+
+    /// Share of the amount in proportion of part to whole, rounded toward zero
+    /// (so shares of parts never add up to more than the amount).
+    /// Returns None for zero whole.
+    pub fn share(&self, part: MoneyAmount, whole: MoneyAmount) -> Option<Self> {
+        let product = i128::from(self.0) * i128::from(part.0);
+        let share = product.checked_div(i128::from(whole.0))?;
+        IntegerType::try_from(share).ok().map(MoneyAmount)
+    }
+
+    /// Fixed point representation (amount multiplied by 10000)
+    pub(crate) fn to_raw(self) -> IntegerType {
+        self.0
+    }
+
+    /// Create from fixed point representation (amount multiplied by 10000)
+    pub(crate) fn from_raw(raw: IntegerType) -> Self {
+        Self(raw)
     }
 }
 
@@ -36,7 +65,7 @@ impl From<f64> for MoneyAmount {
 
 impl From<IntegerType> for MoneyAmount {
     fn from(value: IntegerType) -> Self {
-        Self((value * 10000).into())
+        Self(value * 10000)
     }
 }
 
@@ -59,6 +88,56 @@ impl std::fmt::Display for MoneyAmount {
     }
 }
 
+/// Money amount is not a decimal number with at most 4 decimal points
+#[derive(Debug, PartialEq)]
+pub struct ParseMoneyError;
+
+impl std::fmt::Display for ParseMoneyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("invalid money amount")
+    }
+}
+
+impl std::error::Error for ParseMoneyError {}
+
+/// Exact parsing of decimal numbers (unlike going through f64)
+impl FromStr for MoneyAmount {
+    type Err = ParseMoneyError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let s = s.trim();
+        let (negative, digits) = match s.strip_prefix('-') {
+            Some(digits) => (true, digits),
+            None => (false, s.strip_prefix('+').unwrap_or(s)),
+        };
+        let (whole, fraction) = digits.split_once('.').unwrap_or((digits, ""));
+
+        if (whole.is_empty() && fraction.is_empty())
+            || fraction.len() > 4
+            || !whole
+                .chars()
+                .chain(fraction.chars())
+                .all(|c| c.is_ascii_digit())
+        {
+            return Err(ParseMoneyError);
+        }
+
+        let whole = match whole {
+            "" => 0,
+            whole => whole.parse::<IntegerType>().map_err(|_| ParseMoneyError)?,
+        };
+        let fraction = format!("{fraction:0<4}")
+            .parse::<IntegerType>()
+            .map_err(|_| ParseMoneyError)?;
+        let value = whole
+            .checked_mul(10000)
+            .and_then(|whole| whole.checked_add(fraction))
+            .ok_or(ParseMoneyError)?;
+
+        Ok(Self(if negative { -value } else { value }))
+    }
+}
+
 impl PartialOrd for MoneyAmount {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         self.0.partial_cmp(&other.0)
@@ -77,7 +156,6 @@ impl PartialOrd<IntegerType> for MoneyAmount {
     }
 }
 
-#[cfg(test)]
 pub static MAX: MoneyAmount = MoneyAmount(IntegerType::MAX);
 
 #[cfg(test)]
@@ -94,6 +172,48 @@ mod tests {
         assert_eq!(MoneyAmount::from(123.4567).0, 1234567);
     }
 
+    #[test]
+    fn can_parse_from_string() {
+        assert_eq!("123.4567".parse(), Ok(MoneyAmount(1234567)));
+        assert_eq!(" -0.5 ".parse(), Ok(MoneyAmount(-5000)));
+        assert_eq!("7".parse(), Ok(MoneyAmount(70000)));
+        assert_eq!(".25".parse(), Ok(MoneyAmount(2500)));
+    }
+
+    #[test]
+    fn parsing_rejects_invalid_amounts() {
+        for invalid in ["", ".", "1.23456", "1e3", "1.-2", "abc", "922337203685478"] {
+            assert_eq!(
+                invalid.parse::<MoneyAmount>(),
+                Err(ParseMoneyError),
+                "{invalid}"
+            );
+        }
+    }
+
+    #[test]
+    fn percent_is_rounded() {
+        let percent = |amount: &str, percent: &str| {
+            MoneyAmount::from_str(amount)
+                .unwrap()
+                .percent(percent.parse().unwrap())
+        };
+        assert_eq!(percent("200", "1.5"), Some(MoneyAmount(30000)));
+        assert_eq!(percent("0.0150", "1"), Some(MoneyAmount(2)));
+        assert_eq!(percent("0.0149", "1"), Some(MoneyAmount(1)));
+        assert_eq!(percent("-0.0150", "1"), Some(MoneyAmount(-2)));
+        assert_eq!(MAX.percent(MoneyAmount::from(200)), None);
+    }
+
+    #[test]
+    fn share_is_rounded_toward_zero() {
+        let fee = MoneyAmount::from(5);
+        assert_eq!(fee.share(1.into(), 3.into()), Some(MoneyAmount(16666)));
+        assert_eq!(fee.share(3.into(), 3.into()), Some(fee));
+        assert_eq!(MAX.share(MAX, 1.into()), None);
+        assert_eq!(fee.share(1.into(), 0.into()), None);
+    }
+
     #[test]
     fn can_add_money() {
         assert_eq!(
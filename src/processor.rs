@@ -1,39 +1,971 @@
 use crate::{
-    account::{Account, AuditRecord, ClientId},
-    transactions::{Transaction, TransactionDetail},
+    account::{Account, AuditRecord, ClientId, Dispute, ExpiryPolicy},
+    account_store::AccountStore,
+    applied::{self, AppliedStore},
+    events::{Balance, Event, Subscriber},
+    fees::{FeeReversal, Fees},
+    journal::Journal,
+    lifecycle::{Action, Lifecycles, State},
+    limits::Limits,
+    metrics::{Gauges, SharedMetrics},
+    middleware::Middleware,
+    money::MoneyAmount,
+    reversible::{Lookup, Reversible, ReversibleStore},
+    screening::{Context, History, RecentTransaction, Screening, Verdict, HISTORY_LEN},
+    stats::Summary,
+    transactions::{Timestamp, Transaction, TransactionDetail, TransactionId},
 };
-use std::collections::HashMap;
+use std::{
+    borrow::Cow,
+    collections::{BTreeMap, HashMap},
+    io,
+    sync::PoisonError,
+    time::Instant,
+};
+
+/// Automatic expiry of disputes that never got resolved or charged back
+#[derive(Debug, Clone, Copy)]
+pub struct DisputeExpiry {
+    /// How long dispute can stay open, in seconds
+    pub max_duration: Timestamp,
+    pub policy: ExpiryPolicy,
+}
+
+/// Open dispute that is about to expire
+#[derive(Debug, PartialEq)]
+pub struct ExpiringDispute {
+    pub client_id: ClientId,
+    pub tx_id: TransactionId,
+    pub amount: MoneyAmount,
+    pub expires_at: Timestamp,
+}
 
 #[derive(Default)]
-pub struct Processor {
-    pub accounts: HashMap<ClientId, Account>,
+pub struct Processor<A = HashMap<ClientId, Account>> {
+    pub accounts: A,
+    pub dispute_expiry: Option<DisputeExpiry>,
+
+    /// Deposits that can still be disputed, refunded or reversed
+    pub deposits: Box<dyn ReversibleStore>,
+
+    /// Withdrawals that can still be reversed (in the same kind of store as deposits)
+    pub withdrawals: Box<dyn ReversibleStore>,
+
+    /// Dispute lifecycle states of deposits and withdrawals
+    pub lifecycles: Lifecycles,
+
+    /// Ids of processed transactions, to skip ones that are sent again
+    pub applied: Box<dyn AppliedStore>,
+
+    /// Log of transactions to rebuild state from
+    pub journal: Option<Journal>,
+
+    /// Velocity and amount limits checked before transactions are applied
+    pub limits: Limits,
+
+    /// Validators, enrichers and observers run around every transaction, in order
+    pub middleware: Vec<Box<dyn Middleware>>,
+
+    /// Rules checking transactions for suspicious patterns before they are applied
+    pub screening: Vec<Box<dyn Screening>>,
+
+    /// Fees charged on deposits and withdrawals
+    pub fees: Fees,
+
+    /// Records of fees charged or refunded by transaction being applied
+    fee_records: Vec<AuditRecord>,
+
+    /// Receivers of events about account changes
+    pub subscribers: Vec<Box<dyn Subscriber>>,
+
+    /// Events of transaction being processed, delivered once it is committed
+    events: Vec<Event>,
+
+    /// Statistics of processed transactions
+    pub summary: Summary,
+
+    /// Accounts created by transaction being processed
+    created_accounts: usize,
+
+    /// Metrics updated after every transaction, if they are served
+    pub metrics: Option<SharedMetrics>,
+
+    /// Gauges of all accounts, as of the last committed transaction
+    gauges: Gauges,
+
+    /// Change of gauges by transaction being processed
+    gauge_change: Gauges,
+
+    /// Recently processed transactions of each client (for screening)
+    history: HashMap<ClientId, History>,
+
+    /// Transaction being processed, kept in history once it is committed
+    recent: Option<(ClientId, RecentTransaction)>,
+
+    /// Latest seen transaction time
+    clock: Option<Timestamp>,
+
+    /// Disputed amounts of open disputes with known opening time, oldest first
+    open_disputes: BTreeMap<(Timestamp, ClientId, TransactionId), MoneyAmount>,
+
+    /// Previous amounts of open disputes changed by transaction being processed
+    uncommitted_disputes: Vec<((Timestamp, ClientId, TransactionId), Option<MoneyAmount>)>,
 }
 
-impl Processor {
+impl<A: AccountStore> Processor<A> {
+    /// Create processor over existing accounts (e.g. loaded from disk)
+    pub fn new(mut accounts: A) -> io::Result<Self> {
+        let mut open_disputes = BTreeMap::new();
+        let mut lifecycles = Lifecycles::default();
+        let mut gauges = Gauges::default();
+        accounts.for_each(|client_id, account| {
+            gauges += Gauges::of(account);
+            for (&tx_id, dispute) in &account.disputed_amounts {
+                lifecycles.change(client_id, tx_id, State::Disputed);
+                if let Some(opened_at) = dispute.opened_at {
+                    open_disputes.insert((opened_at, client_id, tx_id), dispute.amount);
+                }
+            }
+        })?;
+        lifecycles.commit();
+
+        Ok(Self {
+            accounts,
+            dispute_expiry: None,
+            deposits: Default::default(),
+            withdrawals: Default::default(),
+            lifecycles,
+            applied: Default::default(),
+            journal: None,
+            limits: Default::default(),
+            middleware: Vec::new(),
+            screening: Vec::new(),
+            fees: Default::default(),
+            fee_records: Vec::new(),
+            subscribers: Vec::new(),
+            events: Vec::new(),
+            summary: Summary::default(),
+            created_accounts: 0,
+            metrics: None,
+            gauges,
+            gauge_change: Gauges::default(),
+            history: HashMap::new(),
+            recent: None,
+            clock: None,
+            open_disputes,
+            uncommitted_disputes: Vec::new(),
+        })
+    }
+
     /// Process transactions and return AuditRecord for each
+    /// (and for each dispute that expired along the way)
     pub fn process<'a, T: IntoIterator<Item = &'a Transaction>>(
         &mut self,
         transactions: T,
-    ) -> impl Iterator<Item = AuditRecord> + use<'_, 'a, T> {
+    ) -> impl Iterator<Item = AuditRecord> + use<'_, 'a, T, A> {
         transactions
             .into_iter()
-            .map(|transaction| self.process_transaction(transaction))
-            .into_iter()
+            .flat_map(|transaction| self.process_transaction(transaction))
+    }
+
+    /// Process single transaction.
+    /// Returns records of disputes that expired by the time of transaction,
+    /// followed by AuditRecord of the transaction itself.
+    /// Storage commits transaction together with its audit records,
+    /// if that fails only `StorageError` is returned and state kept in memory
+    /// (clock, open disputes, lifecycles, limits and screening history) is rolled back too.
+    /// Transaction is journaled once it is committed,
+    /// if that fails `StorageError` follows its records.
+    /// Outcome is counted in `summary`.
+    pub fn process_transaction(&mut self, tx: &Transaction) -> Vec<AuditRecord> {
+        let started = Instant::now();
+        let audit = self.commit_transaction(tx);
+        let took = started.elapsed();
+        self.summary.count(tx, &audit, took);
+        if let Some(metrics) = &self.metrics {
+            let mut metrics = metrics.lock().unwrap_or_else(PoisonError::into_inner);
+            metrics.observe(tx, &audit, took, self.gauges);
+        }
+        audit
+    }
+
+    /// Apply, commit and journal single transaction
+    fn commit_transaction(&mut self, tx: &Transaction) -> Vec<AuditRecord> {
+        if self.accounts.begin().is_err() {
+            return vec![AuditRecord::StorageError];
+        }
+        self.deposits.begin();
+        self.withdrawals.begin();
+
+        let clock = self.clock;
+        let mut audit = Vec::new();
+        let committed = tx
+            .timestamp
+            .map_or(Ok(()), |now| self.advance_clock(now, &mut audit))
+            .and_then(|()| self.apply(tx, &mut audit))
+            .and_then(|()| self.accounts.commit(tx, &audit));
+
+        if committed.is_err() {
+            // Storage keeps whatever it could not roll back, nothing to do about it here
+            let _ = self.accounts.rollback();
+            self.deposits.rollback();
+            self.withdrawals.rollback();
+            self.applied.rollback();
+            self.lifecycles.rollback();
+            self.limits.rollback();
+            self.clock = clock;
+            for (key, amount) in self.uncommitted_disputes.drain(..).rev() {
+                match amount {
+                    Some(amount) => self.open_disputes.insert(key, amount),
+                    None => self.open_disputes.remove(&key),
+                };
+            }
+            self.recent = None;
+            self.fee_records.clear();
+            self.events.clear();
+            self.created_accounts = 0;
+            self.gauge_change = Gauges::default();
+            return vec![AuditRecord::StorageError];
+        }
+
+        // Accounts are stored already, so history that fails to follow them is only reported
+        let history = [self.deposits.commit(), self.withdrawals.commit()];
+        if history.iter().any(Result::is_err) {
+            audit.push(AuditRecord::StorageError);
+        }
+        self.applied.commit();
+        self.lifecycles.commit();
+        self.limits.commit();
+        self.uncommitted_disputes.clear();
+        if let Some((client_id, recent)) = self.recent.take() {
+            let history = self.history.entry(client_id).or_default();
+            if history.len() == HISTORY_LEN {
+                history.pop_front();
+            }
+            history.push_back(recent);
+        }
+        self.summary.accounts_created += std::mem::take(&mut self.created_accounts);
+        self.gauges += std::mem::take(&mut self.gauge_change);
+        for event in self.events.drain(..) {
+            self.summary.notify(&event);
+            for subscriber in &mut self.subscribers {
+                subscriber.notify(&event);
+            }
+        }
+
+        if let Some(journal) = &mut self.journal {
+            if journal.append(tx).is_err() {
+                audit.push(AuditRecord::StorageError);
+            }
+        }
+        audit
+    }
+
+    /// Make accounts durable, and then ids of transactions applied to them
+    pub fn persist(&mut self) -> io::Result<()> {
+        self.accounts.persist()?;
+        self.applied.persist()
+    }
+
+    /// List open disputes that expire within given number of seconds
+    /// from the latest seen transaction time, soonest first
+    pub fn expiring_disputes(&self, within: Timestamp) -> Vec<ExpiringDispute> {
+        let (Some(expiry), Some(now)) = (self.dispute_expiry, self.clock) else {
+            return Vec::new();
+        };
+        let horizon = now.saturating_add(within);
+
+        self.open_disputes
+            .iter()
+            .map(
+                |(&(opened_at, client_id, tx_id), &amount)| ExpiringDispute {
+                    client_id,
+                    tx_id,
+                    amount,
+                    expires_at: opened_at.saturating_add(expiry.max_duration),
+                },
+            )
+            .take_while(|dispute| dispute.expires_at <= horizon)
+            .collect()
+    }
+
+    /// Run transaction through middleware chain and apply it,
+    /// adding its AuditRecord (and screening flags) to audit.
+    /// Transactions with id are applied only once, whatever their outcome was.
+    fn apply(&mut self, tx: &Transaction, audit: &mut Vec<AuditRecord>) -> io::Result<()> {
+        let (id, hash) = (tx.id, applied::content_hash(tx));
+        let applied = match id {
+            Some(id) => self.applied.get(id)?,
+            None => None,
+        };
+        if let Some(applied) = applied {
+            audit.push(match applied == hash {
+                true => AuditRecord::AlreadyApplied,
+                false => AuditRecord::ConflictsWithApplied,
+            });
+            return Ok(());
+        }
+
+        // transaction is copied only to be changed by middleware
+        let mut tx = Cow::Borrowed(tx);
+        let mut rejection = None;
+        for middleware in &mut self.middleware {
+            let account = self.accounts.get(tx.client_id)?;
+            if let Err(reason) = middleware.before(tx.to_mut(), account) {
+                rejection = Some(AuditRecord::Rejected { reason });
+                break;
+            }
+        }
+
+        let (record, followers) = match rejection {
+            Some(record) => (record, Vec::new()),
+            None => self.execute(&tx)?,
+        };
+
+        if !self.middleware.is_empty() {
+            let account = self.accounts.get(tx.client_id)?;
+            for middleware in &mut self.middleware {
+                middleware.after(&tx, &record, account);
+            }
+        }
+
+        if let Some(id) = id {
+            self.applied.insert(id, hash)?;
+        }
+        audit.push(record);
+        audit.extend(followers);
+        Ok(())
+    }
+
+    /// Check limits and screening rules and apply transaction to account.
+    /// Returns AuditRecord of transaction and records following it (fees and screening flags).
+    fn execute(&mut self, tx: &Transaction) -> io::Result<(AuditRecord, Vec<AuditRecord>)> {
+        let at = tx.timestamp.or(self.clock);
+
+        if let Some(limit) = self.limits.check(tx.client_id, &tx.detail, at) {
+            return Ok((AuditRecord::LimitExceeded { limit }, Vec::new()));
+        }
+
+        let flags = match self.screen(tx, at)? {
+            Ok(flags) => flags,
+            Err(reason) => return Ok((AuditRecord::Rejected { reason }, Vec::new())),
+        };
+
+        let record = match tx.detail {
+            TransactionDetail::Deposit { amount } => {
+                self.deposit(tx.client_id, tx.input_tx_id(), amount, at)
+            }
+            TransactionDetail::Withdrawal { amount } => {
+                self.withdraw(tx.client_id, tx.input_tx_id(), amount, at)
+            }
+            TransactionDetail::Dispute { tx_id, amount } => {
+                self.dispute(tx.client_id, tx_id, amount, at)
+            }
+            TransactionDetail::Resolve { tx_id, amount } => {
+                self.resolve(tx.client_id, tx_id, amount, at)
+            }
+            TransactionDetail::ChargeBack { tx_id, amount } => {
+                self.chargeback(tx.client_id, tx_id, amount)
+            }
+            TransactionDetail::Refund { tx_id, amount } => self.refund(tx.client_id, tx_id, amount),
+            TransactionDetail::Reversal { tx_id } => self.reverse(tx.client_id, tx_id),
+        }?;
+
+        if record == AuditRecord::Processed {
+            self.limits.record(tx.client_id, &tx.detail, at);
+            self.remember(tx, at);
+        }
+        let mut followers = std::mem::take(&mut self.fee_records);
+        followers.extend(
+            flags
+                .into_iter()
+                .map(|reason| AuditRecord::Flagged { reason }),
+        );
+        Ok((record, followers))
+    }
+
+    /// Run screening rules, returns reasons to flag transaction or reason to reject it
+    fn screen(
+        &mut self,
+        tx: &Transaction,
+        at: Option<Timestamp>,
+    ) -> io::Result<Result<Vec<String>, String>> {
+        if self.screening.is_empty() {
+            return Ok(Ok(Vec::new()));
+        }
+
+        let no_history = History::new();
+        let context = Context {
+            tx,
+            at,
+            account: self.accounts.get(tx.client_id)?,
+            history: self.history.get(&tx.client_id).unwrap_or(&no_history),
+        };
+
+        let mut flags = Vec::new();
+        for rule in &mut self.screening {
+            match rule.screen(&context) {
+                Verdict::Allow => {}
+                Verdict::Flag { reason } => flags.push(reason),
+                Verdict::Reject { reason } => return Ok(Err(reason)),
+            }
+        }
+        Ok(Ok(flags))
+    }
+
+    /// Keep processed transaction in client history for screening, once it is committed
+    fn remember(&mut self, tx: &Transaction, at: Option<Timestamp>) {
+        if self.screening.is_empty() {
+            return;
+        }
+        let recent = RecentTransaction {
+            detail: tx.detail,
+            at,
+        };
+        self.recent = Some((tx.client_id, recent));
+    }
+
+    fn deposit(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        amount: MoneyAmount,
+        at: Option<Timestamp>,
+    ) -> io::Result<AuditRecord> {
+        let Some(fee) = self.fees.deposit_fee(amount) else {
+            return Ok(AuditRecord::MoneyOverflow);
+        };
+        if !self.can_credit(self.fees.house_account, fee)? {
+            return Ok(AuditRecord::MoneyOverflow);
+        }
+
+        // fee never exceeds deposited amount
+        let credited = amount.try_change(-fee).unwrap_or(amount);
+        let record = self.change_account(client_id, None, |account| account.deposit(credited))?;
+        if record == AuditRecord::Processed {
+            self.deposits.insert(
+                client_id,
+                tx_id,
+                Reversible {
+                    amount,
+                    fee,
+                    at,
+                    disputes: 0,
+                },
+            )?;
+            self.charge_fee(fee)?;
+        }
+        Ok(record)
+    }
+
+    /// Withdraw amount and fee from client account
+    fn withdraw(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        amount: MoneyAmount,
+        at: Option<Timestamp>,
+    ) -> io::Result<AuditRecord> {
+        let Some((fee, total)) = self
+            .fees
+            .withdrawal_fee(amount)
+            .and_then(|fee| Some((fee, amount.try_change(fee)?)))
+        else {
+            return Ok(AuditRecord::MoneyOverflow);
+        };
+        if !self.can_credit(self.fees.house_account, fee)? {
+            return Ok(AuditRecord::MoneyOverflow);
+        }
+
+        let record = self.change_account(client_id, None, |account| account.withdraw(total))?;
+        if record == AuditRecord::Processed {
+            self.withdrawals.insert(
+                client_id,
+                tx_id,
+                Reversible {
+                    amount,
+                    fee,
+                    at,
+                    disputes: 0,
+                },
+            )?;
+            self.charge_fee(fee)?;
+        }
+        Ok(record)
+    }
+
+    /// Return (part of) deposit that is not disputed to the sender
+    fn refund(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        part: Option<MoneyAmount>,
+    ) -> io::Result<AuditRecord> {
+        if let Err(record) = self.check_lifecycle(client_id, tx_id, Action::Refund)? {
+            return Ok(record);
+        }
+        let deposit = match self.deposits.get(client_id, tx_id)? {
+            Lookup::Found(deposit) if deposit.amount > 0 => deposit,
+            Lookup::Evicted => return Ok(AuditRecord::ReferencedTransactionEvicted),
+            Lookup::Found(_) | Lookup::NotFound => {
+                return Ok(AuditRecord::ReferencedTransactionNotFound)
+            }
+        };
+        let remaining = deposit.amount;
+        let amount = match part {
+            None => remaining,
+            Some(part) if part <= 0 => return Ok(AuditRecord::InvalidPartialAmount),
+            Some(part) if part > remaining => {
+                return Ok(AuditRecord::RefundExceedsDeposit { remaining })
+            }
+            Some(part) => part,
+        };
+
+        let disputed = self
+            .dispute_of(client_id, tx_id)?
+            .map(|dispute| dispute.amount);
+        // house keeps fee of the refunded part, client returns what it was credited
+        let kept = fee_share(&deposit, disputed, amount);
+        let credited = amount.try_change(-kept).unwrap_or(amount);
+        let record = self.change_account(client_id, None, |account| account.refund(credited))?;
+        if record == AuditRecord::Processed {
+            let deposit = Reversible {
+                amount: remaining.try_change(-amount).unwrap_or_default(),
+                fee: deposit.fee.try_change(-kept).unwrap_or_default(),
+                ..deposit
+            };
+            self.remember_deposit(client_id, tx_id, deposit)?;
+        }
+        Ok(record)
+    }
+
+    /// Void deposit (what is left of it after refunds) or withdrawal
+    fn reverse(&mut self, client_id: ClientId, tx_id: TransactionId) -> io::Result<AuditRecord> {
+        if let Err(record) = self.check_lifecycle(client_id, tx_id, Action::Reversal)? {
+            return Ok(record);
+        }
+
+        match self.deposits.get(client_id, tx_id)? {
+            Lookup::Found(deposit) if deposit.amount > 0 => {
+                // fee goes back from house account with the voided deposit,
+                // client returns what it was credited
+                let credited = deposit
+                    .amount
+                    .try_change(-deposit.fee)
+                    .unwrap_or(deposit.amount);
+                let record = self
+                    .change_account(client_id, None, |account| account.reverse_deposit(credited))?;
+                if record == AuditRecord::Processed {
+                    self.deposits.remove(client_id, tx_id)?;
+                    self.lifecycles.change(client_id, tx_id, State::Reversed);
+                    // fee that does not fit is left to be settled manually
+                    self.take_back_fee(deposit.fee)?;
+                }
+                return Ok(record);
+            }
+            Lookup::Evicted => return Ok(AuditRecord::ReferencedTransactionEvicted),
+            Lookup::Found(_) | Lookup::NotFound => {}
+        }
+
+        match self.withdrawals.get(client_id, tx_id)? {
+            Lookup::Found(withdrawal) => {
+                let record = self.change_account(client_id, None, |account| {
+                    account.reverse_withdrawal(withdrawal.amount)
+                })?;
+                if record == AuditRecord::Processed {
+                    self.withdrawals.remove(client_id, tx_id)?;
+                    self.lifecycles.change(client_id, tx_id, State::Reversed);
+                }
+                Ok(record)
+            }
+            Lookup::Evicted => Ok(AuditRecord::ReferencedTransactionEvicted),
+            Lookup::NotFound => Ok(AuditRecord::ReferencedTransactionNotFound),
+        }
+    }
+
+    fn can_credit(&mut self, client_id: ClientId, amount: MoneyAmount) -> io::Result<bool> {
+        Ok(amount == 0
+            || self
+                .accounts
+                .get(client_id)?
+                .is_none_or(|account| account.available.try_change(amount).is_some()))
+    }
+
+    /// Credit fee taken from client to house account
+    fn charge_fee(&mut self, fee: MoneyAmount) -> io::Result<()> {
+        if fee == 0 {
+            return Ok(());
+        }
+        self.change_account(self.fees.house_account, None, |account| {
+            account.deposit(fee)
+        })?;
+        self.fee_records.push(AuditRecord::FeeCharged { fee });
+        Ok(())
+    }
+
+    /// Settle fee share of charged back part of deposit (disputed before chargeback):
+    /// return it from house account to the client if policy says so, and store what is left
+    /// of the deposit while part of it is disputed (so that each part of the fee is refunded
+    /// at most once), forgetting it once it is closed by chargeback
+    fn charge_back_fee(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        part: MoneyAmount,
+        disputed: MoneyAmount,
+    ) -> io::Result<()> {
+        let Lookup::Found(deposit) = self.deposits.get(client_id, tx_id)? else {
+            return Ok(());
+        };
+        let fee = fee_share(&deposit, Some(disputed), part);
+        if self.dispute_of(client_id, tx_id)?.is_some() {
+            let left = Reversible {
+                fee: deposit.fee.try_change(-fee).unwrap_or_default(),
+                ..deposit
+            };
+            self.deposits.insert(client_id, tx_id, left)?;
+        } else {
+            // charged back deposit can not change anymore
+            self.deposits.remove(client_id, tx_id)?;
+        }
+        if self.fees.reversal == FeeReversal::Keep {
+            return Ok(());
+        }
+
+        // refund that does not fit is left to be settled manually
+        if self.can_credit(client_id, fee)? && self.take_back_fee(fee)? {
+            self.change_account(client_id, None, |account| account.deposit(fee))?;
+        }
+        Ok(())
+    }
+
+    /// Take deposit fee back from house account, `false` if it does not fit
+    fn take_back_fee(&mut self, fee: MoneyAmount) -> io::Result<bool> {
+        let house_account = self.fees.house_account;
+        let house_can_pay = self
+            .accounts
+            .get(house_account)?
+            .is_some_and(|house| house.available.try_change(-fee).is_some());
+        if fee == 0 || !house_can_pay {
+            return Ok(false);
+        }
+
+        self.change_account(house_account, None, |house| {
+            house.available = house.available.try_change(-fee).unwrap_or(house.available);
+            AuditRecord::Processed
+        })?;
+        self.fee_records.push(AuditRecord::FeeRefunded { fee });
+        Ok(true)
+    }
+
+    /// Dispute (part of) deposit that is not disputed yet
+    fn dispute(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        part: Option<MoneyAmount>,
+        opened_at: Option<Timestamp>,
+    ) -> io::Result<AuditRecord> {
+        if let Err(record) = self.check_lifecycle(client_id, tx_id, Action::Dispute)? {
+            return Ok(record);
+        }
+        let deposit = match self.deposits.get(client_id, tx_id)? {
+            Lookup::Found(deposit) if deposit.amount > 0 => deposit,
+            Lookup::Evicted => return Ok(AuditRecord::DisputedDepositEvicted),
+            Lookup::Found(_) | Lookup::NotFound => return Ok(AuditRecord::DisputedDepositNotFound),
+        };
+        let remaining = deposit.amount;
+        let amount = match part {
+            None => remaining,
+            Some(part) if part <= 0 => return Ok(AuditRecord::InvalidPartialAmount),
+            Some(part) if part > remaining => {
+                return Ok(AuditRecord::DisputeExceedsDeposit { remaining })
+            }
+            Some(part) => part,
+        };
+
+        let before = self.dispute_of(client_id, tx_id)?;
+        let opened = Event::DisputeOpened {
+            client_id,
+            tx_id,
+            amount,
+        };
+        let record = self.change_account(client_id, Some(opened), |account| {
+            account.dispute(tx_id, amount, opened_at)
+        })?;
+        if record != AuditRecord::Processed {
+            return Ok(record);
+        }
+
+        self.track_dispute(client_id, tx_id, before)?;
+        self.lifecycles.change(client_id, tx_id, State::Disputed);
+        let left = remaining.try_change(-amount).unwrap_or_default();
+        self.remember_deposit(
+            client_id,
+            tx_id,
+            Reversible {
+                amount: left,
+                disputes: deposit.disputes + u32::from(before.is_none()),
+                ..deposit
+            },
+        )?;
+        Ok(record)
+    }
+
+    /// Resolve (part of) dispute and make it disputable again
+    /// (as if it was deposited at given time if the deposit was evicted meanwhile)
+    fn resolve(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        part: Option<MoneyAmount>,
+        at: Option<Timestamp>,
+    ) -> io::Result<AuditRecord> {
+        if let Err(record) = self.check_lifecycle(client_id, tx_id, Action::Resolve)? {
+            return Ok(record);
+        }
+        let before = self.dispute_of(client_id, tx_id)?;
+        let amount = before.and_then(|dispute| dispute.part(part).ok());
+        let resolved = amount.map(|amount| Event::DisputeResolved {
+            client_id,
+            tx_id,
+            amount,
+        });
+        let record =
+            self.change_account(client_id, resolved, |account| account.resolve(tx_id, part))?;
+
+        if let Some(amount) = amount.filter(|_| record == AuditRecord::Processed) {
+            self.track_dispute(client_id, tx_id, before)?;
+            self.close_lifecycle(client_id, tx_id, State::Resolved)?;
+            let deposit = match self.deposits.get(client_id, tx_id)? {
+                Lookup::Found(deposit) => deposit,
+                Lookup::Evicted | Lookup::NotFound => Reversible {
+                    at,
+                    disputes: 1,
+                    ..Default::default()
+                },
+            };
+            let remaining = deposit.amount.try_change(amount).unwrap_or(amount);
+            self.deposits.insert(
+                client_id,
+                tx_id,
+                Reversible {
+                    amount: remaining,
+                    ..deposit
+                },
+            )?;
+        }
+        Ok(record)
+    }
+
+    /// Charge back (part of) dispute, charged back money can not be disputed again
+    fn chargeback(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        part: Option<MoneyAmount>,
+    ) -> io::Result<AuditRecord> {
+        if let Err(record) = self.check_lifecycle(client_id, tx_id, Action::ChargeBack)? {
+            return Ok(record);
+        }
+        let before = self.dispute_of(client_id, tx_id)?;
+        let amount = before.and_then(|dispute| dispute.part(part).ok());
+        let charged_back = amount.map(|amount| Event::ChargedBack {
+            client_id,
+            tx_id,
+            amount,
+        });
+        let record = self.change_account(client_id, charged_back, |account| {
+            account.chargeback(tx_id, part)
+        })?;
+
+        if let Some(amount) = amount.filter(|_| record == AuditRecord::Processed) {
+            self.track_dispute(client_id, tx_id, before)?;
+            self.close_lifecycle(client_id, tx_id, State::ChargedBack)?;
+            let disputed = before.map_or(amount, |dispute| dispute.amount);
+            self.charge_back_fee(client_id, tx_id, amount, disputed)?;
+        }
+        Ok(record)
+    }
+
+    /// Change account of client (created if it does not exist yet), queueing events
+    /// about balance change, given event (if change is processed) and locking of the account
+    fn change_account(
+        &mut self,
+        client_id: ClientId,
+        event: Option<Event>,
+        change: impl FnOnce(&mut Account) -> AuditRecord,
+    ) -> io::Result<AuditRecord> {
+        if self.accounts.get(client_id)?.is_none() {
+            self.created_accounts += 1;
+        }
+        let account = self.accounts.get_mut(client_id)?;
+        let (before, was_locked) = (Balance::from(&*account), account.locked);
+        let gauges = Gauges::of(account);
+        let record = change(account);
+        self.gauge_change += Gauges::of(account) - gauges;
+
+        let (after, locked) = (Balance::from(&*account), account.locked);
+        if before != after {
+            self.events.push(Event::BalanceChanged {
+                client_id,
+                before,
+                after,
+            });
+        }
+        if let Some(event) = event.filter(|_| record == AuditRecord::Processed) {
+            self.events.push(event);
+        }
+        if locked && !was_locked {
+            self.events.push(Event::AccountLocked { client_id });
+        }
+        Ok(record)
+    }
+
+    /// Move clock forward and apply expiry policy to disputes that were open for too long,
+    /// adding their records to audit.
+    /// Disputes that could not be closed are expired again with the next transaction.
+    fn advance_clock(&mut self, now: Timestamp, audit: &mut Vec<AuditRecord>) -> io::Result<()> {
+        let now = self.clock.map_or(now, |clock| clock.max(now));
+        self.clock = Some(now);
+
+        let Some(expiry) = self.dispute_expiry else {
+            return Ok(());
+        };
+        let Some(expired_since) = now.checked_sub(expiry.max_duration) else {
+            return Ok(());
+        };
+
+        let mut still_open = Vec::new();
+        while let Some((&key, &amount)) = self.open_disputes.first_key_value() {
+            let (opened_at, client_id, tx_id) = key;
+            if opened_at > expired_since {
+                break;
+            }
+            self.change_open_dispute(key, None);
+
+            let outcome = match expiry.policy {
+                ExpiryPolicy::Resolve => self.resolve(client_id, tx_id, None, Some(now))?,
+                ExpiryPolicy::ChargeBack => self.chargeback(client_id, tx_id, None)?,
+            };
+            if outcome != AuditRecord::Processed && self.dispute_of(client_id, tx_id)?.is_some() {
+                still_open.push((key, amount));
+            }
+            audit.push(AuditRecord::DisputeExpired {
+                client_id,
+                tx_id,
+                policy: expiry.policy,
+                outcome: Box::new(outcome),
+            });
+            audit.append(&mut self.fee_records);
+        }
+
+        for (key, amount) in still_open {
+            self.change_open_dispute(key, Some(amount));
+        }
+        Ok(())
     }
 
-    fn process_transaction(&mut self, tx: &Transaction) -> AuditRecord {
-        let account = self
+    fn dispute_of(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+    ) -> io::Result<Option<Dispute>> {
+        Ok(self
             .accounts
-            .entry(tx.client_id)
-            .or_insert_with(Default::default);
+            .get(client_id)?
+            .and_then(|account| account.disputed_amounts.get(&tx_id).copied()))
+    }
+
+    /// Check that action is allowed in dispute lifecycle of referenced transaction
+    fn check_lifecycle(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        action: Action,
+    ) -> io::Result<Result<(), AuditRecord>> {
+        let disputes = match self.deposits.get(client_id, tx_id)? {
+            Lookup::Found(deposit) => deposit.disputes,
+            Lookup::Evicted | Lookup::NotFound => 0,
+        };
+        Ok(self.lifecycles.check(client_id, tx_id, disputes, action))
+    }
+
+    /// Store what is left of deposit, forgetting it once nothing is left and no part of it
+    /// is disputed (fully disputed deposit is kept to be disputable again with its time)
+    fn remember_deposit(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        deposit: Reversible,
+    ) -> io::Result<()> {
+        if deposit.amount > 0 || self.dispute_of(client_id, tx_id)?.is_some() {
+            self.deposits.insert(client_id, tx_id, deposit)
+        } else {
+            self.deposits.remove(client_id, tx_id)
+        }
+    }
+
+    /// Move deposit to given state once no part of it is disputed anymore
+    fn close_lifecycle(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        closed: State,
+    ) -> io::Result<()> {
+        if self.dispute_of(client_id, tx_id)?.is_none() {
+            self.lifecycles.change(client_id, tx_id, closed);
+        }
+        Ok(())
+    }
 
-        match tx.detail {
-            TransactionDetail::Deposit { amount } => account.deposit(tx.id, amount),
-            TransactionDetail::Withdrawal { amount } => account.withdraw(amount),
-            TransactionDetail::Dispute { tx_id } => account.dispute(tx_id),
-            TransactionDetail::Resolve { tx_id } => account.resolve(tx_id),
-            TransactionDetail::ChargeBack { tx_id } => account.chargeback(tx_id),
+    /// Update expiry tracking of changed dispute (given as it was before the change)
+    fn track_dispute(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        before: Option<Dispute>,
+    ) -> io::Result<()> {
+        if let Some(opened_at) = before.and_then(|dispute| dispute.opened_at) {
+            self.change_open_dispute((opened_at, client_id, tx_id), None);
+        }
+        let after = self.dispute_of(client_id, tx_id)?;
+        if let Some((opened_at, dispute)) =
+            after.and_then(|dispute| Some((dispute.opened_at?, dispute)))
+        {
+            self.change_open_dispute((opened_at, client_id, tx_id), Some(dispute.amount));
         }
+        Ok(())
+    }
+
+    /// Set (or remove) amount of open dispute, remembering the previous one for rollback
+    fn change_open_dispute(
+        &mut self,
+        key: (Timestamp, ClientId, TransactionId),
+        amount: Option<MoneyAmount>,
+    ) {
+        let previous = match amount {
+            Some(amount) => self.open_disputes.insert(key, amount),
+            None => self.open_disputes.remove(&key),
+        };
+        self.uncommitted_disputes.push((key, previous));
+    }
+}
+
+/// Share of deposit fee for given part of what is left of the deposit (disputed or not),
+/// the whole fee for the last part
+fn fee_share(
+    deposit: &Reversible,
+    disputed: Option<MoneyAmount>,
+    part: MoneyAmount,
+) -> MoneyAmount {
+    let left = deposit
+        .amount
+        .try_change(disputed.unwrap_or_default())
+        .unwrap_or(deposit.amount);
+    match deposit.fee.share(part, left) {
+        Some(share) if share <= deposit.fee => share,
+        _ => deposit.fee,
     }
 }
 
@@ -43,24 +975,36 @@ mod tests {
     use super::*;
     use crate::{
         account::{account, Account, AuditRecord},
+        account_store::FileAccountStore,
+        applied::FileAppliedStore,
+        events::Balance,
+        fees::Fee,
+        journal,
+        limits::{Limit, Window},
+        middleware::{BlockedClients, MinimumDeposit},
         money::{self, MoneyAmount},
         processor::ClientId,
-        transactions::{chargeback, deposit, dispute, resolve, withdraw},
+        reversible::{EvictionPolicy, MemoryReversibleStore},
+        screening,
+        transactions::{chargeback, deposit, dispute, refund, resolve, reversal, withdraw},
     };
-    use std::collections::HashMap;
+    use std::{cell::RefCell, collections::HashMap, rc::Rc};
 
     /// Assert that after processing of given transactions
-    /// we get expected audit records and account states.
+    /// we get expected audit records, events and account states.
     fn assert_processing<'a, T: IntoIterator<Item = &'a Transaction>>(
         transactions: T,
         expected_audit: &[AuditRecord],
+        expected_events: &[Event],
         expected_accounts: impl Into<HashMap<ClientId, Account>>,
     ) {
-        let mut processor = Processor::default();
+        let mut processor: Processor = Processor::default();
+        let events = subscribe(&mut processor);
         let audit: Vec<AuditRecord> = processor.process(transactions).collect();
         let expected_accounts = expected_accounts.into();
 
         assert_eq!(audit, expected_audit);
+        assert_eq!(*events.borrow(), expected_events);
         assert_eq!(processor.accounts, expected_accounts);
     }
 
@@ -69,6 +1013,10 @@ mod tests {
         assert_processing(
             &[deposit(2, 100, 99.8765), deposit(1, 101, 12.1234)],
             &[AuditRecord::Processed, AuditRecord::Processed],
+            &[
+                balance_of_changed(2, (0, 0), (99.8765, 0)),
+                balance_changed((0, 0), (12.1234, 0)),
+            ],
             [
                 (1, account(12.1234, 0, false)),
                 (2, account(99.8765, 0, false)),
@@ -80,8 +1028,9 @@ mod tests {
     fn deposit_fails_on_negative_amounts() {
         let money = MoneyAmount::from(10);
         assert_processing(
-            &[deposit(1, 101, money), deposit(1, 101, -13)],
+            &[deposit(1, 101, money), deposit(1, 102, -13)],
             &[AuditRecord::Processed, AuditRecord::CanNotDepositNegative],
+            &[balance_changed((0, 0), (money, 0))],
             [(1, account(money, 0, false))],
         );
     }
@@ -90,8 +1039,9 @@ mod tests {
     fn deposit_fails_on_overflow() {
         let large = money::MAX.try_change(-100).unwrap();
         assert_processing(
-            &[deposit(1, 101, large), deposit(1, 101, 101)],
+            &[deposit(1, 101, large), deposit(1, 102, 101)],
             &[AuditRecord::Processed, AuditRecord::MoneyOverflow],
+            &[balance_changed((0, 0), (large, 0))],
             [(1, account(large, 0, false))],
         );
     }
@@ -101,6 +1051,10 @@ mod tests {
         assert_processing(
             &[deposit(1, 100, 12.1234), withdraw(1, 101, 2.12)],
             &[AuditRecord::Processed, AuditRecord::Processed],
+            &[
+                balance_changed((0, 0), (12.1234, 0)),
+                balance_changed((12.1234, 0), (10.0034, 0)),
+            ],
             [(1, account(10.0034, 0, false))],
         );
     }
@@ -110,6 +1064,7 @@ mod tests {
         assert_processing(
             &[deposit(1, 100, 12.1234), withdraw(1, 101, -3)],
             &[AuditRecord::Processed, AuditRecord::CanNotWithdrawNegative],
+            &[balance_changed((0, 0), (12.1234, 0))],
             [(1, account(12.1234, 0, false))],
         );
     }
@@ -122,6 +1077,7 @@ mod tests {
                 AuditRecord::Processed,
                 AuditRecord::NotEnoughMoneyToWithdraw,
             ],
+            &[balance_changed((0, 0), (12.1234, 0))],
             [(1, account(12.1234, 0, false))],
         );
     }
@@ -141,6 +1097,14 @@ mod tests {
                 AuditRecord::Processed,
                 AuditRecord::AccountLocked,
             ],
+            &[
+                balance_changed((0, 0), (13, 0)),
+                balance_changed((13, 0), (0, 13)),
+                dispute_opened(100, 13),
+                balance_changed((0, 13), (0, 0)),
+                charged_back(100, 13),
+                Event::AccountLocked { client_id: 1 },
+            ],
             [(1, account(0, 0, true))],
         );
     }
@@ -158,6 +1122,12 @@ mod tests {
                 AuditRecord::Processed,
                 AuditRecord::Processed,
             ],
+            &[
+                balance_changed((0, 0), (1000, 0)),
+                balance_changed((1000, 0), (1200, 0)),
+                balance_changed((1200, 0), (1000, 200)),
+                dispute_opened(101, 200),
+            ],
             [(1, account(1000, 200, false))],
         );
     }
@@ -179,6 +1149,12 @@ mod tests {
                 AuditRecord::DisputedDepositNotFound,
                 AuditRecord::DisputedDepositNotFound,
             ],
+            &[
+                balance_changed((0, 0), (1000, 0)),
+                balance_changed((1000, 0), (800, 0)),
+                balance_changed((800, 0), (-200, 1000)),
+                dispute_opened(100, 1000),
+            ],
             [(1, account(-200, 1000, false))],
         );
     }
@@ -192,6 +1168,11 @@ mod tests {
                 AuditRecord::Processed,
                 AuditRecord::DisputedDepositNotFound,
             ],
+            &[
+                balance_changed((0, 0), (1000, 0)),
+                balance_changed((1000, 0), (0, 1000)),
+                dispute_opened(100, 1000),
+            ],
             [(1, account(0, 1000, false))],
         );
     }
@@ -209,6 +1190,12 @@ mod tests {
                 AuditRecord::Processed,
                 AuditRecord::Processed,
             ],
+            &[
+                balance_changed((0, 0), (600, 0)),
+                balance_changed((600, 0), (100, 0)),
+                balance_changed((100, 0), (-500, 600)),
+                dispute_opened(100, 600),
+            ],
             [(1, account(-500, 600, false))],
         );
     }
@@ -222,6 +1209,13 @@ mod tests {
                 AuditRecord::Processed,
                 AuditRecord::Processed,
             ],
+            &[
+                balance_changed((0, 0), (1000, 0)),
+                balance_changed((1000, 0), (0, 1000)),
+                dispute_opened(100, 1000),
+                balance_changed((0, 1000), (1000, 0)),
+                dispute_resolved(100, 1000),
+            ],
             [(1, account(1000, 0, false))],
         );
     }
@@ -235,6 +1229,14 @@ mod tests {
                 AuditRecord::Processed,
                 AuditRecord::Processed,
             ],
+            &[
+                balance_changed((0, 0), (1000, 0)),
+                balance_changed((1000, 0), (0, 1000)),
+                dispute_opened(100, 1000),
+                balance_changed((0, 1000), (0, 0)),
+                charged_back(100, 1000),
+                Event::AccountLocked { client_id: 1 },
+            ],
             [(1, account(0, 0, true))],
         );
     }
@@ -252,9 +1254,1338 @@ mod tests {
                 AuditRecord::Processed,
                 AuditRecord::Processed,
                 AuditRecord::Processed,
-                AuditRecord::DisputeNotFound,
+                AuditRecord::IllegalTransition {
+                    state: State::ChargedBack,
+                    action: Action::ChargeBack,
+                },
+            ],
+            &[
+                balance_changed((0, 0), (1000, 0)),
+                balance_changed((1000, 0), (0, 1000)),
+                dispute_opened(100, 1000),
+                balance_changed((0, 1000), (0, 0)),
+                charged_back(100, 1000),
+                Event::AccountLocked { client_id: 1 },
             ],
             [(1, account(0, 0, true))],
         );
     }
+
+    #[test]
+    fn replayed_disputes_are_skipped() {
+        assert_processing(
+            &[
+                deposit(1, 100, 100),
+                dispute(1, 100).partial(30).with_id(101),
+                dispute(1, 100).partial(30).with_id(101),
+                resolve(1, 100).with_id(102),
+                resolve(1, 100).with_id(102),
+                dispute(1, 100).with_id(103),
+                dispute(2, 100).with_id(103),
+                dispute(1, 100).with_id(104),
+            ],
+            &[
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::AlreadyApplied,
+                AuditRecord::Processed,
+                AuditRecord::AlreadyApplied,
+                AuditRecord::Processed,
+                AuditRecord::ConflictsWithApplied,
+                AuditRecord::DisputedDepositNotFound,
+            ],
+            &[
+                balance_changed((0, 0), (100, 0)),
+                balance_changed((100, 0), (70, 30)),
+                dispute_opened(100, 30),
+                balance_changed((70, 30), (100, 0)),
+                dispute_resolved(100, 30),
+                balance_changed((100, 0), (0, 100)),
+                dispute_opened(100, 100),
+            ],
+            [(1, account(0, 100, false))],
+        );
+    }
+
+    #[test]
+    fn partial_disputes_of_one_deposit() {
+        assert_processing(
+            &[
+                deposit(1, 100, 100),
+                dispute(1, 100).partial(30),
+                dispute(1, 100).partial(50),
+                dispute(1, 100).partial(30),
+                dispute(1, 100).partial(-1),
+                dispute(1, 100),
+            ],
+            &[
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::DisputeExceedsDeposit {
+                    remaining: 20.into(),
+                },
+                AuditRecord::InvalidPartialAmount,
+                AuditRecord::Processed,
+            ],
+            &[
+                balance_changed((0, 0), (100, 0)),
+                balance_changed((100, 0), (70, 30)),
+                dispute_opened(100, 30),
+                balance_changed((70, 30), (20, 80)),
+                dispute_opened(100, 50),
+                balance_changed((20, 80), (0, 100)),
+                dispute_opened(100, 20),
+            ],
+            [(1, account(0, 100, false))],
+        );
+    }
+
+    #[test]
+    fn partial_resolve_makes_part_disputable_again() {
+        assert_processing(
+            &[
+                deposit(1, 100, 100),
+                dispute(1, 100).partial(60),
+                resolve(1, 100).partial(70),
+                resolve(1, 100).partial(20),
+                dispute(1, 100).partial(61),
+                dispute(1, 100).partial(60),
+                resolve(1, 100),
+            ],
+            &[
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::ExceedsDisputedAmount {
+                    disputed: 60.into(),
+                },
+                AuditRecord::Processed,
+                AuditRecord::DisputeExceedsDeposit {
+                    remaining: 60.into(),
+                },
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+            ],
+            &[
+                balance_changed((0, 0), (100, 0)),
+                balance_changed((100, 0), (40, 60)),
+                dispute_opened(100, 60),
+                balance_changed((40, 60), (60, 40)),
+                dispute_resolved(100, 20),
+                balance_changed((60, 40), (0, 100)),
+                dispute_opened(100, 60),
+                balance_changed((0, 100), (100, 0)),
+                dispute_resolved(100, 100),
+            ],
+            [(1, account(100, 0, false))],
+        );
+    }
+
+    #[test]
+    fn partial_chargeback_keeps_rest_of_dispute_open() {
+        let mut processor: Processor = Processor::default();
+        let audit: Vec<AuditRecord> = processor
+            .process(&[
+                deposit(1, 100, 100),
+                dispute(1, 100).partial(40),
+                chargeback(1, 100).partial(10),
+                chargeback(1, 100).partial(10),
+                dispute(1, 100).partial(61),
+            ])
+            .collect();
+
+        assert_eq!(
+            audit,
+            [
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::DisputeExceedsDeposit {
+                    remaining: 60.into(),
+                },
+            ]
+        );
+        assert_eq!(processor.accounts, [(1, account(60, 20, true))].into());
+        assert_eq!(
+            processor.accounts[&1].disputed_amounts[&100].amount,
+            MoneyAmount::from(20)
+        );
+    }
+
+    #[test]
+    fn refunds_are_bounded_by_deposit() {
+        assert_processing(
+            &[
+                deposit(1, 100, 100),
+                refund(1, 100).partial(30),
+                refund(1, 100).partial(80),
+                dispute(1, 100).partial(71),
+                dispute(1, 100).partial(50),
+                refund(1, 100),
+                refund(1, 101),
+            ],
+            &[
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::RefundExceedsDeposit {
+                    remaining: 70.into(),
+                },
+                AuditRecord::DisputeExceedsDeposit {
+                    remaining: 70.into(),
+                },
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::ReferencedTransactionNotFound,
+            ],
+            &[
+                balance_changed((0, 0), (100, 0)),
+                balance_changed((100, 0), (70, 0)),
+                balance_changed((70, 0), (20, 50)),
+                dispute_opened(100, 50),
+                balance_changed((20, 50), (0, 50)),
+            ],
+            [(1, account(0, 50, false))],
+        );
+    }
+
+    #[test]
+    fn refund_needs_available_money() {
+        assert_processing(
+            &[
+                deposit(1, 100, 100),
+                withdraw(1, 101, 90),
+                refund(1, 100).partial(20),
+            ],
+            &[
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::NotEnoughMoneyToRefund,
+            ],
+            &[
+                balance_changed((0, 0), (100, 0)),
+                balance_changed((100, 0), (10, 0)),
+            ],
+            [(1, account(10, 0, false))],
+        );
+    }
+
+    #[test]
+    fn reversals_void_deposits_and_withdrawals() {
+        assert_processing(
+            &[
+                deposit(1, 100, 100),
+                deposit(1, 101, 50),
+                withdraw(1, 102, 120),
+                refund(1, 100).partial(10),
+                dispute(1, 101),
+                reversal(1, 101),
+                reversal(1, 100),
+                reversal(1, 102),
+                reversal(1, 102),
+                dispute(1, 100),
+            ],
+            &[
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::IllegalTransition {
+                    state: State::Disputed,
+                    action: Action::Reversal,
+                },
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::IllegalTransition {
+                    state: State::Reversed,
+                    action: Action::Reversal,
+                },
+                AuditRecord::IllegalTransition {
+                    state: State::Reversed,
+                    action: Action::Dispute,
+                },
+            ],
+            &[
+                balance_changed((0, 0), (100, 0)),
+                balance_changed((100, 0), (150, 0)),
+                balance_changed((150, 0), (30, 0)),
+                balance_changed((30, 0), (20, 0)),
+                balance_changed((20, 0), (-30, 50)),
+                dispute_opened(101, 50),
+                balance_changed((-30, 50), (-120, 50)),
+                balance_changed((-120, 50), (0, 50)),
+            ],
+            [(1, account(0, 50, false))],
+        );
+    }
+
+    #[test]
+    fn redisputes_are_limited() {
+        let mut processor: Processor = Processor::default();
+        processor.lifecycles.max_redisputes = Some(1);
+        let audit: Vec<AuditRecord> = processor
+            .process(&[
+                deposit(1, 100, 100),
+                dispute(1, 100),
+                resolve(1, 100),
+                resolve(1, 100),
+                dispute(1, 100),
+                resolve(1, 100),
+                dispute(1, 100),
+            ])
+            .collect();
+
+        assert_eq!(
+            audit[3..],
+            [
+                AuditRecord::IllegalTransition {
+                    state: State::Resolved,
+                    action: Action::Resolve,
+                },
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::RedisputeLimitReached { max: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn partial_chargeback_keeps_deposit_disputed() {
+        let mut processor: Processor = Processor::default();
+        let audit: Vec<AuditRecord> = processor
+            .process(&[
+                deposit(1, 100, 100),
+                dispute(1, 100),
+                chargeback(1, 100).partial(40),
+                resolve(1, 100),
+                dispute(1, 100),
+                chargeback(1, 100),
+                resolve(1, 100),
+            ])
+            .collect();
+
+        assert_eq!(
+            audit[2..],
+            [
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::IllegalTransition {
+                    state: State::ChargedBack,
+                    action: Action::Resolve,
+                },
+            ]
+        );
+        assert_eq!(processor.accounts, [(1, account(0, 0, true))].into());
+    }
+
+    fn expiring_processor(policy: ExpiryPolicy) -> Processor {
+        Processor {
+            dispute_expiry: Some(DisputeExpiry {
+                max_duration: 100,
+                policy,
+            }),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn expired_dispute_is_resolved() {
+        let mut processor = expiring_processor(ExpiryPolicy::Resolve);
+        let audit: Vec<AuditRecord> = processor
+            .process(&[
+                deposit(1, 100, 1000.0).at(10),
+                dispute(1, 100).at(20),
+                deposit(1, 101, 5.0).at(119),
+                deposit(1, 102, 5.0).at(120),
+            ])
+            .collect();
+
+        assert_eq!(
+            audit,
+            [
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::DisputeExpired {
+                    client_id: 1,
+                    tx_id: 100,
+                    policy: ExpiryPolicy::Resolve,
+                    outcome: Box::new(AuditRecord::Processed),
+                },
+                AuditRecord::Processed,
+            ]
+        );
+        assert_eq!(processor.accounts, [(1, account(1010, 0, false))].into());
+    }
+
+    #[test]
+    fn expired_dispute_that_can_not_be_closed_expires_again() {
+        // Dispute holds more than account has, e.g. in accounts stored by an older version
+        let mut account = account(0, 0, false);
+        account.disputed_amounts.insert(
+            100,
+            Dispute {
+                amount: 10.into(),
+                opened_at: Some(0),
+            },
+        );
+        let mut processor = Processor::new(HashMap::from([(1, account)])).unwrap();
+        processor.dispute_expiry = expiring_processor(ExpiryPolicy::Resolve).dispute_expiry;
+
+        let expired = AuditRecord::DisputeExpired {
+            client_id: 1,
+            tx_id: 100,
+            policy: ExpiryPolicy::Resolve,
+            outcome: Box::new(AuditRecord::NotEnoughMoneyToRelease),
+        };
+        for tx_id in [101, 102] {
+            let audit: Vec<AuditRecord> =
+                processor.process(&[deposit(2, tx_id, 5).at(200)]).collect();
+            assert_eq!(audit, [expired.clone(), AuditRecord::Processed]);
+        }
+        assert_eq!(processor.expiring_disputes(0).len(), 1);
+    }
+
+    #[test]
+    fn expired_dispute_is_charged_back() {
+        let mut processor = expiring_processor(ExpiryPolicy::ChargeBack);
+        let audit: Vec<AuditRecord> = processor
+            .process(&[
+                deposit(1, 100, 1000.0).at(10),
+                dispute(1, 100).at(20),
+                deposit(2, 101, 5.0).at(500),
+            ])
+            .collect();
+
+        assert_eq!(
+            audit[2],
+            AuditRecord::DisputeExpired {
+                client_id: 1,
+                tx_id: 100,
+                policy: ExpiryPolicy::ChargeBack,
+                outcome: Box::new(AuditRecord::Processed),
+            }
+        );
+        assert_eq!(
+            processor.accounts,
+            [(1, account(0, 0, true)), (2, account(5, 0, false))].into()
+        );
+    }
+
+    #[test]
+    fn resolved_dispute_does_not_expire() {
+        let mut processor = expiring_processor(ExpiryPolicy::ChargeBack);
+        let audit: Vec<AuditRecord> = processor
+            .process(&[
+                deposit(1, 100, 1000.0).at(10),
+                dispute(1, 100).at(20),
+                resolve(1, 100).at(30),
+                deposit(1, 101, 5.0).at(500),
+            ])
+            .collect();
+
+        assert_eq!(
+            audit,
+            [
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+            ]
+        );
+        assert_eq!(processor.accounts, [(1, account(1005, 0, false))].into());
+    }
+
+    #[test]
+    fn lists_disputes_approaching_expiry() {
+        let mut processor = expiring_processor(ExpiryPolicy::Resolve);
+        processor
+            .process(&[
+                deposit(1, 100, 10.0).at(0),
+                deposit(2, 101, 20.0).at(0),
+                dispute(1, 100).at(10),
+                dispute(2, 101).at(50),
+                deposit(3, 102, 1.0).at(60),
+            ])
+            .for_each(drop);
+
+        assert_eq!(
+            processor.expiring_disputes(50),
+            [ExpiringDispute {
+                client_id: 1,
+                tx_id: 100,
+                amount: MoneyAmount::from(10),
+                expires_at: 110,
+            }]
+        );
+        assert_eq!(processor.expiring_disputes(90).len(), 2);
+    }
+
+    #[test]
+    fn dispute_of_evicted_deposit() {
+        let mut processor: Processor = Processor {
+            deposits: Box::new(MemoryReversibleStore::new(EvictionPolicy {
+                max_per_account: Some(1),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        let audit: Vec<AuditRecord> = processor
+            .process(&[
+                deposit(1, 100, 10.0),
+                deposit(1, 101, 20.0),
+                dispute(1, 100),
+                dispute(1, 102),
+                dispute(1, 101),
+            ])
+            .collect();
+
+        assert_eq!(
+            audit,
+            [
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::DisputedDepositEvicted,
+                AuditRecord::DisputedDepositNotFound,
+                AuditRecord::Processed,
+            ]
+        );
+        assert_eq!(processor.accounts, [(1, account(10, 20, false))].into());
+    }
+
+    #[test]
+    fn disputed_deposits_keep_their_time() {
+        let mut processor: Processor = Processor {
+            deposits: Box::new(MemoryReversibleStore::new(EvictionPolicy {
+                max_age: Some(100),
+                ..Default::default()
+            })),
+            ..Default::default()
+        };
+        let audit: Vec<AuditRecord> = processor
+            .process(&[
+                deposit(1, 100, 10).at(0),
+                deposit(1, 101, 10).at(10),
+                dispute(1, 100).partial(4).at(50),
+                resolve(1, 100).at(60),
+                dispute(1, 101).at(70),
+                resolve(1, 101).at(80),
+                deposit(1, 102, 1).at(105),
+                dispute(1, 100).at(106),
+                dispute(1, 101).at(106),
+            ])
+            .collect();
+
+        assert_eq!(
+            audit,
+            [
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::DisputedDepositEvicted,
+                AuditRecord::Processed,
+            ]
+        );
+        assert_eq!(processor.accounts, [(1, account(11, 10, false))].into());
+    }
+
+    #[test]
+    fn keeps_open_disputes_across_restarts() {
+        let path = std::env::temp_dir().join(format!(
+            "transactor-processor-restart-{}",
+            std::process::id()
+        ));
+        let mut processor = Processor::new(FileAccountStore::open(&path, 10).unwrap()).unwrap();
+        processor
+            .process(&[deposit(1, 100, 10.0).at(0), dispute(1, 100).at(10)])
+            .for_each(drop);
+        processor.accounts.persist().unwrap();
+        drop(processor);
+
+        let mut processor = Processor::new(FileAccountStore::open(&path, 10).unwrap()).unwrap();
+        processor.dispute_expiry = Some(DisputeExpiry {
+            max_duration: 100,
+            policy: ExpiryPolicy::ChargeBack,
+        });
+        let audit: Vec<AuditRecord> = processor.process(&[deposit(2, 101, 1.0).at(200)]).collect();
+
+        assert_eq!(
+            audit[0],
+            AuditRecord::DisputeExpired {
+                client_id: 1,
+                tx_id: 100,
+                policy: ExpiryPolicy::ChargeBack,
+                outcome: Box::new(AuditRecord::Processed),
+            }
+        );
+        assert_eq!(
+            processor.accounts.get(1).unwrap(),
+            Some(&account(0, 0, true))
+        );
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reprocessing_batch_across_restarts_is_safe() {
+        let path = std::env::temp_dir().join(format!(
+            "transactor-processor-applied-{}",
+            std::process::id()
+        ));
+        let batch = [
+            withdraw(1, 100, 5.0),
+            deposit(1, 101, 10.0),
+            dispute(1, 101).partial(2.0).with_id(102),
+        ];
+        let mut processor: Processor = Processor {
+            applied: Box::new(FileAppliedStore::open(&path).unwrap()),
+            ..Default::default()
+        };
+        let audit: Vec<AuditRecord> = processor.process(&batch).collect();
+        assert_eq!(
+            audit,
+            [
+                AuditRecord::NotEnoughMoneyToWithdraw,
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+            ]
+        );
+        processor.persist().unwrap();
+        drop(processor);
+
+        let mut processor: Processor = Processor {
+            applied: Box::new(FileAppliedStore::open(&path).unwrap()),
+            ..Default::default()
+        };
+        let audit: Vec<AuditRecord> = processor
+            .process(batch.iter().chain(&[deposit(1, 101, 11.0)]))
+            .collect();
+
+        assert_eq!(
+            audit,
+            [
+                AuditRecord::AlreadyApplied,
+                AuditRecord::AlreadyApplied,
+                AuditRecord::AlreadyApplied,
+                AuditRecord::ConflictsWithApplied,
+            ]
+        );
+        assert!(processor.accounts.is_empty());
+        std::fs::remove_file(path).unwrap();
+    }
+
+    /// Accounts in memory whose commits fail while `fail` is set, failed changes are rolled back
+    #[derive(Default)]
+    struct FailingCommits {
+        accounts: HashMap<ClientId, Account>,
+        committed: HashMap<ClientId, Account>,
+        fail: bool,
+    }
+
+    impl AccountStore for FailingCommits {
+        fn get(&mut self, client_id: ClientId) -> io::Result<Option<&Account>> {
+            Ok(self.accounts.get(&client_id))
+        }
+
+        fn get_mut(&mut self, client_id: ClientId) -> io::Result<&mut Account> {
+            Ok(self.accounts.entry(client_id).or_default())
+        }
+
+        fn for_each(&mut self, f: impl FnMut(ClientId, &Account)) -> io::Result<()> {
+            self.accounts.for_each(f)
+        }
+
+        fn persist(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+
+        fn begin(&mut self) -> io::Result<()> {
+            self.committed = self.accounts.clone();
+            Ok(())
+        }
+
+        fn commit(&mut self, _tx: &Transaction, _audit: &[AuditRecord]) -> io::Result<()> {
+            match self.fail {
+                true => Err(io::Error::other("commit failed")),
+                false => Ok(()),
+            }
+        }
+
+        fn rollback(&mut self) -> io::Result<()> {
+            self.accounts = std::mem::take(&mut self.committed);
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn transactions_failing_to_commit_are_not_journaled() {
+        let path = std::env::temp_dir().join(format!(
+            "transactor-processor-journal-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut processor = Processor::new(FailingCommits::default()).unwrap();
+        processor.journal = Some(Journal::open(&path).unwrap());
+        processor.accounts.fail = true;
+        let audit: Vec<AuditRecord> = processor.process(&[deposit(1, 100, 10.0)]).collect();
+        assert_eq!(audit, [AuditRecord::StorageError]);
+
+        processor.accounts.fail = false;
+        let audit: Vec<AuditRecord> = processor.process(&[deposit(1, 101, 1.0)]).collect();
+        assert_eq!(audit, [AuditRecord::Processed]);
+
+        let entries = journal::read(&path).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].0, 1);
+        assert_eq!(entries[0].1.input_tx_id(), 101);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn failing_commit_rolls_back_state_kept_in_memory() {
+        let mut processor = Processor::new(FailingCommits::default()).unwrap();
+        processor.dispute_expiry = Some(DisputeExpiry {
+            max_duration: 10,
+            policy: ExpiryPolicy::ChargeBack,
+        });
+        processor.limits.default.max_transactions_per_window = Some(2);
+        processor
+            .process(&[deposit(1, 100, 10).at(0), dispute(1, 100).at(1)])
+            .for_each(drop);
+
+        // Would expire the dispute, charge it back and count towards the limit
+        processor.accounts.fail = true;
+        let audit: Vec<AuditRecord> = processor.process(&[deposit(1, 101, 5).at(20)]).collect();
+        assert_eq!(audit, [AuditRecord::StorageError]);
+        assert_eq!(processor.expiring_disputes(10).len(), 1);
+
+        processor.accounts.fail = false;
+        let audit: Vec<AuditRecord> = processor
+            .process(&[resolve(1, 100).at(5), deposit(1, 102, 1).at(6)])
+            .collect();
+        assert_eq!(audit, [AuditRecord::Processed, AuditRecord::Processed]);
+        assert_eq!(
+            processor.accounts.accounts,
+            [(1, account(11, 0, false))].into()
+        );
+
+        let audit: Vec<AuditRecord> = processor
+            .process(&[dispute(1, 101).at(7), reversal(1, 101).at(8)])
+            .collect();
+        assert_eq!(
+            audit,
+            [
+                AuditRecord::DisputedDepositNotFound,
+                AuditRecord::ReferencedTransactionNotFound
+            ]
+        );
+    }
+
+    #[test]
+    fn transactions_failing_to_commit_are_not_remembered_as_applied() {
+        let path = std::env::temp_dir().join(format!(
+            "transactor-processor-uncommitted-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut processor = Processor::new(FailingCommits::default()).unwrap();
+        processor.applied = Box::new(FileAppliedStore::open(&path).unwrap());
+        processor.accounts.fail = true;
+        let audit: Vec<AuditRecord> = processor.process(&[deposit(1, 100, 10.0)]).collect();
+        assert_eq!(audit, [AuditRecord::StorageError]);
+
+        processor.accounts.fail = false;
+        let audit: Vec<AuditRecord> = processor
+            .process(&[deposit(1, 100, 10.0), deposit(1, 101, 1.0)])
+            .collect();
+        assert_eq!(audit, [AuditRecord::Processed, AuditRecord::Processed]);
+
+        // Run stops before it is persisted, its transactions are processed again
+        drop(processor);
+        let mut processor: Processor = Processor {
+            applied: Box::new(FileAppliedStore::open(&path).unwrap()),
+            ..Default::default()
+        };
+        let audit: Vec<AuditRecord> = processor.process(&[deposit(1, 100, 10.0)]).collect();
+        assert_eq!(audit, [AuditRecord::Processed]);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn withdrawals_over_limit_are_rejected() {
+        let mut processor: Processor = Processor::default();
+        processor.limits.default.max_withdrawal = Some(MoneyAmount::from(100));
+        processor.limits.default.max_withdrawn_per_window = Some(MoneyAmount::from(150));
+        let audit: Vec<AuditRecord> = processor
+            .process(&[
+                deposit(1, 100, 1000.0).at(0),
+                withdraw(1, 101, 101.0).at(1),
+                withdraw(1, 102, 100.0).at(2),
+                withdraw(1, 103, 60.0).at(3),
+                withdraw(1, 104, 60.0).at(86402),
+            ])
+            .collect();
+
+        assert_eq!(
+            audit,
+            [
+                AuditRecord::Processed,
+                AuditRecord::LimitExceeded {
+                    limit: Limit::Withdrawal {
+                        max: MoneyAmount::from(100)
+                    }
+                },
+                AuditRecord::Processed,
+                AuditRecord::LimitExceeded {
+                    limit: Limit::WithdrawnInWindow {
+                        max: MoneyAmount::from(150),
+                        window: Window::Rolling(86400)
+                    }
+                },
+                AuditRecord::Processed,
+            ]
+        );
+        assert_eq!(processor.accounts, [(1, account(840, 0, false))].into());
+    }
+
+    #[test]
+    fn screening_flags_and_rejects_transactions() {
+        let mut processor: Processor = Processor {
+            screening: vec![
+                Box::new(screening::RapidWithdrawal::default()),
+                Box::new(screening::DisputeBurst {
+                    max: 1,
+                    action: screening::Action::Reject,
+                    ..Default::default()
+                }),
+            ],
+            ..Default::default()
+        };
+        let audit: Vec<AuditRecord> = processor
+            .process(&[
+                deposit(1, 100, 10.0).at(0),
+                deposit(1, 101, 10.0).at(1),
+                withdraw(1, 102, 20.0).at(2),
+                withdraw(1, 103, 20.0).at(3),
+                dispute(1, 100).at(4),
+                dispute(1, 101).at(5),
+            ])
+            .collect();
+
+        assert_eq!(
+            audit,
+            [
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::Flagged {
+                    reason: "withdrawal of 20.0000 deposited within 3600s".into()
+                },
+                AuditRecord::NotEnoughMoneyToWithdraw,
+                AuditRecord::Processed,
+                AuditRecord::Rejected {
+                    reason: "more than 1 disputes within 86400s".into()
+                },
+            ]
+        );
+    }
+
+    type Seen = Vec<(TransactionId, Option<Timestamp>, AuditRecord)>;
+
+    /// Sets missing timestamps and records what it sees after transactions
+    #[derive(Default)]
+    struct Stamper {
+        seen: Rc<RefCell<Seen>>,
+    }
+
+    impl Middleware for Stamper {
+        fn before(
+            &mut self,
+            tx: &mut Transaction,
+            _account: Option<&Account>,
+        ) -> Result<(), String> {
+            tx.timestamp = tx.timestamp.or(Some(42));
+            Ok(())
+        }
+
+        fn after(&mut self, tx: &Transaction, record: &AuditRecord, _account: Option<&Account>) {
+            self.seen
+                .borrow_mut()
+                .push((tx.input_tx_id(), tx.timestamp, record.clone()));
+        }
+    }
+
+    #[test]
+    fn journal_keeps_transactions_before_middleware() {
+        let path = std::env::temp_dir().join(format!(
+            "transactor-processor-middleware-journal-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_file(&path);
+        let mut processor: Processor = Processor {
+            middleware: vec![Box::new(Stamper::default())],
+            journal: Some(Journal::open(&path).unwrap()),
+            ..Default::default()
+        };
+        processor
+            .process(&[deposit(1, 100, 10.0), deposit(1, 101, 5.0).at(7)])
+            .for_each(drop);
+
+        let entries = journal::read(&path).unwrap();
+        let timestamps: Vec<Option<Timestamp>> =
+            entries.iter().map(|(_, tx)| tx.timestamp).collect();
+        assert_eq!(timestamps, [None, Some(7)]);
+
+        // replay runs the same middleware again
+        let stamper = Stamper::default();
+        let seen = stamper.seen.clone();
+        let mut replayed: Processor = Processor {
+            middleware: vec![Box::new(stamper)],
+            ..Default::default()
+        };
+        journal::replay(&mut replayed, &entries, None);
+        assert_eq!(replayed.accounts, processor.accounts);
+        assert_eq!(seen.borrow()[0].1, Some(42));
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn middleware_runs_in_order_around_transactions() {
+        let stamper = Stamper::default();
+        let seen = stamper.seen.clone();
+        let mut processor: Processor = Processor {
+            middleware: vec![
+                Box::new(BlockedClients {
+                    clients: [2].into(),
+                }),
+                Box::new(stamper),
+                Box::new(MinimumDeposit { min: 1.into() }),
+            ],
+            ..Default::default()
+        };
+        let blocked = AuditRecord::Rejected {
+            reason: "client 2 is blocked".into(),
+        };
+        let too_small = AuditRecord::Rejected {
+            reason: "deposit below minimum 1.0000".into(),
+        };
+        let audit: Vec<AuditRecord> = processor
+            .process(&[
+                deposit(1, 100, 10.0),
+                deposit(2, 101, 10.0),
+                deposit(1, 102, 0.5).at(7),
+            ])
+            .collect();
+
+        assert_eq!(
+            audit,
+            [AuditRecord::Processed, blocked.clone(), too_small.clone()]
+        );
+        // blocked transaction never reached stamper, but it still observes it
+        assert_eq!(
+            *seen.borrow(),
+            [
+                (100, Some(42), AuditRecord::Processed),
+                (101, None, blocked),
+                (102, Some(7), too_small),
+            ]
+        );
+        assert_eq!(processor.accounts, [(1, account(10, 0, false))].into());
+    }
+
+    /// Collect events emitted by processor
+    fn subscribe(processor: &mut Processor) -> Rc<RefCell<Vec<Event>>> {
+        let events = Rc::new(RefCell::new(Vec::new()));
+        let received = events.clone();
+        processor.subscribers = vec![Box::new(move |event: &Event| {
+            received.borrow_mut().push(*event)
+        })];
+        events
+    }
+
+    /// Events emitted while processing given transactions
+    fn events_of(processor: &mut Processor, transactions: &[Transaction]) -> Vec<Event> {
+        let events = subscribe(processor);
+        processor.process(transactions).for_each(drop);
+        events.take()
+    }
+
+    fn balance_changed(
+        before: (impl Into<MoneyAmount>, impl Into<MoneyAmount>),
+        after: (impl Into<MoneyAmount>, impl Into<MoneyAmount>),
+    ) -> Event {
+        balance_of_changed(1, before, after)
+    }
+
+    fn balance_of_changed(
+        client_id: ClientId,
+        before: (impl Into<MoneyAmount>, impl Into<MoneyAmount>),
+        after: (impl Into<MoneyAmount>, impl Into<MoneyAmount>),
+    ) -> Event {
+        Event::BalanceChanged {
+            client_id,
+            before: Balance {
+                available: before.0.into(),
+                held: before.1.into(),
+            },
+            after: Balance {
+                available: after.0.into(),
+                held: after.1.into(),
+            },
+        }
+    }
+
+    fn dispute_opened(tx_id: TransactionId, amount: i64) -> Event {
+        Event::DisputeOpened {
+            client_id: 1,
+            tx_id,
+            amount: amount.into(),
+        }
+    }
+
+    fn dispute_resolved(tx_id: TransactionId, amount: i64) -> Event {
+        Event::DisputeResolved {
+            client_id: 1,
+            tx_id,
+            amount: amount.into(),
+        }
+    }
+
+    fn charged_back(tx_id: TransactionId, amount: i64) -> Event {
+        Event::ChargedBack {
+            client_id: 1,
+            tx_id,
+            amount: amount.into(),
+        }
+    }
+
+    #[test]
+    fn deposits_and_withdrawals_change_balance() {
+        let events = events_of(
+            &mut Processor::default(),
+            &[
+                deposit(1, 100, 12),
+                withdraw(1, 101, 2),
+                withdraw(1, 102, 20),
+            ],
+        );
+
+        assert_eq!(
+            events,
+            [
+                balance_changed((0, 0), (12, 0)),
+                balance_changed((12, 0), (10, 0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn failed_transactions_emit_no_events() {
+        let events = events_of(
+            &mut Processor::default(),
+            &[
+                deposit(1, 100, -13),
+                withdraw(1, 101, 1),
+                dispute(1, 100),
+                resolve(1, 100),
+                chargeback(1, 100),
+            ],
+        );
+
+        assert_eq!(events, []);
+    }
+
+    #[test]
+    fn dispute_and_resolve_events() {
+        let events = events_of(
+            &mut Processor::default(),
+            &[deposit(1, 100, 1000), dispute(1, 100), resolve(1, 100)],
+        );
+
+        assert_eq!(
+            events,
+            [
+                balance_changed((0, 0), (1000, 0)),
+                balance_changed((1000, 0), (0, 1000)),
+                dispute_opened(100, 1000),
+                balance_changed((0, 1000), (1000, 0)),
+                dispute_resolved(100, 1000),
+            ]
+        );
+    }
+
+    #[test]
+    fn chargeback_events() {
+        let events = events_of(
+            &mut Processor::default(),
+            &[
+                deposit(1, 100, 1000),
+                dispute(1, 100),
+                chargeback(1, 100),
+                chargeback(1, 100),
+                withdraw(1, 101, 1),
+            ],
+        );
+
+        assert_eq!(
+            events[3..],
+            [
+                balance_changed((0, 1000), (0, 0)),
+                charged_back(100, 1000),
+                Event::AccountLocked { client_id: 1 },
+            ]
+        );
+    }
+
+    #[test]
+    fn expired_dispute_events() {
+        let events = events_of(
+            &mut expiring_processor(ExpiryPolicy::Resolve),
+            &[
+                deposit(1, 100, 1000).at(10),
+                dispute(1, 100).at(20),
+                deposit(1, 101, 5).at(120),
+            ],
+        );
+
+        assert_eq!(
+            events[3..],
+            [
+                balance_changed((0, 1000), (1000, 0)),
+                dispute_resolved(100, 1000),
+                balance_changed((1000, 0), (1005, 0)),
+            ]
+        );
+    }
+
+    #[test]
+    fn events_can_be_sent_to_channel() {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let mut processor: Processor = Processor {
+            subscribers: vec![Box::new(sender)],
+            ..Default::default()
+        };
+        let handle = std::thread::spawn(move || receiver.iter().count());
+
+        processor
+            .process(&[deposit(1, 100, 10), dispute(1, 100), chargeback(1, 100)])
+            .for_each(drop);
+        drop(processor);
+
+        assert_eq!(handle.join().unwrap(), 6);
+    }
+
+    fn processor_with_fees(reversal: FeeReversal) -> Processor {
+        Processor {
+            fees: Fees {
+                deposit: Some(Fee {
+                    percent: 1.into(),
+                    ..Default::default()
+                }),
+                withdrawal: Some(Fee {
+                    flat: 1.into(),
+                    ..Default::default()
+                }),
+                house_account: 99,
+                reversal,
+            },
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn fees_are_credited_to_house_account() {
+        let mut processor = processor_with_fees(FeeReversal::Keep);
+        let audit: Vec<AuditRecord> = processor
+            .process(&[
+                deposit(1, 100, 100),
+                withdraw(1, 101, 98.5),
+                withdraw(1, 102, 98),
+            ])
+            .collect();
+
+        assert_eq!(
+            audit,
+            [
+                AuditRecord::Processed,
+                AuditRecord::FeeCharged { fee: 1.into() },
+                AuditRecord::NotEnoughMoneyToWithdraw,
+                AuditRecord::Processed,
+                AuditRecord::FeeCharged { fee: 1.into() },
+            ]
+        );
+        assert_eq!(
+            processor.accounts,
+            [(1, account(0, 0, false)), (99, account(2, 0, false))].into()
+        );
+    }
+
+    #[test]
+    fn deposit_fee_reversal_on_chargeback() {
+        let transactions = [deposit(1, 100, 100), dispute(1, 100), chargeback(1, 100)];
+
+        // dispute holds the whole deposit while only 99 was credited, so the fee is owed
+        let mut processor = processor_with_fees(FeeReversal::Keep);
+        processor.process(&transactions).for_each(drop);
+        assert_eq!(
+            processor.accounts,
+            [(1, account(-1, 0, true)), (99, account(1, 0, false))].into()
+        );
+
+        let mut processor = processor_with_fees(FeeReversal::Refund);
+        let audit: Vec<AuditRecord> = processor.process(&transactions).collect();
+        assert_eq!(
+            audit[3..],
+            [
+                AuditRecord::Processed,
+                AuditRecord::FeeRefunded { fee: 1.into() }
+            ]
+        );
+        assert_eq!(
+            processor.accounts,
+            [(1, account(0, 0, true)), (99, account(0, 0, false))].into()
+        );
+    }
+
+    #[test]
+    fn deposit_fee_is_split_among_refunded_and_charged_back_parts() {
+        let mut processor = processor_with_fees(FeeReversal::Refund);
+        let audit: Vec<AuditRecord> = processor
+            .process(&[
+                deposit(1, 100, 100),
+                refund(1, 100).partial(50),
+                dispute(1, 100),
+                chargeback(1, 100).partial(20),
+                chargeback(1, 100),
+                dispute(1, 100),
+            ])
+            .collect();
+
+        assert_eq!(
+            audit,
+            [
+                AuditRecord::Processed,
+                AuditRecord::FeeCharged { fee: 1.into() },
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::FeeRefunded { fee: 0.2.into() },
+                AuditRecord::Processed,
+                AuditRecord::FeeRefunded { fee: 0.3.into() },
+                AuditRecord::IllegalTransition {
+                    state: State::ChargedBack,
+                    action: Action::Dispute,
+                },
+            ]
+        );
+        assert_eq!(
+            processor.accounts,
+            [(1, account(0, 0, true)), (99, account(0.5, 0, false))].into()
+        );
+        assert_eq!(processor.deposits.get(1, 100).unwrap(), Lookup::NotFound);
+    }
+
+    #[test]
+    fn refund_returns_what_deposit_credited() {
+        let mut processor = processor_with_fees(FeeReversal::Refund);
+        let audit: Vec<AuditRecord> = processor
+            .process(&[deposit(1, 100, 100), refund(1, 100)])
+            .collect();
+
+        assert_eq!(
+            audit,
+            [
+                AuditRecord::Processed,
+                AuditRecord::FeeCharged { fee: 1.into() },
+                AuditRecord::Processed,
+            ]
+        );
+        // house keeps the fee of refunded deposit
+        assert_eq!(
+            processor.accounts,
+            [(1, account(0, 0, false)), (99, account(1, 0, false))].into()
+        );
+        assert_eq!(processor.deposits.get(1, 100).unwrap(), Lookup::NotFound);
+    }
+
+    #[test]
+    fn reversal_returns_deposit_fee() {
+        let mut processor = processor_with_fees(FeeReversal::Keep);
+        let audit: Vec<AuditRecord> = processor
+            .process(&[
+                deposit(1, 100, 100),
+                refund(1, 100).partial(50),
+                reversal(1, 100),
+            ])
+            .collect();
+
+        assert_eq!(
+            audit,
+            [
+                AuditRecord::Processed,
+                AuditRecord::FeeCharged { fee: 1.into() },
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::FeeRefunded { fee: 0.5.into() },
+            ]
+        );
+        // house keeps only the fee of refunded part
+        assert_eq!(
+            processor.accounts,
+            [(1, account(0, 0, false)), (99, account(0.5, 0, false))].into()
+        );
+    }
+
+    #[test]
+    fn flat_deposit_fee_is_refunded_once_over_partial_chargebacks() {
+        let mut processor: Processor = Processor {
+            fees: Fees {
+                deposit: Some(Fee {
+                    flat: 1.into(),
+                    percent: 1.into(),
+                    min: Some(2.into()),
+                    ..Default::default()
+                }),
+                house_account: 99,
+                reversal: FeeReversal::Refund,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let audit: Vec<AuditRecord> = processor
+            .process(&[
+                deposit(1, 100, 100),
+                dispute(1, 100),
+                chargeback(1, 100).partial(25),
+                chargeback(1, 100).partial(25),
+                chargeback(1, 100),
+            ])
+            .collect();
+
+        assert_eq!(
+            audit,
+            [
+                AuditRecord::Processed,
+                AuditRecord::FeeCharged { fee: 2.into() },
+                AuditRecord::Processed,
+                AuditRecord::Processed,
+                AuditRecord::FeeRefunded { fee: 0.5.into() },
+                AuditRecord::Processed,
+                AuditRecord::FeeRefunded { fee: 0.5.into() },
+                AuditRecord::Processed,
+                AuditRecord::FeeRefunded { fee: 1.into() },
+            ]
+        );
+        assert_eq!(
+            processor.accounts,
+            [(1, account(0, 0, true)), (99, account(0, 0, false))].into()
+        );
+    }
 }
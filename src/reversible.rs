@@ -0,0 +1,639 @@
+use crate::{
+    account::ClientId,
+    money::{IntegerType, MoneyAmount},
+    transactions::{Timestamp, TransactionId},
+};
+use std::{
+    collections::{BTreeMap, HashMap},
+    fs::File,
+    io::{self, BufReader, BufWriter, Read, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+/// Previously made deposit or withdrawal as remembered by a store
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct Reversible {
+    /// Amount that is not disputed or refunded yet
+    pub amount: MoneyAmount,
+
+    /// Fee charged for the part that is not refunded or charged back yet
+    pub fee: MoneyAmount,
+
+    /// When the transaction was made, if known
+    pub at: Option<Timestamp>,
+
+    /// How many times it went from settled or resolved to disputed
+    pub disputes: u32,
+}
+
+/// Result of looking up previously made deposit or withdrawal
+#[derive(Debug, PartialEq)]
+pub enum Lookup {
+    Found(Reversible),
+    /// Transaction was made, but it was evicted from history to bound memory usage
+    Evicted,
+    NotFound,
+}
+
+/// Storage of transactions (deposits or withdrawals) that can still be disputed,
+/// refunded or reversed, with their amount that is not disputed or refunded yet
+pub trait ReversibleStore {
+    /// Remember transaction, or change remembered one (keeping its place in eviction order)
+    fn insert(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        transaction: Reversible,
+    ) -> io::Result<()>;
+
+    /// Look up previously remembered transaction
+    fn get(&mut self, client_id: ClientId, tx_id: TransactionId) -> io::Result<Lookup>;
+
+    /// Forget transaction, e.g. once it is under dispute
+    fn remove(&mut self, client_id: ClientId, tx_id: TransactionId) -> io::Result<()>;
+
+    /// Start keeping changes apart until commit, if storage does not share a transaction
+    /// with accounts
+    fn begin(&mut self) {}
+
+    /// Make changes since begin, once accounts they belong to are stored
+    fn commit(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+
+    /// Discard changes since begin, their accounts were not stored
+    fn rollback(&mut self) {}
+}
+
+impl Default for Box<dyn ReversibleStore> {
+    fn default() -> Self {
+        Box::new(MemoryReversibleStore::default())
+    }
+}
+
+/// Changes of a store made since begin, applied to it on commit
+#[derive(Default)]
+pub struct Uncommitted {
+    changes: Option<Vec<(TransactionKey, Option<Reversible>)>>,
+}
+
+impl Uncommitted {
+    pub fn begin(&mut self) {
+        self.changes = Some(Vec::new());
+    }
+
+    /// Keep change (absent transaction for removal) until commit, false outside of begin
+    pub fn record(&mut self, key: TransactionKey, transaction: Option<Reversible>) -> bool {
+        match &mut self.changes {
+            Some(changes) => {
+                changes.push((key, transaction));
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// The latest uncommitted change of given transaction
+    pub fn get(&self, key: TransactionKey) -> Option<Lookup> {
+        let (_, transaction) = self
+            .changes
+            .as_ref()?
+            .iter()
+            .rfind(|(changed, _)| *changed == key)?;
+        Some(match transaction {
+            Some(transaction) => Lookup::Found(*transaction),
+            None => Lookup::NotFound,
+        })
+    }
+
+    /// Changes to apply in order they were made, stops keeping further ones apart
+    pub fn take(&mut self) -> Vec<(TransactionKey, Option<Reversible>)> {
+        self.changes.take().unwrap_or_default()
+    }
+
+    pub fn rollback(&mut self) {
+        self.changes = None;
+    }
+}
+
+/// Apply changes taken from [`Uncommitted`] to the store they were kept apart from
+pub fn apply_uncommitted(
+    store: &mut impl ReversibleStore,
+    changes: Vec<(TransactionKey, Option<Reversible>)>,
+) -> io::Result<()> {
+    for ((client_id, tx_id), transaction) in changes {
+        match transaction {
+            Some(transaction) => store.insert(client_id, tx_id, transaction)?,
+            None => store.remove(client_id, tx_id)?,
+        }
+    }
+    Ok(())
+}
+
+/// Limits on how many transactions are kept in memory
+#[derive(Debug, Clone, Copy, Default)]
+pub struct EvictionPolicy {
+    /// Evict transactions older than this many seconds
+    pub max_age: Option<Timestamp>,
+
+    /// Keep at most this many transactions per account
+    pub max_per_account: Option<usize>,
+
+    /// Keep at most this many transactions across all accounts
+    pub max_total: Option<usize>,
+}
+
+pub type TransactionKey = (ClientId, TransactionId);
+
+struct Entry {
+    transaction: Reversible,
+    seq: u64,
+}
+
+/// In-memory history of deposits or withdrawals with eviction.
+///
+/// Evicted transactions are either spilled to a file or only remembered as evicted
+/// in a fixed size filter.
+/// Transactions are kept in order of insertion only when the policy needs it.
+/// Transactions without known time are treated as the oldest ones.
+#[derive(Default)]
+pub struct MemoryReversibleStore {
+    policy: EvictionPolicy,
+
+    entries: HashMap<TransactionKey, Entry>,
+
+    /// Transactions in order of insertion, with time they were made (for age and total limits)
+    order: BTreeMap<u64, (TransactionKey, Option<Timestamp>)>,
+
+    /// Transactions of each account in order of insertion (for per account limit)
+    per_account: HashMap<ClientId, BTreeMap<u64, TransactionId>>,
+
+    next_seq: u64,
+
+    /// Transactions that were evicted without a spill file
+    evicted: EvictedFilter,
+
+    spill: Option<SpillFile>,
+
+    uncommitted: Uncommitted,
+}
+
+impl MemoryReversibleStore {
+    pub fn new(policy: EvictionPolicy) -> Self {
+        Self {
+            policy,
+            ..Default::default()
+        }
+    }
+
+    fn keeps_order(&self) -> bool {
+        self.policy.max_age.is_some() || self.policy.max_total.is_some()
+    }
+
+    /// Write evicted transactions to given file instead of forgetting them
+    pub fn with_spill_file(mut self, path: impl AsRef<Path>) -> io::Result<Self> {
+        self.spill = Some(SpillFile::create(path)?);
+        Ok(self)
+    }
+
+    /// Remove transaction from memory
+    fn forget(&mut self, key: TransactionKey) -> Option<Entry> {
+        let entry = self.entries.remove(&key)?;
+        self.order.remove(&entry.seq);
+        if let Some(account_entries) = self.per_account.get_mut(&key.0) {
+            account_entries.remove(&entry.seq);
+            if account_entries.is_empty() {
+                self.per_account.remove(&key.0);
+            }
+        }
+        Some(entry)
+    }
+
+    fn evict(&mut self, key: TransactionKey) -> io::Result<()> {
+        let Some(entry) = self.forget(key) else {
+            return Ok(());
+        };
+        match &mut self.spill {
+            Some(spill) => spill.write(key, Some(entry.transaction)),
+            None => {
+                self.evicted.insert(key);
+                Ok(())
+            }
+        }
+    }
+
+    fn enforce_policy(&mut self, client_id: ClientId, now: Option<Timestamp>) -> io::Result<()> {
+        if let Some(max_per_account) = self.policy.max_per_account {
+            while let Some(account_entries) = self.per_account.get(&client_id) {
+                if account_entries.len() <= max_per_account {
+                    break;
+                }
+                let Some((_, &tx_id)) = account_entries.first_key_value() else {
+                    break;
+                };
+                self.evict((client_id, tx_id))?;
+            }
+        }
+
+        if let (Some(max_age), Some(now)) = (self.policy.max_age, now) {
+            let oldest_allowed = now.saturating_sub(max_age);
+            while let Some((_, &(key, at))) = self.order.first_key_value() {
+                if at.is_some_and(|at| at >= oldest_allowed) {
+                    break;
+                }
+                self.evict(key)?;
+            }
+        }
+
+        if let Some(max_total) = self.policy.max_total {
+            while self.entries.len() > max_total {
+                let Some((_, &(key, _))) = self.order.first_key_value() else {
+                    break;
+                };
+                self.evict(key)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl ReversibleStore for MemoryReversibleStore {
+    fn insert(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        transaction: Reversible,
+    ) -> io::Result<()> {
+        let key = (client_id, tx_id);
+        if self.uncommitted.record(key, Some(transaction)) {
+            return Ok(());
+        }
+        let seq = match self.forget(key) {
+            Some(entry) => entry.seq,
+            None => {
+                let seq = self.next_seq;
+                self.next_seq += 1;
+                seq
+            }
+        };
+        self.entries.insert(key, Entry { transaction, seq });
+        if self.keeps_order() {
+            self.order.insert(seq, (key, transaction.at));
+        }
+        if self.policy.max_per_account.is_some() {
+            self.per_account
+                .entry(client_id)
+                .or_default()
+                .insert(seq, tx_id);
+        }
+
+        self.enforce_policy(client_id, transaction.at)
+    }
+
+    fn get(&mut self, client_id: ClientId, tx_id: TransactionId) -> io::Result<Lookup> {
+        let key = (client_id, tx_id);
+        if let Some(lookup) = self.uncommitted.get(key) {
+            return Ok(lookup);
+        }
+        if let Some(entry) = self.entries.get(&key) {
+            return Ok(Lookup::Found(entry.transaction));
+        }
+
+        if let Some(spill) = &mut self.spill {
+            return Ok(match spill.find(key)? {
+                Some(transaction) => Lookup::Found(transaction),
+                None => Lookup::NotFound,
+            });
+        }
+
+        Ok(if self.evicted.contains(key) {
+            Lookup::Evicted
+        } else {
+            Lookup::NotFound
+        })
+    }
+
+    fn remove(&mut self, client_id: ClientId, tx_id: TransactionId) -> io::Result<()> {
+        let key = (client_id, tx_id);
+        if self.uncommitted.record(key, None) {
+            return Ok(());
+        }
+        self.forget(key);
+        // transaction may be back in memory (e.g. after resolved dispute) with older copy in the file
+        match &mut self.spill {
+            Some(spill) => spill.write(key, None),
+            None => Ok(()),
+        }
+    }
+
+    fn begin(&mut self) {
+        self.uncommitted.begin();
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        let changes = self.uncommitted.take();
+        apply_uncommitted(self, changes)
+    }
+
+    fn rollback(&mut self) {
+        self.uncommitted.rollback();
+    }
+}
+
+/// Bloom filter of evicted transactions, so that memory stays bounded however many are evicted.
+///
+/// A transaction that was never made may be reported as evicted (more likely the more of them
+/// were evicted), which only changes the reason why its dispute is rejected.
+#[derive(Default)]
+struct EvictedFilter {
+    /// Allocated on the first eviction
+    bits: Vec<u64>,
+}
+
+/// 2^23 bits (1 MiB) keep false positives under 1% up to about 800 thousand evicted transactions
+const FILTER_BITS: u32 = 23;
+const FILTER_HASHES: u32 = 4;
+
+impl EvictedFilter {
+    fn positions((client_id, tx_id): TransactionKey) -> impl Iterator<Item = usize> {
+        let key = (u64::from(client_id) << 32) | u64::from(tx_id);
+        (0..FILTER_HASHES).map(move |i| {
+            // splitmix64 finalizer of the key with a different seed for each hash
+            let mut hash = key.wrapping_add(u64::from(i + 1).wrapping_mul(0x9e3779b97f4a7c15));
+            hash = (hash ^ (hash >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+            hash = (hash ^ (hash >> 27)).wrapping_mul(0x94d049bb133111eb);
+            hash ^= hash >> 31;
+            (hash >> (64 - FILTER_BITS)) as usize
+        })
+    }
+
+    fn insert(&mut self, key: TransactionKey) {
+        if self.bits.is_empty() {
+            self.bits = vec![0; 1 << (FILTER_BITS - 6)];
+        }
+        for position in Self::positions(key) {
+            self.bits[position / 64] |= 1 << (position % 64);
+        }
+    }
+
+    fn contains(&self, key: TransactionKey) -> bool {
+        !self.bits.is_empty()
+            && Self::positions(key)
+                .all(|position| self.bits[position / 64] & (1 << (position % 64)) != 0)
+    }
+}
+
+/// Offsets of fields in spill record (time is preceded by its flag)
+const SPILL_AMOUNT: usize = 7;
+const SPILL_FEE: usize = SPILL_AMOUNT + size_of::<IntegerType>();
+const SPILL_AT: usize = SPILL_FEE + size_of::<IntegerType>() + 1;
+const SPILL_DISPUTES: usize = SPILL_AT + size_of::<Timestamp>();
+const SPILL_RECORD_SIZE: usize = SPILL_DISPUTES + size_of::<u32>();
+
+/// Append-only file of evicted transactions.
+///
+/// Each record is client id, tx id, presence flag, raw amount and fee, flag of known time, the time
+/// and count of disputes.
+/// The latest record of a transaction wins, so removal is recorded as an absent one.
+/// Lookups of spilled transactions scan the whole file, trading latency of (rare) disputes
+/// of old transactions for memory, others are ruled out by a filter without reading it.
+struct SpillFile {
+    path: PathBuf,
+    writer: BufWriter<File>,
+
+    /// Transactions that have a record in the file (or rarely others)
+    spilled: EvictedFilter,
+}
+
+impl SpillFile {
+    fn create(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref().to_path_buf();
+        let writer = BufWriter::new(File::create(&path)?);
+        Ok(Self {
+            path,
+            writer,
+            spilled: EvictedFilter::default(),
+        })
+    }
+
+    fn write(
+        &mut self,
+        (client_id, tx_id): TransactionKey,
+        transaction: Option<Reversible>,
+    ) -> io::Result<()> {
+        if transaction.is_none() && !self.spilled.contains((client_id, tx_id)) {
+            return Ok(());
+        }
+        self.spilled.insert((client_id, tx_id));
+        let Reversible {
+            amount,
+            fee,
+            at,
+            disputes,
+        } = transaction.unwrap_or_default();
+        let mut record = [0u8; SPILL_RECORD_SIZE];
+        record[0..2].copy_from_slice(&client_id.to_le_bytes());
+        record[2..6].copy_from_slice(&tx_id.to_le_bytes());
+        record[6] = transaction.is_some() as u8;
+        record[SPILL_AMOUNT..SPILL_FEE].copy_from_slice(&amount.to_raw().to_le_bytes());
+        record[SPILL_FEE..SPILL_AT - 1].copy_from_slice(&fee.to_raw().to_le_bytes());
+        record[SPILL_AT - 1] = at.is_some() as u8;
+        record[SPILL_AT..SPILL_DISPUTES].copy_from_slice(&at.unwrap_or_default().to_le_bytes());
+        record[SPILL_DISPUTES..].copy_from_slice(&disputes.to_le_bytes());
+        self.writer.write_all(&record)
+    }
+
+    /// Find the latest record of given transaction
+    fn find(&mut self, key: TransactionKey) -> io::Result<Option<Reversible>> {
+        if !self.spilled.contains(key) {
+            return Ok(None);
+        }
+        self.writer.flush()?;
+        let mut reader = BufReader::new(File::open(&self.path)?);
+        let mut record = [0u8; SPILL_RECORD_SIZE];
+        let mut found = None;
+
+        loop {
+            match reader.read_exact(&mut record) {
+                Ok(()) => {}
+                Err(error) if error.kind() == io::ErrorKind::UnexpectedEof => break,
+                Err(error) => return Err(error),
+            }
+
+            let client_id = ClientId::from_le_bytes([record[0], record[1]]);
+            let tx_id = TransactionId::from_le_bytes([record[2], record[3], record[4], record[5]]);
+            if (client_id, tx_id) != key {
+                continue;
+            }
+
+            let money = |range: Range<usize>| {
+                let mut raw = [0u8; size_of::<IntegerType>()];
+                raw.copy_from_slice(&record[range]);
+                MoneyAmount::from_raw(IntegerType::from_le_bytes(raw))
+            };
+            found = (record[6] != 0).then(|| {
+                let mut at = [0u8; size_of::<Timestamp>()];
+                at.copy_from_slice(&record[SPILL_AT..SPILL_DISPUTES]);
+                let mut disputes = [0u8; size_of::<u32>()];
+                disputes.copy_from_slice(&record[SPILL_DISPUTES..]);
+                Reversible {
+                    amount: money(SPILL_AMOUNT..SPILL_FEE),
+                    fee: money(SPILL_FEE..SPILL_AT - 1),
+                    at: (record[SPILL_AT - 1] != 0).then(|| Timestamp::from_le_bytes(at)),
+                    disputes: u32::from_le_bytes(disputes),
+                }
+            });
+        }
+
+        Ok(found)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn store(policy: EvictionPolicy) -> MemoryReversibleStore {
+        MemoryReversibleStore::new(policy)
+    }
+
+    fn made(amount: i64, at: Option<Timestamp>) -> Reversible {
+        Reversible {
+            amount: amount.into(),
+            at,
+            ..Default::default()
+        }
+    }
+
+    fn found(amount: i64) -> Lookup {
+        Lookup::Found(made(amount, None))
+    }
+
+    #[test]
+    fn evicts_oldest_deposits_over_global_budget() {
+        let mut store = store(EvictionPolicy {
+            max_total: Some(2),
+            ..Default::default()
+        });
+        store.insert(1, 100, made(10, None)).unwrap();
+        store.insert(2, 101, made(20, None)).unwrap();
+        store.insert(1, 102, made(30, None)).unwrap();
+
+        assert_eq!(store.get(1, 100).unwrap(), Lookup::Evicted);
+        assert_eq!(store.get(2, 101).unwrap(), found(20));
+        assert_eq!(store.get(1, 102).unwrap(), found(30));
+        assert_eq!(store.get(1, 103).unwrap(), Lookup::NotFound);
+    }
+
+    #[test]
+    fn memory_of_evicted_deposits_is_bounded() {
+        let mut store = store(EvictionPolicy {
+            max_total: Some(10),
+            ..Default::default()
+        });
+        for tx_id in 0..100_000 {
+            store.insert(1, tx_id, made(1, None)).unwrap();
+        }
+
+        assert_eq!(store.entries.len(), 10);
+        assert_eq!(store.order.len(), 10);
+        assert!(store.per_account.is_empty());
+        assert_eq!(store.evicted.bits.len(), 1 << (FILTER_BITS - 6));
+        assert_eq!(store.get(1, 0).unwrap(), Lookup::Evicted);
+        assert_eq!(store.get(1, 99_989).unwrap(), Lookup::Evicted);
+        let false_positives = (100_000..200_000)
+            .filter(|tx_id| store.get(1, *tx_id).unwrap() != Lookup::NotFound)
+            .count();
+        assert!(false_positives < 100, "{false_positives}");
+    }
+
+    #[test]
+    fn evicts_oldest_deposits_of_account() {
+        let mut store = store(EvictionPolicy {
+            max_per_account: Some(1),
+            ..Default::default()
+        });
+        store.insert(1, 100, made(10, None)).unwrap();
+        store.insert(2, 101, made(20, None)).unwrap();
+        store.insert(1, 102, made(30, None)).unwrap();
+
+        assert_eq!(store.get(1, 100).unwrap(), Lookup::Evicted);
+        assert_eq!(store.get(2, 101).unwrap(), found(20));
+        assert_eq!(store.get(1, 102).unwrap(), found(30));
+    }
+
+    #[test]
+    fn evicts_old_deposits() {
+        let mut store = store(EvictionPolicy {
+            max_age: Some(100),
+            ..Default::default()
+        });
+        store.insert(1, 100, made(10, Some(0))).unwrap();
+        store.insert(1, 101, made(20, Some(50))).unwrap();
+        store.insert(1, 102, made(30, Some(120))).unwrap();
+
+        assert_eq!(store.get(1, 100).unwrap(), Lookup::Evicted);
+        assert_eq!(
+            store.get(1, 101).unwrap(),
+            Lookup::Found(made(20, Some(50)))
+        );
+    }
+
+    #[test]
+    fn changed_deposits_keep_their_place_in_eviction_order() {
+        let mut store = store(EvictionPolicy {
+            max_total: Some(2),
+            ..Default::default()
+        });
+        store.insert(1, 100, made(10, Some(0))).unwrap();
+        store.insert(1, 101, made(20, Some(5))).unwrap();
+        store.insert(1, 100, made(4, Some(0))).unwrap();
+        store.insert(1, 102, made(30, Some(9))).unwrap();
+
+        assert_eq!(store.get(1, 100).unwrap(), Lookup::Evicted);
+        assert_eq!(store.get(1, 101).unwrap(), Lookup::Found(made(20, Some(5))));
+    }
+
+    #[test]
+    fn removed_deposits_are_not_found() {
+        let mut store = store(EvictionPolicy::default());
+        store.insert(1, 100, made(10, None)).unwrap();
+        store.remove(1, 100).unwrap();
+
+        assert_eq!(store.get(1, 100).unwrap(), Lookup::NotFound);
+    }
+
+    #[test]
+    fn spills_evicted_deposits_to_file() {
+        let path = std::env::temp_dir().join(format!("transactor-spill-{}", std::process::id()));
+        let mut store = store(EvictionPolicy {
+            max_total: Some(1),
+            ..Default::default()
+        })
+        .with_spill_file(&path)
+        .unwrap();
+        let spilled = Reversible {
+            fee: 1.into(),
+            disputes: 2,
+            ..made(10, Some(7))
+        };
+        store.insert(1, 100, spilled).unwrap();
+        store.insert(2, 101, made(20, None)).unwrap();
+        store.insert(3, 102, made(30, None)).unwrap();
+
+        assert_eq!(store.get(1, 100).unwrap(), Lookup::Found(spilled));
+        assert_eq!(store.get(2, 101).unwrap(), found(20));
+        assert_eq!(store.get(4, 103).unwrap(), Lookup::NotFound);
+
+        store.remove(1, 100).unwrap();
+        assert_eq!(store.get(1, 100).unwrap(), Lookup::NotFound);
+
+        // Transactions that were never spilled are not looked up in the file
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(store.get(5, 104).unwrap(), Lookup::NotFound);
+        store.remove(3, 102).unwrap();
+        assert_eq!(store.get(3, 102).unwrap(), Lookup::NotFound);
+    }
+}
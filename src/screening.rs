@@ -0,0 +1,450 @@
+use crate::{
+    account::Account,
+    config::{self, Entry, Section},
+    money::MoneyAmount,
+    transactions::{Timestamp, Transaction, TransactionDetail},
+};
+use std::{collections::VecDeque, io, path::Path, str::FromStr};
+
+/// Number of recent transactions of each client kept for screening
+pub const HISTORY_LEN: usize = 100;
+
+/// Decision of screening rule about a transaction
+#[derive(Debug, PartialEq)]
+pub enum Verdict {
+    Allow,
+    Reject {
+        reason: String,
+    },
+    /// Allow, but report transaction as suspicious
+    Flag {
+        reason: String,
+    },
+}
+
+/// Recently processed transaction of a client
+#[derive(Debug, Clone, Copy)]
+pub struct RecentTransaction {
+    pub detail: TransactionDetail,
+    pub at: Option<Timestamp>,
+}
+
+/// Recently processed transactions of a client, oldest first
+pub type History = VecDeque<RecentTransaction>;
+
+/// What screening rule can see about a transaction
+pub struct Context<'a> {
+    pub tx: &'a Transaction,
+
+    /// Time of transaction, if known
+    pub at: Option<Timestamp>,
+
+    /// Account of the client, if it exists
+    pub account: Option<&'a Account>,
+
+    pub history: &'a History,
+}
+
+impl Context<'_> {
+    /// Recent transactions made within given number of seconds before this one
+    pub fn recent(&self, window: Timestamp) -> impl Iterator<Item = &RecentTransaction> {
+        let since = self.at.map(|at| at.saturating_sub(window));
+        self.history
+            .iter()
+            .filter(move |recent| since.is_some() && recent.at >= since)
+    }
+}
+
+/// Rule screening transactions for suspicious patterns before they are applied
+pub trait Screening {
+    fn screen(&mut self, context: &Context) -> Verdict;
+}
+
+/// What built-in rules do with suspicious transactions
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub enum Action {
+    #[default]
+    Flag,
+    Reject,
+}
+
+impl FromStr for Action {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "flag" => Ok(Action::Flag),
+            "reject" => Ok(Action::Reject),
+            _ => Err(format!("unknown action {s}")),
+        }
+    }
+}
+
+impl Action {
+    fn verdict(self, reason: String) -> Verdict {
+        match self {
+            Action::Flag => Verdict::Flag { reason },
+            Action::Reject => Verdict::Reject { reason },
+        }
+    }
+}
+
+/// Several deposits just below reporting threshold within a window
+pub struct Structuring {
+    pub threshold: MoneyAmount,
+
+    /// How close to the threshold deposit must be to count
+    pub margin: MoneyAmount,
+
+    pub count: usize,
+    pub window: Timestamp,
+    pub action: Action,
+}
+
+impl Default for Structuring {
+    fn default() -> Self {
+        Self {
+            threshold: 10000.into(),
+            margin: 1000.into(),
+            count: 3,
+            window: 24 * 60 * 60,
+            action: Action::Flag,
+        }
+    }
+}
+
+impl Structuring {
+    fn just_below_threshold(&self, detail: &TransactionDetail) -> bool {
+        let TransactionDetail::Deposit { amount } = detail else {
+            return false;
+        };
+        let lowest = self.threshold.try_change(-self.margin);
+        *amount < self.threshold && lowest.is_some_and(|lowest| *amount >= lowest)
+    }
+}
+
+impl Screening for Structuring {
+    fn screen(&mut self, context: &Context) -> Verdict {
+        if !self.just_below_threshold(&context.tx.detail) {
+            return Verdict::Allow;
+        }
+
+        let previous = context
+            .recent(self.window)
+            .filter(|recent| self.just_below_threshold(&recent.detail))
+            .count();
+        if context.at.is_none() || previous + 1 < self.count {
+            return Verdict::Allow;
+        }
+
+        self.action.verdict(format!(
+            "{} deposits just below {} within {}s",
+            previous + 1,
+            self.threshold,
+            self.window
+        ))
+    }
+}
+
+/// Withdrawal of (at least) everything deposited shortly before
+pub struct RapidWithdrawal {
+    pub within: Timestamp,
+    pub action: Action,
+}
+
+impl Default for RapidWithdrawal {
+    fn default() -> Self {
+        Self {
+            within: 60 * 60,
+            action: Action::Flag,
+        }
+    }
+}
+
+impl Screening for RapidWithdrawal {
+    fn screen(&mut self, context: &Context) -> Verdict {
+        let TransactionDetail::Withdrawal { amount } = context.tx.detail else {
+            return Verdict::Allow;
+        };
+
+        let deposited = context
+            .recent(self.within)
+            .filter_map(|recent| match recent.detail {
+                TransactionDetail::Deposit { amount } => Some(amount),
+                _ => None,
+            })
+            .try_fold(MoneyAmount::default(), |total, amount| {
+                total.try_change(amount)
+            });
+        let Some(deposited) = deposited.filter(|deposited| *deposited > 0) else {
+            return Verdict::Allow;
+        };
+        // withdrawals the account can not cover fail anyway
+        let covered = context
+            .account
+            .is_some_and(|account| account.available >= amount);
+        if amount < deposited || !covered {
+            return Verdict::Allow;
+        }
+
+        self.action.verdict(format!(
+            "withdrawal of {deposited} deposited within {}s",
+            self.within
+        ))
+    }
+}
+
+/// Many disputes of one client within a window
+pub struct DisputeBurst {
+    pub max: usize,
+    pub window: Timestamp,
+    pub action: Action,
+}
+
+impl Default for DisputeBurst {
+    fn default() -> Self {
+        Self {
+            max: 3,
+            window: 24 * 60 * 60,
+            action: Action::Flag,
+        }
+    }
+}
+
+impl Screening for DisputeBurst {
+    fn screen(&mut self, context: &Context) -> Verdict {
+        if !matches!(context.tx.detail, TransactionDetail::Dispute { .. }) {
+            return Verdict::Allow;
+        }
+
+        let previous = context
+            .recent(self.window)
+            .filter(|recent| matches!(recent.detail, TransactionDetail::Dispute { .. }))
+            .count();
+        if context.at.is_none() || previous < self.max {
+            return Verdict::Allow;
+        }
+
+        self.action.verdict(format!(
+            "more than {} disputes within {}s",
+            self.max, self.window
+        ))
+    }
+}
+
+/// All built-in rules with default settings
+pub fn built_in() -> Vec<Box<dyn Screening>> {
+    vec![
+        Box::new(Structuring::default()),
+        Box::new(RapidWithdrawal::default()),
+        Box::new(DisputeBurst::default()),
+    ]
+}
+
+/// Read built-in rules to use from config file like
+///
+/// ```text
+/// [structuring]
+/// threshold = 10000
+/// margin = 1000
+/// count = 3
+/// window = 86400
+///
+/// [rapid_withdrawal]
+/// within = 3600
+/// action = reject
+///
+/// [dispute_burst]
+/// max = 3
+/// window = 86400
+/// ```
+///
+/// Only rules with a section are used, settings not mentioned keep default values.
+/// Empty file enables all rules.
+pub fn read(path: impl AsRef<Path>) -> io::Result<Vec<Box<dyn Screening>>> {
+    from_config(&config::read(path)?)
+}
+
+pub fn from_config(sections: &[Section]) -> io::Result<Vec<Box<dyn Screening>>> {
+    if sections.is_empty() {
+        return Ok(built_in());
+    }
+
+    sections
+        .iter()
+        .map(|section| {
+            let rule: Box<dyn Screening> = match section.name.as_str() {
+                "structuring" => {
+                    let mut rule = Structuring::default();
+                    for entry in &section.entries {
+                        match entry.key.as_str() {
+                            "threshold" => rule.threshold = entry.parse()?,
+                            "margin" => rule.margin = entry.parse()?,
+                            "count" => rule.count = entry.parse()?,
+                            "window" => rule.window = entry.parse()?,
+                            "action" => rule.action = entry.parse()?,
+                            key => return Err(unknown_setting(entry, key)),
+                        }
+                    }
+                    Box::new(rule)
+                }
+                "rapid_withdrawal" => {
+                    let mut rule = RapidWithdrawal::default();
+                    for entry in &section.entries {
+                        match entry.key.as_str() {
+                            "within" => rule.within = entry.parse()?,
+                            "action" => rule.action = entry.parse()?,
+                            key => return Err(unknown_setting(entry, key)),
+                        }
+                    }
+                    Box::new(rule)
+                }
+                "dispute_burst" => {
+                    let mut rule = DisputeBurst::default();
+                    for entry in &section.entries {
+                        match entry.key.as_str() {
+                            "max" => rule.max = entry.parse()?,
+                            "window" => rule.window = entry.parse()?,
+                            "action" => rule.action = entry.parse()?,
+                            key => return Err(unknown_setting(entry, key)),
+                        }
+                    }
+                    Box::new(rule)
+                }
+                name => {
+                    let line = section.entries.first().map_or(0, |e| e.line);
+                    return Err(config::invalid(line, format!("unknown rule {name}")));
+                }
+            };
+            Ok(rule)
+        })
+        .collect()
+}
+
+fn unknown_setting(entry: &Entry, key: &str) -> io::Error {
+    config::invalid(entry.line, format!("unknown setting {key}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transactions::{deposit, dispute, withdraw};
+
+    fn screen(rule: &mut impl Screening, history: &[Transaction], tx: Transaction) -> Verdict {
+        let account = Account {
+            available: 1000.into(),
+            ..Default::default()
+        };
+        let history: History = history
+            .iter()
+            .map(|tx| RecentTransaction {
+                detail: tx.detail,
+                at: tx.timestamp,
+            })
+            .collect();
+        rule.screen(&Context {
+            at: tx.timestamp,
+            tx: &tx,
+            account: Some(&account),
+            history: &history,
+        })
+    }
+
+    fn flagged(verdict: Verdict) -> bool {
+        matches!(verdict, Verdict::Flag { .. })
+    }
+
+    #[test]
+    fn flags_structuring() {
+        let mut rule = Structuring::default();
+        let history = [deposit(1, 1, 9500.0).at(0), deposit(1, 2, 9900.0).at(100)];
+
+        assert!(flagged(screen(
+            &mut rule,
+            &history,
+            deposit(1, 3, 9999.0).at(200)
+        )));
+        assert!(!flagged(screen(
+            &mut rule,
+            &history,
+            deposit(1, 3, 10000.0).at(200)
+        )));
+        assert!(!flagged(screen(
+            &mut rule,
+            &history,
+            deposit(1, 3, 9999.0).at(90000)
+        )));
+    }
+
+    #[test]
+    fn flags_rapid_withdrawal() {
+        let mut rule = RapidWithdrawal::default();
+        let history = [deposit(1, 1, 500.0).at(0)];
+
+        assert!(flagged(screen(
+            &mut rule,
+            &history,
+            withdraw(1, 2, 500.0).at(60)
+        )));
+        assert!(!flagged(screen(
+            &mut rule,
+            &history,
+            withdraw(1, 2, 400.0).at(60)
+        )));
+        assert!(!flagged(screen(
+            &mut rule,
+            &history,
+            withdraw(1, 2, 500.0).at(7200)
+        )));
+        assert!(!flagged(screen(
+            &mut rule,
+            &history,
+            withdraw(1, 2, 1500.0).at(60)
+        )));
+    }
+
+    #[test]
+    fn rejects_dispute_bursts() {
+        let mut rule = DisputeBurst {
+            max: 2,
+            action: Action::Reject,
+            ..Default::default()
+        };
+        let history = [dispute(1, 1).at(0), dispute(1, 2).at(10)];
+
+        assert_eq!(
+            screen(&mut rule, &history, dispute(1, 3).at(20)),
+            Verdict::Reject {
+                reason: "more than 2 disputes within 86400s".into()
+            }
+        );
+        assert_eq!(
+            screen(&mut rule, &history[1..], dispute(1, 3).at(20)),
+            Verdict::Allow
+        );
+    }
+
+    #[test]
+    fn reads_rules_from_config() {
+        let rules =
+            from_config(&config::parse("[dispute_burst]\nmax = 1\naction = reject").unwrap());
+        let mut rules = rules.unwrap();
+        assert_eq!(rules.len(), 1);
+
+        let history: History = [RecentTransaction {
+            detail: dispute(1, 1).detail,
+            at: Some(0),
+        }]
+        .into();
+        let verdict = rules[0].screen(&Context {
+            tx: &dispute(1, 2).at(1),
+            at: Some(1),
+            account: None,
+            history: &history,
+        });
+        assert!(matches!(verdict, Verdict::Reject { .. }));
+
+        assert_eq!(from_config(&[]).unwrap().len(), 3);
+        assert!(from_config(&config::parse("[structuring]\naction = ignore").unwrap()).is_err());
+    }
+}
@@ -0,0 +1,663 @@
+use crate::{
+    account::{Account, AuditRecord, ClientId, Dispute, ExpiryPolicy},
+    account_store::AccountStore,
+    applied::{AppliedStore, ContentHash},
+    money::MoneyAmount,
+    reversible::{Lookup, Reversible, ReversibleStore},
+    transactions::{Transaction, TransactionId},
+};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::{collections::HashMap, io, path::Path, rc::Rc};
+
+/// Money amounts are stored as integers in 1/10000 units,
+/// `balances` view shows them as decimals.
+const SCHEMA: &str = "
+    PRAGMA journal_mode = WAL;
+    PRAGMA synchronous = NORMAL;
+
+    CREATE TABLE IF NOT EXISTS accounts (
+        client INTEGER PRIMARY KEY,
+        available INTEGER NOT NULL,
+        held INTEGER NOT NULL,
+        locked INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS disputes (
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        amount INTEGER NOT NULL,
+        opened_at INTEGER,
+        PRIMARY KEY (client, tx)
+    );
+
+    CREATE TABLE IF NOT EXISTS deposits (
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        amount INTEGER NOT NULL,
+        fee INTEGER NOT NULL DEFAULT 0,
+        deposited_at INTEGER,
+        disputes INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (client, tx)
+    );
+
+    CREATE TABLE IF NOT EXISTS withdrawals (
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        amount INTEGER NOT NULL,
+        fee INTEGER NOT NULL DEFAULT 0,
+        withdrawn_at INTEGER,
+        disputes INTEGER NOT NULL DEFAULT 0,
+        PRIMARY KEY (client, tx)
+    );
+
+    CREATE TABLE IF NOT EXISTS applied (
+        tx INTEGER PRIMARY KEY,
+        hash INTEGER NOT NULL
+    );
+
+    CREATE TABLE IF NOT EXISTS outcomes (
+        seq INTEGER PRIMARY KEY AUTOINCREMENT,
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        id INTEGER,
+        type TEXT NOT NULL,
+        amount INTEGER,
+        timestamp INTEGER,
+        outcome TEXT NOT NULL,
+        details TEXT
+    );
+
+    CREATE VIEW IF NOT EXISTS balances AS
+        SELECT client,
+            available / 10000.0 AS available,
+            held / 10000.0 AS held,
+            (available + held) / 10000.0 AS total,
+            locked
+        FROM accounts;
+";
+
+fn io_error(error: rusqlite::Error) -> io::Error {
+    io::Error::other(error)
+}
+
+/// Accounts and outcome log kept in a local SQLite database.
+///
+/// Accounts used by a transaction are cached until it is committed,
+/// so the account changes, deposit and withdrawal history changes and outcomes of each
+/// transaction are written in a single database transaction.
+pub struct SqliteStore {
+    conn: Rc<Connection>,
+
+    /// Accounts used since the last commit, with a flag whether they changed
+    cache: HashMap<ClientId, (Account, bool)>,
+}
+
+impl SqliteStore {
+    /// Open (or create) database
+    pub fn open(path: impl AsRef<Path>) -> io::Result<Self> {
+        let conn = Connection::open(path).map_err(io_error)?;
+        conn.execute_batch(SCHEMA).map_err(io_error)?;
+        Ok(Self {
+            conn: Rc::new(conn),
+            cache: HashMap::new(),
+        })
+    }
+
+    /// Deposit history kept in the same database
+    pub fn deposits(&self) -> SqliteReversible {
+        SqliteReversible {
+            conn: self.conn.clone(),
+            table: "deposits",
+            time_column: "deposited_at",
+        }
+    }
+
+    /// Withdrawal history kept in the same database
+    pub fn withdrawals(&self) -> SqliteReversible {
+        SqliteReversible {
+            conn: self.conn.clone(),
+            table: "withdrawals",
+            time_column: "withdrawn_at",
+        }
+    }
+
+    /// Processed transactions kept in the same database
+    pub fn applied(&self) -> SqliteApplied {
+        SqliteApplied {
+            conn: self.conn.clone(),
+        }
+    }
+
+    /// Write changed accounts and forget cached ones
+    fn flush(&mut self) -> rusqlite::Result<()> {
+        for (client_id, (account, changed)) in self.cache.drain() {
+            if changed {
+                save(&self.conn, client_id, &account)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Log records of transaction, records of expired disputes (and fees following them)
+    /// under the expired dispute as resolve or chargeback without amount
+    fn log(&self, tx: &Transaction, audit: &[AuditRecord]) -> rusqlite::Result<()> {
+        let mut insert = self.conn.prepare_cached(
+            "INSERT INTO outcomes (client, tx, id, type, amount, timestamp, outcome, details)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        )?;
+        let mut expired = None;
+        for record in audit {
+            match record {
+                AuditRecord::DisputeExpired {
+                    client_id,
+                    tx_id,
+                    policy,
+                    ..
+                } => {
+                    let kind = match policy {
+                        ExpiryPolicy::Resolve => "resolve",
+                        ExpiryPolicy::ChargeBack => "chargeback",
+                    };
+                    expired = Some((*client_id, *tx_id, kind));
+                }
+                AuditRecord::FeeCharged { .. } | AuditRecord::FeeRefunded { .. } => {}
+                _ => expired = None,
+            }
+            let (client_id, tx_id, id, kind, amount) = match expired {
+                Some((client_id, tx_id, kind)) => (client_id, tx_id, None, kind, None),
+                None => (
+                    tx.client_id,
+                    tx.input_tx_id(),
+                    tx.referencing_id(),
+                    tx.detail.name(),
+                    tx.detail.amount().map(MoneyAmount::to_raw),
+                ),
+            };
+            insert.execute(params![
+                client_id,
+                tx_id,
+                id,
+                kind,
+                amount,
+                tx.timestamp,
+                record.kind(),
+                record.details()
+            ])?;
+        }
+        Ok(())
+    }
+}
+
+fn load(conn: &Connection, client_id: ClientId) -> rusqlite::Result<Option<Account>> {
+    let Some(mut account) = conn
+        .prepare_cached("SELECT available, held, locked FROM accounts WHERE client = ?1")?
+        .query_row([client_id], |row| {
+            Ok(Account {
+                available: MoneyAmount::from_raw(row.get(0)?),
+                held: MoneyAmount::from_raw(row.get(1)?),
+                locked: row.get(2)?,
+                ..Default::default()
+            })
+        })
+        .optional()?
+    else {
+        return Ok(None);
+    };
+
+    let mut disputes =
+        conn.prepare_cached("SELECT tx, amount, opened_at FROM disputes WHERE client = ?1")?;
+    let mut rows = disputes.query([client_id])?;
+    while let Some(row) = rows.next()? {
+        account.disputed_amounts.insert(
+            row.get(0)?,
+            Dispute {
+                amount: MoneyAmount::from_raw(row.get(1)?),
+                opened_at: row.get(2)?,
+            },
+        );
+    }
+
+    Ok(Some(account))
+}
+
+fn save(conn: &Connection, client_id: ClientId, account: &Account) -> rusqlite::Result<()> {
+    conn.prepare_cached(
+        "INSERT OR REPLACE INTO accounts (client, available, held, locked)
+            VALUES (?1, ?2, ?3, ?4)",
+    )?
+    .execute(params![
+        client_id,
+        account.available.to_raw(),
+        account.held.to_raw(),
+        account.locked
+    ])?;
+
+    conn.prepare_cached("DELETE FROM disputes WHERE client = ?1")?
+        .execute([client_id])?;
+    let mut insert = conn.prepare_cached(
+        "INSERT INTO disputes (client, tx, amount, opened_at) VALUES (?1, ?2, ?3, ?4)",
+    )?;
+    for (tx_id, dispute) in &account.disputed_amounts {
+        insert.execute(params![
+            client_id,
+            tx_id,
+            dispute.amount.to_raw(),
+            dispute.opened_at
+        ])?;
+    }
+    Ok(())
+}
+
+impl AccountStore for SqliteStore {
+    fn get(&mut self, client_id: ClientId) -> io::Result<Option<&Account>> {
+        if !self.cache.contains_key(&client_id) {
+            let Some(account) = load(&self.conn, client_id).map_err(io_error)? else {
+                return Ok(None);
+            };
+            self.cache.insert(client_id, (account, false));
+        }
+        Ok(self.cache.get(&client_id).map(|(account, _)| account))
+    }
+
+    fn get_mut(&mut self, client_id: ClientId) -> io::Result<&mut Account> {
+        if !self.cache.contains_key(&client_id) {
+            let account = load(&self.conn, client_id).map_err(io_error)?;
+            self.cache
+                .insert(client_id, (account.unwrap_or_default(), false));
+        }
+        let (account, changed) = self
+            .cache
+            .get_mut(&client_id)
+            .expect("account was just cached");
+        *changed = true;
+        Ok(account)
+    }
+
+    fn for_each(&mut self, mut f: impl FnMut(ClientId, &Account)) -> io::Result<()> {
+        self.flush().map_err(io_error)?;
+
+        let client_ids: Vec<ClientId> = self
+            .conn
+            .prepare("SELECT client FROM accounts ORDER BY client")
+            .and_then(|mut select| select.query_map([], |row| row.get(0))?.collect())
+            .map_err(io_error)?;
+        for client_id in client_ids {
+            if let Some(account) = load(&self.conn, client_id).map_err(io_error)? {
+                f(client_id, &account);
+            }
+        }
+        Ok(())
+    }
+
+    fn persist(&mut self) -> io::Result<()> {
+        self.flush().map_err(io_error)
+    }
+
+    fn begin(&mut self) -> io::Result<()> {
+        self.flush().map_err(io_error)?;
+        self.conn.execute_batch("BEGIN").map_err(io_error)
+    }
+
+    fn commit(&mut self, tx: &Transaction, audit: &[AuditRecord]) -> io::Result<()> {
+        self.flush().map_err(io_error)?;
+        self.log(tx, audit).map_err(io_error)?;
+        self.conn.execute_batch("COMMIT").map_err(io_error)
+    }
+
+    fn rollback(&mut self) -> io::Result<()> {
+        self.cache.clear();
+        if self.conn.is_autocommit() {
+            return Ok(());
+        }
+        self.conn.execute_batch("ROLLBACK").map_err(io_error)
+    }
+}
+
+/// Deposit or withdrawal history kept in a table of local SQLite database, without eviction
+pub struct SqliteReversible {
+    conn: Rc<Connection>,
+    table: &'static str,
+    time_column: &'static str,
+}
+
+impl ReversibleStore for SqliteReversible {
+    fn insert(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        transaction: Reversible,
+    ) -> io::Result<()> {
+        let (table, time_column) = (self.table, self.time_column);
+        self.conn
+            .prepare_cached(&format!(
+                "INSERT OR REPLACE INTO {table} (client, tx, amount, fee, {time_column}, disputes)
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6)"
+            ))
+            .and_then(|mut insert| {
+                insert.execute(params![
+                    client_id,
+                    tx_id,
+                    transaction.amount.to_raw(),
+                    transaction.fee.to_raw(),
+                    transaction.at,
+                    transaction.disputes
+                ])
+            })
+            .map_err(io_error)?;
+        Ok(())
+    }
+
+    fn get(&mut self, client_id: ClientId, tx_id: TransactionId) -> io::Result<Lookup> {
+        let transaction = self
+            .conn
+            .prepare_cached(&format!(
+                "SELECT amount, fee, {}, disputes FROM {} WHERE client = ?1 AND tx = ?2",
+                self.time_column, self.table
+            ))
+            .and_then(|mut select| {
+                select
+                    .query_row(params![client_id, tx_id], |row| {
+                        Ok(Reversible {
+                            amount: MoneyAmount::from_raw(row.get(0)?),
+                            fee: MoneyAmount::from_raw(row.get(1)?),
+                            at: row.get(2)?,
+                            disputes: row.get(3)?,
+                        })
+                    })
+                    .optional()
+            })
+            .map_err(io_error)?;
+
+        Ok(match transaction {
+            Some(transaction) => Lookup::Found(transaction),
+            None => Lookup::NotFound,
+        })
+    }
+
+    fn remove(&mut self, client_id: ClientId, tx_id: TransactionId) -> io::Result<()> {
+        self.conn
+            .prepare_cached(&format!(
+                "DELETE FROM {} WHERE client = ?1 AND tx = ?2",
+                self.table
+            ))
+            .and_then(|mut delete| delete.execute(params![client_id, tx_id]))
+            .map_err(io_error)?;
+        Ok(())
+    }
+}
+
+/// Processed transactions kept in a local SQLite database,
+/// content hashes are stored as signed integers with the same bits
+pub struct SqliteApplied {
+    conn: Rc<Connection>,
+}
+
+impl AppliedStore for SqliteApplied {
+    fn get(&mut self, id: TransactionId) -> io::Result<Option<ContentHash>> {
+        let hash: Option<i64> = self
+            .conn
+            .prepare_cached("SELECT hash FROM applied WHERE tx = ?1")
+            .and_then(|mut select| select.query_row([id], |row| row.get(0)).optional())
+            .map_err(io_error)?;
+        Ok(hash.map(|hash| ContentHash::from_le_bytes(hash.to_le_bytes())))
+    }
+
+    fn insert(&mut self, id: TransactionId, hash: ContentHash) -> io::Result<()> {
+        self.conn
+            .prepare_cached("INSERT OR REPLACE INTO applied (tx, hash) VALUES (?1, ?2)")
+            .and_then(|mut insert| {
+                insert.execute(params![id, i64::from_le_bytes(hash.to_le_bytes())])
+            })
+            .map_err(io_error)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account::account,
+        processor::{DisputeExpiry, Processor},
+        transactions::{deposit, dispute, resolve, reversal, withdraw},
+    };
+    use std::path::PathBuf;
+
+    struct TempDb(PathBuf);
+
+    impl TempDb {
+        fn new(name: &str) -> Self {
+            let path = std::env::temp_dir()
+                .join(format!("transactor-{name}-{}.sqlite", std::process::id()));
+            let _ = std::fs::remove_file(&path);
+            Self(path)
+        }
+
+        fn processor(&self) -> Processor<SqliteStore> {
+            let mut processor = Processor::new(SqliteStore::open(&self.0).unwrap()).unwrap();
+            processor.deposits = Box::new(processor.accounts.deposits());
+            processor.withdrawals = Box::new(processor.accounts.withdrawals());
+            processor.applied = Box::new(processor.accounts.applied());
+            processor
+        }
+    }
+
+    impl Drop for TempDb {
+        fn drop(&mut self) {
+            for suffix in ["", "-wal", "-shm"] {
+                let mut path = self.0.clone().into_os_string();
+                path.push(suffix);
+                let _ = std::fs::remove_file(path);
+            }
+        }
+    }
+
+    #[test]
+    fn keeps_state_across_restarts() {
+        let db = TempDb::new("sqlite-restart");
+        db.processor()
+            .process(&[deposit(1, 100, 10.0), deposit(2, 101, 5.0)])
+            .for_each(drop);
+
+        let mut processor = db.processor();
+        let audit: Vec<AuditRecord> = processor
+            .process(&[dispute(1, 100), withdraw(2, 102, 1.0)])
+            .collect();
+
+        assert_eq!(audit, [AuditRecord::Processed, AuditRecord::Processed]);
+        assert_eq!(
+            processor.accounts.get(1).unwrap(),
+            Some(&account(0, 10, false))
+        );
+        assert_eq!(
+            processor.accounts.get(2).unwrap(),
+            Some(&account(4, 0, false))
+        );
+    }
+
+    #[test]
+    fn reverses_withdrawals_after_restart() {
+        let db = TempDb::new("sqlite-withdrawals");
+        db.processor()
+            .process(&[deposit(1, 100, 10.0), withdraw(1, 101, 4.0)])
+            .for_each(drop);
+
+        let mut processor = db.processor();
+        let audit: Vec<AuditRecord> = processor.process(&[reversal(1, 101)]).collect();
+
+        assert_eq!(audit, [AuditRecord::Processed]);
+        assert_eq!(
+            processor.accounts.get(1).unwrap(),
+            Some(&account(10, 0, false))
+        );
+    }
+
+    #[test]
+    fn limits_redisputes_after_restart() {
+        let db = TempDb::new("sqlite-redisputes");
+        db.processor()
+            .process(&[
+                deposit(1, 100, 10.0),
+                dispute(1, 100),
+                resolve(1, 100),
+                dispute(1, 100),
+                resolve(1, 100),
+            ])
+            .for_each(drop);
+
+        let mut processor = db.processor();
+        processor.lifecycles.max_redisputes = Some(1);
+        let audit: Vec<AuditRecord> = processor.process(&[dispute(1, 100)]).collect();
+
+        assert_eq!(audit, [AuditRecord::RedisputeLimitReached { max: 1 }]);
+    }
+
+    #[test]
+    fn skips_transactions_applied_before_restart() {
+        let db = TempDb::new("sqlite-applied");
+        let batch = [deposit(1, 100, 10.0), dispute(1, 100).with_id(101)];
+        db.processor().process(&batch).for_each(drop);
+
+        let mut processor = db.processor();
+        let audit: Vec<AuditRecord> = processor
+            .process(batch.iter().chain(&[withdraw(1, 100, 1.0)]))
+            .collect();
+
+        assert_eq!(
+            audit,
+            [
+                AuditRecord::AlreadyApplied,
+                AuditRecord::AlreadyApplied,
+                AuditRecord::ConflictsWithApplied,
+            ]
+        );
+        assert_eq!(
+            processor.accounts.get(1).unwrap(),
+            Some(&account(0, 10, false))
+        );
+    }
+
+    #[test]
+    fn logs_expired_disputes_under_their_deposit() {
+        let db = TempDb::new("sqlite-expired");
+        let mut processor = db.processor();
+        processor.dispute_expiry = Some(DisputeExpiry {
+            max_duration: 10,
+            policy: ExpiryPolicy::Resolve,
+        });
+        processor
+            .process(&[
+                deposit(1, 100, 10.0).at(0),
+                dispute(1, 100).at(1),
+                deposit(2, 101, 5.0).at(20),
+            ])
+            .for_each(drop);
+
+        type Outcome = (ClientId, TransactionId, String, String, Option<String>);
+        let outcomes: Vec<Outcome> = processor
+            .accounts
+            .conn
+            .prepare(
+                "SELECT client, tx, type, outcome, details FROM outcomes WHERE seq > 2 ORDER BY seq",
+            )
+            .unwrap()
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(
+            outcomes,
+            [
+                (
+                    1,
+                    100,
+                    "resolve".into(),
+                    "DisputeExpired".into(),
+                    Some("Processed".into())
+                ),
+                (2, 101, "deposit".into(), "Processed".into(), None),
+            ]
+        );
+    }
+
+    #[test]
+    fn logs_outcomes_of_transactions() {
+        let db = TempDb::new("sqlite-outcomes");
+        let mut processor = db.processor();
+        processor
+            .process(&[
+                deposit(1, 100, 10.0),
+                resolve(1, 100),
+                dispute(1, 100).partial(20.0),
+                withdraw(1, 101, 1.5),
+            ])
+            .for_each(drop);
+
+        type Outcome = (TransactionId, String, Option<i64>, String, Option<String>);
+        let outcomes: Vec<Outcome> = processor
+            .accounts
+            .conn
+            .prepare("SELECT tx, type, amount, outcome, details FROM outcomes ORDER BY seq")
+            .unwrap()
+            .query_map([], |row| {
+                Ok((
+                    row.get(0)?,
+                    row.get(1)?,
+                    row.get(2)?,
+                    row.get(3)?,
+                    row.get(4)?,
+                ))
+            })
+            .unwrap()
+            .collect::<Result<_, _>>()
+            .unwrap();
+
+        assert_eq!(
+            outcomes,
+            [
+                (
+                    100,
+                    "deposit".into(),
+                    Some(100000),
+                    "Processed".into(),
+                    None
+                ),
+                (100, "resolve".into(), None, "DisputeNotFound".into(), None),
+                (
+                    100,
+                    "dispute".into(),
+                    Some(200000),
+                    "DisputeExceedsDeposit".into(),
+                    Some("remaining 10.0000".into())
+                ),
+                (
+                    101,
+                    "withdrawal".into(),
+                    Some(15000),
+                    "Processed".into(),
+                    None
+                ),
+            ]
+        );
+
+        let available: f64 = processor
+            .accounts
+            .conn
+            .query_row(
+                "SELECT available FROM balances WHERE client = 1",
+                [],
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(available, 8.5);
+    }
+}
@@ -0,0 +1,303 @@
+use crate::{
+    account::AuditRecord,
+    events::{Event, Subscriber},
+    money::{self, MoneyAmount},
+    transactions::{Transaction, TransactionDetail},
+};
+use std::{
+    collections::BTreeMap,
+    fmt,
+    io::{self, Write},
+    ops::AddAssign,
+    time::Duration,
+};
+
+/// Counts of outcomes of processed transactions
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct Stats {
+    pub transactions: usize,
+    pub processed: usize,
+    pub failed: usize,
+
+    /// Transactions that were already applied before
+    pub skipped: usize,
+}
+
+/// Record of the transaction itself among its records
+pub fn outcome(records: &[AuditRecord]) -> Option<&AuditRecord> {
+    // Records of expired disputes come before record of the transaction
+    records
+        .iter()
+        .find(|record| !matches!(record, AuditRecord::DisputeExpired { .. }))
+}
+
+impl Stats {
+    /// Count outcome of a transaction by its records
+    pub fn count(&mut self, records: &[AuditRecord]) {
+        self.transactions += 1;
+        match outcome(records) {
+            Some(AuditRecord::Processed) => self.processed += 1,
+            Some(AuditRecord::AlreadyApplied | AuditRecord::ConflictsWithApplied) => {
+                self.skipped += 1
+            }
+            _ => self.failed += 1,
+        }
+    }
+}
+
+impl AddAssign for Stats {
+    fn add_assign(&mut self, other: Self) {
+        self.transactions += other.transactions;
+        self.processed += other.processed;
+        self.failed += other.failed;
+        self.skipped += other.skipped;
+    }
+}
+
+impl fmt::Display for Stats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} transactions, {} processed, {} failed, {} skipped",
+            self.transactions, self.processed, self.failed, self.skipped
+        )
+    }
+}
+
+/// Statistics of all transactions processed by processor
+#[derive(Debug, Default, Clone)]
+pub struct Summary {
+    pub stats: Stats,
+
+    /// Number of transactions by their type
+    pub types: BTreeMap<&'static str, usize>,
+
+    /// Number of audit records by their kind, including records of expired disputes,
+    /// fees and flags that follow transactions
+    pub outcomes: BTreeMap<&'static str, usize>,
+
+    pub deposited: MoneyAmount,
+    pub withdrawn: MoneyAmount,
+
+    /// Amounts of opened disputes
+    pub held: MoneyAmount,
+    pub charged_back: MoneyAmount,
+
+    pub accounts_created: usize,
+    pub accounts_locked: usize,
+
+    /// Time spent processing transactions
+    pub busy: Duration,
+}
+
+impl Summary {
+    /// Count committed transaction by its records
+    pub fn count(&mut self, tx: &Transaction, records: &[AuditRecord], took: Duration) {
+        self.stats.count(records);
+        *self.types.entry(tx.detail.name()).or_default() += 1;
+        for record in records {
+            *self.outcomes.entry(record.kind()).or_default() += 1;
+        }
+        if outcome(records) == Some(&AuditRecord::Processed) {
+            match tx.detail {
+                TransactionDetail::Deposit { amount } => add(&mut self.deposited, amount),
+                TransactionDetail::Withdrawal { amount } => add(&mut self.withdrawn, amount),
+                _ => {}
+            }
+        }
+        self.busy += took;
+    }
+
+    /// Processed transactions per second
+    pub fn throughput(&self) -> f64 {
+        match self.busy.is_zero() {
+            true => 0.0,
+            false => self.stats.transactions as f64 / self.busy.as_secs_f64(),
+        }
+    }
+
+    /// Write as JSON object, amounts are strings like in JSON Lines input
+    pub fn write_json(&self, out: &mut impl Write) -> io::Result<()> {
+        fn counts<K: fmt::Display>(counts: &BTreeMap<K, usize>) -> String {
+            let counts: Vec<String> = counts
+                .iter()
+                .map(|(key, count)| format!("\"{key}\":{count}"))
+                .collect();
+            format!("{{{}}}", counts.join(","))
+        }
+        writeln!(
+            out,
+            "{{\"transactions\":{},\"processed\":{},\"failed\":{},\"skipped\":{},\
+             \"types\":{},\"outcomes\":{},\
+             \"deposited\":\"{}\",\"withdrawn\":\"{}\",\"held\":\"{}\",\"charged_back\":\"{}\",\
+             \"accounts_created\":{},\"accounts_locked\":{},\
+             \"seconds\":{:.6},\"transactions_per_second\":{:.1}}}",
+            self.stats.transactions,
+            self.stats.processed,
+            self.stats.failed,
+            self.stats.skipped,
+            counts(&self.types),
+            counts(&self.outcomes),
+            self.deposited,
+            self.withdrawn,
+            self.held,
+            self.charged_back,
+            self.accounts_created,
+            self.accounts_locked,
+            self.busy.as_secs_f64(),
+            self.throughput(),
+        )
+    }
+}
+
+/// Add amount to total, saturated instead of overflowing
+fn add(total: &mut MoneyAmount, amount: MoneyAmount) {
+    *total = total.try_change(amount).unwrap_or(money::MAX);
+}
+
+/// Volumes of disputes and locked accounts are counted from events of committed transactions
+impl Subscriber for Summary {
+    fn notify(&mut self, event: &Event) {
+        match *event {
+            Event::DisputeOpened { amount, .. } => add(&mut self.held, amount),
+            Event::ChargedBack { amount, .. } => add(&mut self.charged_back, amount),
+            Event::AccountLocked { .. } => self.accounts_locked += 1,
+            Event::BalanceChanged { .. } | Event::DisputeResolved { .. } => {}
+        }
+    }
+}
+
+impl fmt::Display for Summary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fn counts<K: fmt::Display>(counts: &BTreeMap<K, usize>) -> String {
+            let counts: Vec<String> = counts
+                .iter()
+                .map(|(key, count)| format!("{key} {count}"))
+                .collect();
+            counts.join(", ")
+        }
+        writeln!(f, "{}", self.stats)?;
+        writeln!(f, "types: {}", counts(&self.types))?;
+        writeln!(f, "outcomes: {}", counts(&self.outcomes))?;
+        writeln!(
+            f,
+            "deposited {}, withdrawn {}, held {}, charged back {}",
+            self.deposited, self.withdrawn, self.held, self.charged_back
+        )?;
+        writeln!(
+            f,
+            "accounts: {} created, {} locked",
+            self.accounts_created, self.accounts_locked
+        )?;
+        write!(
+            f,
+            "{:.3}s processing, {:.0} transactions per second",
+            self.busy.as_secs_f64(),
+            self.throughput()
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        account::ExpiryPolicy,
+        processor::Processor,
+        transactions::{chargeback, deposit, dispute, withdraw},
+    };
+
+    #[test]
+    fn counts_outcomes() {
+        let mut processor: Processor = Processor::default();
+        let mut stats = Stats::default();
+        for tx in [
+            deposit(1, 1, 2),
+            withdraw(1, 2, 5),
+            deposit(1, 1, 2),
+            dispute(1, 1),
+        ] {
+            let records: Vec<AuditRecord> = processor.process([&tx]).collect();
+            stats.count(&records);
+        }
+        let expired = AuditRecord::DisputeExpired {
+            client_id: 1,
+            tx_id: 1,
+            policy: ExpiryPolicy::Resolve,
+            outcome: Box::new(AuditRecord::Processed),
+        };
+        stats.count(&[expired, AuditRecord::AccountLocked]);
+
+        let expected = Stats {
+            transactions: 5,
+            processed: 2,
+            failed: 2,
+            skipped: 1,
+        };
+        assert_eq!(stats, expected);
+        assert_eq!(
+            stats.to_string(),
+            "5 transactions, 2 processed, 2 failed, 1 skipped"
+        );
+    }
+
+    #[test]
+    fn summarizes_processed_transactions() {
+        let mut processor: Processor = Processor::default();
+        let transactions = [
+            deposit(1, 1, 10),
+            deposit(2, 2, 5),
+            withdraw(1, 3, 2.5),
+            withdraw(2, 4, 7),
+            deposit(1, 1, 10),
+            dispute(1, 1),
+            chargeback(1, 1),
+            withdraw(1, 5, 1),
+        ];
+        let _ = processor.process(&transactions).count();
+
+        let summary = &processor.summary;
+        let expected = Stats {
+            transactions: 8,
+            processed: 5,
+            failed: 2,
+            skipped: 1,
+        };
+        assert_eq!(summary.stats, expected);
+        assert_eq!(
+            summary.types,
+            BTreeMap::from([
+                ("chargeback", 1),
+                ("deposit", 3),
+                ("dispute", 1),
+                ("withdrawal", 3)
+            ])
+        );
+        assert_eq!(summary.outcomes["Processed"], 5);
+        assert_eq!(summary.outcomes["NotEnoughMoneyToWithdraw"], 1);
+        assert_eq!(summary.outcomes["AccountLocked"], 1);
+        assert_eq!(summary.outcomes["AlreadyApplied"], 1);
+        assert_eq!(
+            (summary.deposited, summary.withdrawn),
+            (MoneyAmount::from(15), MoneyAmount::from(2.5))
+        );
+        assert_eq!(
+            (summary.held, summary.charged_back),
+            (MoneyAmount::from(10), MoneyAmount::from(10))
+        );
+        assert_eq!((summary.accounts_created, summary.accounts_locked), (2, 1));
+
+        let mut json = Vec::new();
+        summary.write_json(&mut json).unwrap();
+        let json = String::from_utf8(json).unwrap();
+        assert!(json.starts_with(
+            "{\"transactions\":8,\"processed\":5,\"failed\":2,\"skipped\":1,\
+             \"types\":{\"chargeback\":1,\"deposit\":3,\"dispute\":1,\"withdrawal\":3},"
+        ));
+        assert!(json.contains(
+            "\"deposited\":\"15.0000\",\"withdrawn\":\"2.5000\",\
+             \"held\":\"10.0000\",\"charged_back\":\"10.0000\",\
+             \"accounts_created\":2,\"accounts_locked\":1,"
+        ));
+    }
+}
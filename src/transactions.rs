@@ -2,20 +2,134 @@ use crate::{account::ClientId, money::MoneyAmount};
 
 pub type TransactionId = u32;
 
-#[derive(Debug)]
+/// Point in time in seconds since unix epoch.
+pub type Timestamp = u64;
+
+#[derive(Debug, Clone, Copy)]
 pub enum TransactionDetail {
-    Deposit { amount: MoneyAmount },
-    Withdrawal { amount: MoneyAmount },
-    Dispute { tx_id: TransactionId },
-    Resolve { tx_id: TransactionId },
-    ChargeBack { tx_id: TransactionId },
+    Deposit {
+        amount: MoneyAmount,
+    },
+    Withdrawal {
+        amount: MoneyAmount,
+    },
+    /// Disputes, resolves or charges back part of deposit, if amount is given
+    Dispute {
+        tx_id: TransactionId,
+        amount: Option<MoneyAmount>,
+    },
+    Resolve {
+        tx_id: TransactionId,
+        amount: Option<MoneyAmount>,
+    },
+    ChargeBack {
+        tx_id: TransactionId,
+        amount: Option<MoneyAmount>,
+    },
+    /// Returns (part of) deposit to the sender
+    Refund {
+        tx_id: TransactionId,
+        amount: Option<MoneyAmount>,
+    },
+    /// Voids deposit or withdrawal
+    Reversal {
+        tx_id: TransactionId,
+    },
+}
+
+impl TransactionDetail {
+    /// Transaction type as it appears in input
+    pub fn name(&self) -> &'static str {
+        match self {
+            TransactionDetail::Deposit { .. } => "deposit",
+            TransactionDetail::Withdrawal { .. } => "withdrawal",
+            TransactionDetail::Dispute { .. } => "dispute",
+            TransactionDetail::Resolve { .. } => "resolve",
+            TransactionDetail::ChargeBack { .. } => "chargeback",
+            TransactionDetail::Refund { .. } => "refund",
+            TransactionDetail::Reversal { .. } => "reversal",
+        }
+    }
+
+    /// Deposit or withdrawal referenced by dispute, resolve, chargeback, refund or reversal
+    pub fn referenced_tx_id(&self) -> Option<TransactionId> {
+        match self {
+            TransactionDetail::Deposit { .. } | TransactionDetail::Withdrawal { .. } => None,
+            TransactionDetail::Dispute { tx_id, .. }
+            | TransactionDetail::Resolve { tx_id, .. }
+            | TransactionDetail::ChargeBack { tx_id, .. }
+            | TransactionDetail::Refund { tx_id, .. }
+            | TransactionDetail::Reversal { tx_id } => Some(*tx_id),
+        }
+    }
+
+    /// Amount of deposit or withdrawal, or part of deposit under dispute or refunded
+    pub fn amount(&self) -> Option<MoneyAmount> {
+        match self {
+            TransactionDetail::Deposit { amount } | TransactionDetail::Withdrawal { amount } => {
+                Some(*amount)
+            }
+            TransactionDetail::Dispute { amount, .. }
+            | TransactionDetail::Resolve { amount, .. }
+            | TransactionDetail::ChargeBack { amount, .. }
+            | TransactionDetail::Refund { amount, .. } => *amount,
+            TransactionDetail::Reversal { .. } => None,
+        }
+    }
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Transaction {
-    pub id: TransactionId,
+    /// Id of the transaction itself, always known for deposits and withdrawals,
+    /// transactions referencing them carry it only if input has one
+    pub id: Option<TransactionId>,
     pub client_id: ClientId,
     pub detail: TransactionDetail,
+
+    /// When transaction happened, if known
+    pub timestamp: Option<Timestamp>,
+}
+
+impl Transaction {
+    /// Transaction id as it appears in input,
+    /// for dispute, resolve, chargeback, refund and reversal it's id of referenced transaction
+    pub fn input_tx_id(&self) -> TransactionId {
+        self.detail
+            .referenced_tx_id()
+            .or(self.id)
+            .unwrap_or_default()
+    }
+
+    /// Id of dispute, resolve, chargeback, refund or reversal itself, if it has one
+    pub fn referencing_id(&self) -> Option<TransactionId> {
+        self.detail.referenced_tx_id().and(self.id)
+    }
+
+    /// Set id of the transaction itself
+    pub fn with_id(mut self, id: TransactionId) -> Self {
+        self.id = Some(id);
+        self
+    }
+
+    /// Dispute, resolve, charge back or refund only given part of deposit
+    pub fn partial(mut self, part: impl Into<MoneyAmount>) -> Self {
+        match &mut self.detail {
+            TransactionDetail::Dispute { amount, .. }
+            | TransactionDetail::Resolve { amount, .. }
+            | TransactionDetail::ChargeBack { amount, .. }
+            | TransactionDetail::Refund { amount, .. } => *amount = Some(part.into()),
+            TransactionDetail::Deposit { .. }
+            | TransactionDetail::Withdrawal { .. }
+            | TransactionDetail::Reversal { .. } => {}
+        }
+        self
+    }
+
+    /// Set time of the transaction
+    pub fn at(mut self, timestamp: Timestamp) -> Self {
+        self.timestamp = Some(timestamp);
+        self
+    }
 }
 
 pub fn deposit(
@@ -25,10 +139,11 @@ pub fn deposit(
 ) -> Transaction {
     Transaction {
         client_id,
-        id: tx_id,
+        id: Some(tx_id),
         detail: TransactionDetail::Deposit {
             amount: amount.into(),
         },
+        timestamp: None,
     }
 }
 
@@ -39,39 +154,69 @@ pub fn withdraw(
 ) -> Transaction {
     Transaction {
         client_id,
-        id: tx_id,
+        id: Some(tx_id),
         detail: TransactionDetail::Withdrawal {
             amount: amount.into(),
         },
+        timestamp: None,
     }
 }
 
 pub fn dispute(client_id: ClientId, disputed_tx_id: TransactionId) -> Transaction {
     Transaction {
         client_id,
-        id: 0, // For simplicity do not track tx_id of dispute
+        id: None,
         detail: TransactionDetail::Dispute {
             tx_id: disputed_tx_id,
+            amount: None,
         },
+        timestamp: None,
     }
 }
 
 pub fn resolve(client_id: ClientId, disputed_tx_id: TransactionId) -> Transaction {
     Transaction {
         client_id,
-        id: 0, // For simplicity do not track tx_id of resolve
+        id: None,
         detail: TransactionDetail::Resolve {
             tx_id: disputed_tx_id,
+            amount: None,
         },
+        timestamp: None,
     }
 }
 
 pub fn chargeback(client_id: ClientId, disputed_tx_id: TransactionId) -> Transaction {
     Transaction {
         client_id,
-        id: 0, // For simplicity do not track tx_id of chargeback
+        id: None,
         detail: TransactionDetail::ChargeBack {
             tx_id: disputed_tx_id,
+            amount: None,
+        },
+        timestamp: None,
+    }
+}
+
+pub fn refund(client_id: ClientId, deposit_tx_id: TransactionId) -> Transaction {
+    Transaction {
+        client_id,
+        id: None,
+        detail: TransactionDetail::Refund {
+            tx_id: deposit_tx_id,
+            amount: None,
+        },
+        timestamp: None,
+    }
+}
+
+pub fn reversal(client_id: ClientId, reversed_tx_id: TransactionId) -> Transaction {
+    Transaction {
+        client_id,
+        id: None,
+        detail: TransactionDetail::Reversal {
+            tx_id: reversed_tx_id,
         },
+        timestamp: None,
     }
 }
@@ -0,0 +1,313 @@
+use crate::{
+    account::{Account, AuditRecord, ClientId},
+    account_store::AccountStore,
+    applied::{AppliedStore, ContentHash},
+    money::{self, MoneyAmount},
+    reversible::{self, Lookup, Reversible, ReversibleStore, Uncommitted},
+    stats::{self, Stats},
+    transactions::{Transaction, TransactionId},
+};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    io,
+};
+
+/// Accounts changed in memory over a store that is only read, so nothing is committed
+pub struct Overlay<A> {
+    base: A,
+    changed: HashMap<ClientId, Account>,
+}
+
+impl<A: AccountStore> Overlay<A> {
+    pub fn new(base: A) -> Self {
+        Self {
+            base,
+            changed: HashMap::new(),
+        }
+    }
+
+    /// Changes of accounts compared to the underlying store, by client
+    pub fn changes(&mut self) -> io::Result<Vec<Change>> {
+        let mut changes = Vec::new();
+        for (&client_id, after) in &self.changed {
+            let before = self.base.get(client_id)?.cloned().unwrap_or_default();
+            let available = difference(after.available, before.available);
+            let held = difference(after.held, before.held);
+            let change = Change {
+                client_id,
+                available,
+                held,
+                total: difference(available, -held),
+                locked: after.locked && !before.locked,
+            };
+            if change.available != 0 || change.held != 0 || change.locked {
+                changes.push(change);
+            }
+        }
+        changes.sort_by_key(|change| change.client_id);
+        Ok(changes)
+    }
+}
+
+/// Difference of amounts, saturated instead of overflowing
+fn difference(after: MoneyAmount, before: MoneyAmount) -> MoneyAmount {
+    after.try_change(-before).unwrap_or(if after > before {
+        money::MAX
+    } else {
+        -money::MAX
+    })
+}
+
+impl<A: AccountStore> AccountStore for Overlay<A> {
+    fn get(&mut self, client_id: ClientId) -> io::Result<Option<&Account>> {
+        match self.changed.get(&client_id) {
+            Some(account) => Ok(Some(account)),
+            None => self.base.get(client_id),
+        }
+    }
+
+    fn get_mut(&mut self, client_id: ClientId) -> io::Result<&mut Account> {
+        if !self.changed.contains_key(&client_id) {
+            let account = self.base.get(client_id)?.cloned().unwrap_or_default();
+            self.changed.insert(client_id, account);
+        }
+        Ok(self.changed.entry(client_id).or_default())
+    }
+
+    fn for_each(&mut self, mut f: impl FnMut(ClientId, &Account)) -> io::Result<()> {
+        let changed = &self.changed;
+        self.base.for_each(|client_id, account| {
+            if !changed.contains_key(&client_id) {
+                f(client_id, account)
+            }
+        })?;
+        for (client_id, account) in changed {
+            f(*client_id, account);
+        }
+        Ok(())
+    }
+
+    fn persist(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Deposits changed in memory over a store that is only read
+pub struct ReversibleOverlay {
+    base: Box<dyn ReversibleStore>,
+    inserted: HashMap<(ClientId, TransactionId), Reversible>,
+    removed: HashSet<(ClientId, TransactionId)>,
+    uncommitted: Uncommitted,
+}
+
+impl ReversibleOverlay {
+    pub fn new(base: Box<dyn ReversibleStore>) -> Self {
+        Self {
+            base,
+            inserted: HashMap::new(),
+            removed: HashSet::new(),
+            uncommitted: Uncommitted::default(),
+        }
+    }
+}
+
+impl ReversibleStore for ReversibleOverlay {
+    fn insert(
+        &mut self,
+        client_id: ClientId,
+        tx_id: TransactionId,
+        transaction: Reversible,
+    ) -> io::Result<()> {
+        if self
+            .uncommitted
+            .record((client_id, tx_id), Some(transaction))
+        {
+            return Ok(());
+        }
+        self.removed.remove(&(client_id, tx_id));
+        self.inserted.insert((client_id, tx_id), transaction);
+        Ok(())
+    }
+
+    fn get(&mut self, client_id: ClientId, tx_id: TransactionId) -> io::Result<Lookup> {
+        let key = (client_id, tx_id);
+        if let Some(lookup) = self.uncommitted.get(key) {
+            return Ok(lookup);
+        }
+        if self.removed.contains(&key) {
+            return Ok(Lookup::NotFound);
+        }
+        match self.inserted.get(&key) {
+            Some(transaction) => Ok(Lookup::Found(*transaction)),
+            None => self.base.get(client_id, tx_id),
+        }
+    }
+
+    fn remove(&mut self, client_id: ClientId, tx_id: TransactionId) -> io::Result<()> {
+        if self.uncommitted.record((client_id, tx_id), None) {
+            return Ok(());
+        }
+        self.inserted.remove(&(client_id, tx_id));
+        self.removed.insert((client_id, tx_id));
+        Ok(())
+    }
+
+    fn begin(&mut self) {
+        self.uncommitted.begin();
+    }
+
+    fn commit(&mut self) -> io::Result<()> {
+        let changes = self.uncommitted.take();
+        reversible::apply_uncommitted(self, changes)
+    }
+
+    fn rollback(&mut self) {
+        self.uncommitted.rollback();
+    }
+}
+
+/// Processed transactions remembered in memory over a store that is only read
+pub struct AppliedOverlay {
+    base: Box<dyn AppliedStore>,
+    inserted: HashMap<TransactionId, ContentHash>,
+}
+
+impl AppliedOverlay {
+    pub fn new(base: Box<dyn AppliedStore>) -> Self {
+        Self {
+            base,
+            inserted: HashMap::new(),
+        }
+    }
+}
+
+impl AppliedStore for AppliedOverlay {
+    fn get(&mut self, id: TransactionId) -> io::Result<Option<ContentHash>> {
+        match self.inserted.get(&id) {
+            Some(hash) => Ok(Some(*hash)),
+            None => self.base.get(id),
+        }
+    }
+
+    fn insert(&mut self, id: TransactionId, hash: ContentHash) -> io::Result<()> {
+        self.inserted.insert(id, hash);
+        Ok(())
+    }
+}
+
+/// Net change of an account
+#[derive(Debug, PartialEq)]
+pub struct Change {
+    pub client_id: ClientId,
+    pub available: MoneyAmount,
+    pub held: MoneyAmount,
+    pub total: MoneyAmount,
+
+    /// Whether account gets locked
+    pub locked: bool,
+}
+
+/// Outcomes of transactions processed without committing them
+#[derive(Debug, Default)]
+pub struct Validation {
+    pub stats: Stats,
+
+    /// Number of transactions by kind of their outcome
+    pub outcomes: BTreeMap<&'static str, usize>,
+
+    /// Transactions that failed, with their outcome
+    pub failed: Vec<(Transaction, AuditRecord)>,
+}
+
+impl Validation {
+    /// Count outcome of a transaction by its records
+    pub fn count(&mut self, tx: &Transaction, records: &[AuditRecord]) {
+        let failed = self.stats.failed;
+        self.stats.count(records);
+        let Some(outcome) = stats::outcome(records) else {
+            return;
+        };
+        *self.outcomes.entry(outcome.kind()).or_default() += 1;
+        if self.stats.failed > failed {
+            self.failed.push((tx.clone(), outcome.clone()));
+        }
+    }
+
+    /// Part of transactions that failed, in percent
+    pub fn failed_percent(&self) -> f64 {
+        match self.stats.transactions {
+            0 => 0.0,
+            transactions => 100.0 * self.stats.failed as f64 / transactions as f64,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        processor::Processor,
+        transactions::{chargeback, deposit, dispute, withdraw},
+    };
+
+    #[test]
+    fn does_not_change_underlying_stores() {
+        let mut base: Processor = Processor::default();
+        let _ = base.process(&[deposit(1, 1, 10), deposit(2, 2, 5)]).count();
+
+        let mut processor = Processor::new(Overlay::new(base.accounts)).unwrap();
+        processor.deposits = Box::new(ReversibleOverlay::new(base.deposits));
+        processor.applied = Box::new(AppliedOverlay::new(base.applied));
+        let mut validation = Validation::default();
+        for tx in [
+            deposit(1, 1, 10),
+            withdraw(2, 3, 7),
+            dispute(1, 1),
+            chargeback(1, 1),
+            deposit(3, 4, 1.5),
+        ] {
+            let records: Vec<AuditRecord> = processor.process([&tx]).collect();
+            validation.count(&tx, &records);
+        }
+
+        assert_eq!(
+            validation.stats,
+            Stats {
+                transactions: 5,
+                processed: 3,
+                failed: 1,
+                skipped: 1,
+            }
+        );
+        assert_eq!(validation.outcomes["Processed"], 3);
+        assert_eq!(validation.outcomes["NotEnoughMoneyToWithdraw"], 1);
+        assert_eq!(validation.failed.len(), 1);
+        assert_eq!(validation.failed_percent(), 20.0);
+
+        let changes = processor.accounts.changes().unwrap();
+        assert_eq!(
+            changes,
+            [
+                Change {
+                    client_id: 1,
+                    available: MoneyAmount::from(-10),
+                    held: MoneyAmount::from(0),
+                    total: MoneyAmount::from(-10),
+                    locked: true,
+                },
+                Change {
+                    client_id: 3,
+                    available: MoneyAmount::from(1.5),
+                    held: MoneyAmount::from(0),
+                    total: MoneyAmount::from(1.5),
+                    locked: false,
+                },
+            ]
+        );
+
+        let Overlay { base, .. } = processor.accounts;
+        assert_eq!(base[&1].available, MoneyAmount::from(10));
+        assert!(!base[&1].locked);
+        assert!(!base.contains_key(&3));
+    }
+}